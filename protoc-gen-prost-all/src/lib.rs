@@ -0,0 +1,360 @@
+#![doc = include_str!("../README.md")]
+
+use std::str;
+
+use protoc_gen_prost::{
+    GenerationCache, Generator, InvalidParameter, Param, ParamSpec, ParameterError, PluginOpts,
+    PluginRequest, PluginSpec, Result,
+};
+
+/// Execute the _Prost!_, _Tonic_, serde, and crate generators from an encoded
+/// [`CodeGeneratorRequest`]
+///
+/// The output is the same as running `protoc-gen-prost`, `protoc-gen-tonic`,
+/// `protoc-gen-prost-serde`, and `protoc-gen-prost-crate` one after the other,
+/// but the request is only decoded once.
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+pub fn execute(raw_request: &[u8]) -> Result {
+    let request = PluginRequest::decode(raw_request)?;
+    let parameter = request.parameter().to_owned();
+    let load = |plugin| PluginOpts::load_namespaced(&parameter, plugin, PLUGINS);
+
+    let opts = load(&PLUGIN)?;
+    let params = Parameters::from_opts(&opts)?;
+    let prost_opts = load(&protoc_gen_prost::PLUGIN)?;
+    let tonic_opts = (!params.no_tonic)
+        .then(|| load(&protoc_gen_tonic::PLUGIN))
+        .transpose()?;
+    let serde_opts = (!params.no_serde)
+        .then(|| load(&protoc_gen_prost_serde::PLUGIN))
+        .transpose()?;
+    let crate_opts = (!params.no_crate)
+        .then(|| load(&protoc_gen_prost_crate::PLUGIN))
+        .transpose()?;
+    let dumped_opts = [
+        Some(&opts),
+        Some(&prost_opts),
+        tonic_opts.as_ref(),
+        serde_opts.as_ref(),
+        crate_opts.as_ref(),
+    ];
+    protoc_gen_prost::dump_request(
+        raw_request,
+        env!("CARGO_PKG_VERSION"),
+        &dumped_opts.into_iter().flatten().collect::<Vec<_>>(),
+    )?;
+
+    // Every generator shares the modules laid out by `protoc-gen-prost`
+    let mut module_request_set = protoc_gen_prost::module_request_set(request, &prost_opts)?;
+
+    let tonic_generator = tonic_opts
+        .as_ref()
+        .map(protoc_gen_tonic::generator)
+        .transpose()?;
+    let serde_generator = serde_opts
+        .as_ref()
+        .map(|opts| protoc_gen_prost_serde::generator(opts, &module_request_set))
+        .transpose()?;
+    let mut crate_generator = crate_opts
+        .as_ref()
+        .map(protoc_gen_prost_crate::generator)
+        .transpose()?;
+    let mut generator = protoc_gen_prost::generator(&prost_opts, &module_request_set)?
+        .chain(tonic_generator)
+        .chain(serde_generator);
+
+    let cache = GenerationCache::from_opts(&opts, env!("CARGO_PKG_VERSION")).map(|cache| {
+        [&tonic_opts, &serde_opts]
+            .into_iter()
+            .flatten()
+            .fold(cache.with_opts(&prost_opts), GenerationCache::with_opts)
+    });
+    let mut files = match cache {
+        Some(cache) => cache.generate(&mut module_request_set, &mut generator)?,
+        None => generator.generate(&module_request_set)?,
+    };
+
+    // The crate files tie every module together, so they are never cached
+    files.extend(crate_generator.generate(&module_request_set)?);
+
+    Ok(files)
+}
+
+/// The plugins whose generators are run, and whose sections prefix their
+/// parameters
+const PLUGINS: &[&PluginSpec] = &[
+    &PLUGIN,
+    &protoc_gen_prost::PLUGIN,
+    &protoc_gen_tonic::PLUGIN,
+    &protoc_gen_prost_serde::PLUGIN,
+    &protoc_gen_prost_crate::PLUGIN,
+];
+
+/// Parameters used to choose the generators run by `protoc-gen-prost-all`
+#[derive(Debug, Default)]
+struct Parameters {
+    no_tonic: bool,
+    no_serde: bool,
+    no_crate: bool,
+}
+
+/// The parameters accepted by `protoc-gen-prost-all`
+const PLUGIN: PluginSpec = PluginSpec {
+    name: "protoc-gen-prost-all",
+    section: "all",
+    params: &[
+        ParamSpec::new("no_tonic", "no_tonic(=<boolean>)"),
+        ParamSpec::new("no_serde", "no_serde(=<boolean>)"),
+        ParamSpec::new("no_crate", "no_crate(=<boolean>)"),
+    ],
+};
+
+impl Parameters {
+    fn from_opts(opts: &PluginOpts<'_>) -> std::result::Result<Self, InvalidParameter> {
+        let mut ret_val = Self::default();
+        opts.apply(&mut ret_val, Self::handle_parameter)?;
+        Ok(ret_val)
+    }
+
+    fn handle_parameter(&mut self, param: Param<'_>) -> std::result::Result<(), ParameterError> {
+        let flag = match param.name() {
+            "no_tonic" => &mut self.no_tonic,
+            "no_serde" => &mut self.no_serde,
+            "no_crate" => &mut self.no_crate,
+            _ => return Err(PLUGIN.reject(param)),
+        };
+
+        match param {
+            Param::Parameter { .. } | Param::Value { value: "true", .. } => *flag = true,
+            Param::Value { value: "false", .. } => *flag = false,
+            _ => return Err(PLUGIN.reject(param)),
+        }
+
+        Ok(())
+    }
+}
+
+impl str::FromStr for Parameters {
+    type Err = InvalidParameter;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::from_opts(&PluginOpts::load_namespaced(s, &PLUGIN, PLUGINS)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_types::{
+        compiler::{code_generator_response::File, CodeGeneratorRequest},
+        DescriptorProto, FileDescriptorProto, MethodDescriptorProto, ServiceDescriptorProto,
+    };
+
+    use super::*;
+
+    fn request(parameter: &str) -> Vec<u8> {
+        let file = FileDescriptorProto {
+            name: Some("greeter/v1/greeter.proto".to_owned()),
+            package: Some("greeter.v1".to_owned()),
+            message_type: vec![DescriptorProto {
+                name: Some("Greeting".to_owned()),
+                ..Default::default()
+            }],
+            service: vec![ServiceDescriptorProto {
+                name: Some("Greeter".to_owned()),
+                method: vec![MethodDescriptorProto {
+                    name: Some("Greet".to_owned()),
+                    input_type: Some(".greeter.v1.Greeting".to_owned()),
+                    output_type: Some(".greeter.v1.Greeting".to_owned()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            syntax: Some("proto3".to_owned()),
+            ..Default::default()
+        };
+        CodeGeneratorRequest {
+            file_to_generate: vec!["greeter/v1/greeter.proto".to_owned()],
+            parameter: Some(parameter.to_owned()),
+            proto_file: vec![file],
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn output_matches_running_each_plugin() {
+        let separately: Vec<File> = [
+            protoc_gen_prost::execute(&request("compile_well_known_types")),
+            protoc_gen_tonic::execute(&request("compile_well_known_types,no_server")),
+            protoc_gen_prost_serde::execute(&request("feature=json")),
+            protoc_gen_prost_crate::execute(&request("")),
+        ]
+        .into_iter()
+        .flat_map(|files| files.expect("code generation failed"))
+        .collect();
+
+        let combined = execute(&request(
+            "compile_well_known_types,tonic.no_server,serde.feature=json",
+        ))
+        .expect("code generation failed");
+
+        assert_eq!(combined, separately);
+    }
+
+    #[test]
+    fn generators_can_be_skipped() {
+        let prost = protoc_gen_prost::execute(&request("")).unwrap();
+        let combined = execute(&request("no_tonic,no_serde,no_crate")).unwrap();
+
+        assert_eq!(combined, prost);
+    }
+
+    #[test]
+    fn dumped_requests_replay_to_the_same_output() {
+        let path = std::env::temp_dir().join(format!(
+            "protoc-gen-prost-all-dump-{}.bin",
+            std::process::id()
+        ));
+        let parameter = format!(
+            "dump_request={},extern_path=.other=::other,tonic.no_server,no_crate",
+            path.display()
+        );
+        let files = execute(&request(&parameter)).unwrap();
+
+        let replayed = std::fs::read(&path).unwrap();
+        assert_eq!(
+            CodeGeneratorRequest::decode(replayed.as_slice())
+                .unwrap()
+                .parameter(),
+            "all.no_crate,prost.extern_path=.other=::other,tonic.extern_path=.other=::other,tonic.\
+             no_server,serde.extern_path=.other=::other"
+        );
+        assert_eq!(execute(&replayed).unwrap(), files);
+
+        std::fs::remove_file(&path).unwrap();
+        let mut description = path.into_os_string();
+        description.push(".txt");
+        std::fs::remove_file(description).unwrap();
+    }
+
+    #[test]
+    fn cached_output_matches_generated_output() {
+        use protoc_gen_prost::testing::request_from_descriptor_set;
+
+        let dir =
+            std::env::temp_dir().join(format!("protoc-gen-prost-all-cache-{}", std::process::id()));
+        let fixture = |name: &str| {
+            let path = format!("{}/../fixtures/{name}.binpb", env!("CARGO_MANIFEST_DIR"));
+            std::fs::read(path).unwrap()
+        };
+        let cases: &[(&str, &[&str])] = &[
+            (
+                "greeter",
+                &["greeter/v1/greeter.proto", "helloworld/v1/helloworld.proto"],
+            ),
+            (
+                "nested",
+                &[
+                    "nested/outer.proto",
+                    "nested/inner/inner.proto",
+                    "nested/inner/deep/deep.proto",
+                ],
+            ),
+        ];
+
+        for parameter in [
+            "serde.feature=json",
+            "serde.no_include,doc_links",
+            "output_layout=per_file,file_descriptor_set=separate,descriptor_pool=crate::POOL",
+            "flat_output_dir,file_descriptor_set=imports,tonic.no_server",
+        ] {
+            for &(fixture_name, files) in cases {
+                let descriptor_set = fixture(fixture_name);
+                let generate = |files: &[&str], parameter: &str| {
+                    let request = request_from_descriptor_set(&descriptor_set, files, parameter);
+                    execute(&request).expect("code generation failed")
+                };
+                let cached = format!("{parameter},cache_dir={}", dir.display());
+
+                // Only the last file is cached to begin with, so that cached
+                // and generated modules are combined
+                generate(&files[files.len() - 1..], &cached);
+
+                let generated = generate(files, parameter);
+                for run in ["partially cached", "cached"] {
+                    assert_eq!(
+                        generate(files, &cached),
+                        generated,
+                        "{run} output differs for `{fixture_name}` with `{parameter}`"
+                    );
+                }
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strict_mode_applies_to_every_generator() {
+        use protoc_gen_prost::testing::{file, request, FieldType};
+
+        let request = request()
+            .parameter("strict,extern_path=.greeter=::remote::greeter,no_crate")
+            .file(
+                file("greeter/local.proto")
+                    .package("greeter")
+                    .import("greeter/remote.proto")
+                    .message("Local", |m| {
+                        m.field("remote", FieldType::Message(".greeter.Remote"), 1)
+                    })
+                    .service("Greeter", |s| {
+                        s.method("Greet", ".greeter.Local", ".greeter.Remote", |m| m)
+                    }),
+            )
+            .import(
+                file("greeter/remote.proto")
+                    .package("greeter")
+                    .message("Remote", |m| m)
+                    .enumeration("Kind", |e| e.value("KIND_UNSPECIFIED", 0)),
+            )
+            .encode();
+
+        let files = execute(&request).expect("code generation failed");
+        let content = |name: &str| {
+            files
+                .iter()
+                .find(|f| f.name() == name && f.insertion_point.is_none())
+                .unwrap_or_else(|| panic!("`{name}` not generated"))
+                .content()
+        };
+
+        let prost = content("greeter/greeter.rs");
+        assert!(prost.contains("pub struct Local"), "{prost}");
+        assert!(!prost.contains("pub struct Remote"), "{prost}");
+
+        let tonic = content("greeter/greeter.tonic.rs");
+        assert!(tonic.contains("::remote::greeter::Remote"), "{tonic}");
+
+        let serde = content("greeter/greeter.serde.rs");
+        assert!(serde.contains("impl serde::Serialize for Local"), "{serde}");
+        assert!(!serde.contains("for Remote"), "{serde}");
+        assert!(!serde.contains("for Kind"), "{serde}");
+    }
+
+    #[test]
+    fn unknown_parameters_are_rejected() {
+        let error = execute(&request("no_sever")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "protoc-gen-prost-all: invalid parameter `no_sever`: unknown parameter, did you mean \
+             `no_server`?"
+        );
+
+        let error = execute(&request("grpc.no_server")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "protoc-gen-prost-all: invalid parameter `grpc.no_server`: unknown parameter"
+        );
+    }
+}
@@ -0,0 +1,5 @@
+use std::io;
+
+fn main() -> io::Result<()> {
+    protoc_gen_prost::plugin_main(env!("CARGO_PKG_VERSION"), protoc_gen_prost_all::execute)
+}
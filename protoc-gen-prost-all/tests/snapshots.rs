@@ -0,0 +1,26 @@
+//! Snapshots of the code generated for the descriptor sets in `fixtures`
+//!
+//! Run with `PROTOC_GEN_PROST_BLESS=1` to update the snapshots.
+
+use std::{fs, path::Path};
+
+use protoc_gen_prost::testing::{assert_snapshot, request_from_descriptor_set};
+
+fn check(name: &str, fixture: &str, files: &[&str], parameter: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let descriptor_set = fs::read(root.join(format!("../fixtures/{fixture}.binpb"))).unwrap();
+    let request = request_from_descriptor_set(&descriptor_set, files, parameter);
+
+    let files = protoc_gen_prost_all::execute(&request).expect("code generation failed");
+    assert_snapshot(root.join(format!("tests/snapshots/{name}.txt")), &files);
+}
+
+#[test]
+fn greeter() {
+    check(
+        "greeter",
+        "greeter",
+        &["greeter/v1/greeter.proto", "helloworld/v1/helloworld.proto"],
+        "tonic.no_server,serde.feature=json",
+    );
+}
@@ -1,9 +1,9 @@
 use std::rc::Rc;
 
 use once_cell::sync::Lazy;
-use prost_build::Module;
+use prost_build::{Comments, Module};
 use prost_types::compiler::code_generator_response::File;
-use protoc_gen_prost::{Generator, ModuleRequestSet, Result};
+use protoc_gen_prost::{has_deprecated_top_level_types, Generator, ModuleRequestSet, Result};
 
 use crate::PackageLimiter;
 
@@ -29,7 +29,13 @@ impl<'a> Generator for IncludeFileGenerator<'a> {
                 self.limiter
                     .is_allowed(request.proto_package_name())
                     .then(|| {
-                        context.move_to(module, request.proto_package_name());
+                        let allow_deprecated = request.files().any(has_deprecated_top_level_types);
+                        context.move_to(
+                            module,
+                            request.proto_package_name(),
+                            &request.package_docs(),
+                            allow_deprecated,
+                        );
                         context.push_include(&filepath);
                         context.push_insertion_point(request.proto_package_name());
                     })
@@ -92,7 +98,13 @@ impl<'a> CodeGenContext<'a> {
         self.buf.push_str(&self.indent);
     }
 
-    fn move_to(&mut self, next: &'a Module, package: &str) {
+    fn move_to(
+        &mut self,
+        next: &'a Module,
+        package: &str,
+        docs: &Comments,
+        allow_deprecated: bool,
+    ) {
         let (down, prefix) = difference(self.last, next);
 
         for _ in 0..down {
@@ -105,7 +117,12 @@ impl<'a> CodeGenContext<'a> {
             self.open_module(module_name);
         }
 
+        docs.append_with_indent((self.indent.len() / INDENT.len()) as u8, &mut self.buf);
         self.push_attribute_insertion_point(package);
+        if allow_deprecated {
+            self.push_indent();
+            self.buf.push_str("#[allow(deprecated)]\n");
+        }
         self.open_module(next.parts().last().unwrap());
 
         self.last = next;
@@ -1,10 +1,11 @@
 #![doc = include_str!("../README.md")]
 
-use std::{rc::Rc, str};
+use std::{mem, rc::Rc, str};
 
-use prost::Message;
-use prost_types::compiler::CodeGeneratorRequest;
-use protoc_gen_prost::{Generator, InvalidParameter, ModuleRequestSet, Param, Params, Result};
+use protoc_gen_prost::{
+    Generator, InvalidParameter, ModuleRequestSet, Param, ParamSpec, ParameterError, PluginOpts,
+    PluginRequest, PluginSpec, Result,
+};
 
 use self::generator::{CargoCrateGenerator, IncludeFileGenerator};
 use crate::generator::FeaturesGenerator;
@@ -12,41 +13,68 @@ use crate::generator::FeaturesGenerator;
 mod generator;
 
 /// Execute the crate file generator from an encoded [`CodeGeneratorRequest`]
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
 pub fn execute(raw_request: &[u8]) -> Result {
-    let request = CodeGeneratorRequest::decode(raw_request)?;
-    let params = request.parameter().parse::<Parameters>()?;
+    let request = PluginRequest::decode(raw_request)?;
+    let parameter = request.parameter().to_owned();
+    let opts = PluginOpts::load(&parameter, &PLUGIN)?;
+    protoc_gen_prost::dump_request(raw_request, env!("CARGO_PKG_VERSION"), &[&opts])?;
+    let params = Parameters::from_opts(&opts)?;
 
-    let module_request_set = ModuleRequestSet::new(
-        request.file_to_generate,
-        request.proto_file,
-        raw_request,
+    let module_request_set = ModuleRequestSet::from_request(
+        request,
         params.default_package_filename.as_deref(),
         params.flat_output_dir,
     )?;
 
-    let include_filename = if params.gen_crate.is_some() {
-        params.include_file.as_deref().unwrap_or("src/lib.rs")
-    } else {
-        params.include_file.as_deref().unwrap_or("mod.rs")
-    };
-    let package_separator = params.package_separator.as_deref().unwrap_or("-");
-
-    let limiter = Rc::new(params.only_include);
-
-    let include_file_generator = IncludeFileGenerator::new(include_filename, limiter.clone());
-    let cargo_crate_generator = params
-        .gen_crate
-        .as_ref()
-        .map(|o| CargoCrateGenerator::new(o.as_deref()));
-    let features_generator = (!params.no_features)
-        .then(|| FeaturesGenerator::new(include_filename, package_separator, limiter.clone()));
-
-    let files = include_file_generator
-        .chain(cargo_crate_generator)
-        .chain(features_generator)
-        .generate(&module_request_set)?;
-
-    Ok(files)
+    CrateGenerator::new(params).generate(&module_request_set)
+}
+
+/// Creates the generators of `protoc-gen-prost-crate` as configured by
+/// `opts`, for running as part of a combined plugin
+pub fn generator(opts: &PluginOpts<'_>) -> std::result::Result<impl Generator, InvalidParameter> {
+    Ok(CrateGenerator::new(Parameters::from_opts(opts)?))
+}
+
+/// The generators configured by the parameters of `protoc-gen-prost-crate`
+struct CrateGenerator {
+    params: Parameters,
+    limiter: Rc<PackageLimiter>,
+}
+
+impl CrateGenerator {
+    fn new(mut params: Parameters) -> Self {
+        let limiter = Rc::new(mem::take(&mut params.only_include));
+        Self { params, limiter }
+    }
+}
+
+impl Generator for CrateGenerator {
+    fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
+        let params = &self.params;
+        let include_filename = if params.gen_crate.is_some() {
+            params.include_file.as_deref().unwrap_or("src/lib.rs")
+        } else {
+            params.include_file.as_deref().unwrap_or("mod.rs")
+        };
+        let package_separator = params.package_separator.as_deref().unwrap_or("-");
+
+        let include_file_generator =
+            IncludeFileGenerator::new(include_filename, self.limiter.clone());
+        let cargo_crate_generator = params
+            .gen_crate
+            .as_ref()
+            .map(|o| CargoCrateGenerator::new(o.as_deref()));
+        let features_generator = (!params.no_features).then(|| {
+            FeaturesGenerator::new(include_filename, package_separator, self.limiter.clone())
+        });
+
+        include_file_generator
+            .chain(cargo_crate_generator)
+            .chain(features_generator)
+            .generate(module_request_set)
+    }
 }
 
 /// Parameters use to configure [`Generator`]s built into `protoc-gen-prost`
@@ -82,71 +110,113 @@ struct Parameters {
     flat_output_dir: bool,
 }
 
-impl str::FromStr for Parameters {
-    type Err = InvalidParameter;
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let mut ret_val = Self::default();
-        for param in Params::from_protoc_plugin_opts(s)? {
-            match param {
-                Param::Parameter {
-                    param: "default_package_filename",
-                }
-                | Param::Value {
-                    param: "default_package_filename",
-                    ..
-                } => ret_val.default_package_filename = param.value().map(|v| v.into_owned()),
-                Param::Value {
-                    param: "include_file",
-                    value: filename,
-                } => ret_val.include_file = Some(filename.to_owned()),
-                Param::Value {
-                    param: "only_include",
-                    value: package,
-                } => {
-                    if ret_val.only_include.push(package.to_owned()).is_err() {
-                        return Err(InvalidParameter::new(format!(
-                            "proto paths must begin with `.`: only_include={package}",
-                        )));
-                    }
-                }
-                Param::Parameter { param: "gen_crate" }
-                | Param::Value {
-                    param: "gen_crate", ..
-                } => ret_val.gen_crate = Some(param.value().map(|t| t.into_owned())),
-                Param::Parameter {
-                    param: "no_features",
-                }
-                | Param::Value {
-                    param: "no_features",
-                    value: "true",
-                } => ret_val.no_features = true,
-                Param::Value {
-                    param: "no_features",
-                    value: "false",
-                } => (),
-                Param::Value {
-                    param: "package_separator",
-                    value: value @ ("." | "-" | "+" | "_"),
-                } => ret_val.package_separator = Some(value.to_string()),
-                Param::Parameter {
-                    param: "flat_output_dir",
+/// The parameters accepted by `protoc-gen-prost-crate`
+pub const PLUGIN: PluginSpec = PluginSpec {
+    name: "protoc-gen-prost-crate",
+    section: "crate",
+    params: &[
+        ParamSpec::new(
+            "default_package_filename",
+            "default_package_filename=<value>",
+        ),
+        ParamSpec::new("include_file", "include_file=<value>"),
+        ParamSpec::new("only_include", "only_include=<proto_path>"),
+        ParamSpec::new("gen_crate", "gen_crate(=<template_path>)"),
+        ParamSpec::new("no_features", "no_features(=<boolean>)"),
+        ParamSpec::new("package_separator", "package_separator=[-_.+]"),
+        ParamSpec::new("flat_output_dir", "flat_output_dir(=<boolean>)"),
+    ],
+};
+
+impl Parameters {
+    fn handle_parameter(&mut self, param: Param<'_>) -> std::result::Result<(), ParameterError> {
+        match param {
+            Param::Parameter {
+                param: "default_package_filename",
+            }
+            | Param::Value {
+                param: "default_package_filename",
+                ..
+            } => self.default_package_filename = param.value().map(|v| v.into_owned()),
+            Param::Value {
+                param: "include_file",
+                value: filename,
+            } => self.include_file = Some(filename.to_owned()),
+            Param::Value {
+                param: "only_include",
+                value: package,
+            } => {
+                if self.only_include.push(package.to_owned()).is_err() {
+                    return Err(ParameterError::invalid_value(
+                        param,
+                        format!("proto paths must begin with `.`, did you mean `.{package}`?"),
+                    ));
                 }
-                | Param::Value {
-                    param: "flat_output_dir",
-                    value: "true",
-                } => ret_val.flat_output_dir = true,
-                Param::Value {
-                    param: "flat_output_dir",
-                    value: "false",
-                } => (),
-                _ => return Err(InvalidParameter::from(param)),
             }
+            Param::Parameter { param: "gen_crate" }
+            | Param::Value {
+                param: "gen_crate", ..
+            } => self.gen_crate = Some(param.value().map(|t| t.into_owned())),
+            Param::Parameter {
+                param: "no_features",
+            }
+            | Param::Value {
+                param: "no_features",
+                value: "true",
+            } => self.no_features = true,
+            Param::Value {
+                param: "no_features",
+                value: "false",
+            } => (),
+            Param::Value {
+                param: "package_separator",
+                value: value @ ("." | "-" | "+" | "_"),
+            } => self.package_separator = Some(value.to_string()),
+            Param::Value {
+                param: "package_separator",
+                value,
+            } => {
+                return Err(ParameterError::invalid_value(
+                    param,
+                    format!(
+                        "`{value}` cannot be used to separate package names in feature flags, \
+                         expected one of `-`, `+`, `_`, or `.`"
+                    ),
+                ))
+            }
+            Param::Parameter {
+                param: "flat_output_dir",
+            }
+            | Param::Value {
+                param: "flat_output_dir",
+                value: "true",
+            } => self.flat_output_dir = true,
+            Param::Value {
+                param: "flat_output_dir",
+                value: "false",
+            } => (),
+            _ => return Err(PLUGIN.reject(param)),
         }
 
+        Ok(())
+    }
+}
+
+impl Parameters {
+    fn from_opts(opts: &PluginOpts<'_>) -> std::result::Result<Self, InvalidParameter> {
+        let mut ret_val = Self::default();
+        opts.apply(&mut ret_val, Self::handle_parameter)?;
         Ok(ret_val)
     }
 }
 
+impl str::FromStr for Parameters {
+    type Err = InvalidParameter;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::from_opts(&PluginOpts::load(s, &PLUGIN)?)
+    }
+}
+
 #[derive(Debug, Default)]
 struct PackageLimiter {
     include_prefixes: Vec<String>,
@@ -177,3 +247,103 @@ impl PackageLimiter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_parameters_are_reported_together() {
+        let error = "no_feature,package_separator=*,only_include=helloworld"
+            .parse::<Parameters>()
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "protoc-gen-prost-crate: 3 invalid parameters\n  * `no_feature`: unknown parameter, \
+             did you mean `no_features`?\n  * `package_separator=*`: `*` cannot be used to \
+             separate package names in feature flags, expected one of `-`, `+`, `_`, or `.`\n  * \
+             `only_include=helloworld`: proto paths must begin with `.`, did you mean \
+             `.helloworld`?"
+        );
+    }
+
+    #[test]
+    fn package_comments_document_the_module() {
+        use protoc_gen_prost::testing::{file, request};
+
+        let request = request()
+            .file(file("x/a.proto").package("x").package_comment("Things."))
+            .file(file("x/b.proto").package("x").package_comment("Things."))
+            .file(
+                file("x/c.proto")
+                    .package("x")
+                    .package_comment("More [things], see https://example.com"),
+            )
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let include_file = files.iter().find(|f| f.name() == "mod.rs").unwrap();
+        assert!(
+            include_file.content().contains(
+                "/// Things.\n///\n/// More \\[things\\], see <https://example.com>\n///\n/// \
+                 Generated from:\n///\n/// * `x/a.proto`\n/// * `x/b.proto`\n/// * \
+                 `x/c.proto`\n// @@protoc_insertion_point(attribute:x)\npub mod x {\n"
+            ),
+            "{}",
+            include_file.content()
+        );
+    }
+
+    #[test]
+    fn packages_with_deprecated_types_allow_deprecated() {
+        use prost_types::MessageOptions;
+        use protoc_gen_prost::testing::{file, request};
+
+        let request = request()
+            .file(file("old/a.proto").package("old").message("Old", |m| {
+                m.options(MessageOptions {
+                    deprecated: Some(true),
+                    ..Default::default()
+                })
+            }))
+            .file(file("new/a.proto").package("new").message("New", |m| m))
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let include_file = files.iter().find(|f| f.name() == "mod.rs").unwrap();
+        let content = include_file.content();
+        assert!(
+            content.contains("#[allow(deprecated)]\npub mod old {\n"),
+            "{content}"
+        );
+        assert_eq!(
+            content.matches("#[allow(deprecated)]").count(),
+            1,
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn packages_of_editions_files_are_included() {
+        use protoc_gen_prost::testing::{file, request};
+
+        let request = request()
+            .file(
+                file("x/a.proto")
+                    .edition(protoc_gen_prost::MAXIMUM_EDITION)
+                    .package("x"),
+            )
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let include_file = files.iter().find(|f| f.name() == "mod.rs").unwrap();
+        assert!(
+            include_file
+                .content()
+                .contains("pub mod x {\n    include!(\"x/x.rs\");\n"),
+            "{}",
+            include_file.content()
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Snapshots of the code generated for the descriptor sets in `fixtures`
+//!
+//! Run with `PROTOC_GEN_PROST_BLESS=1` to update the snapshots.
+
+use std::{fs, path::Path};
+
+use protoc_gen_prost::testing::{assert_snapshot, request_from_descriptor_set};
+
+fn check(name: &str, fixture: &str, files: &[&str], parameter: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let descriptor_set = fs::read(root.join(format!("../fixtures/{fixture}.binpb"))).unwrap();
+    let request = request_from_descriptor_set(&descriptor_set, files, parameter);
+
+    let files = protoc_gen_prost_crate::execute(&request).expect("code generation failed");
+    assert_snapshot(root.join(format!("tests/snapshots/{name}.txt")), &files);
+}
+
+#[test]
+fn nested_packages() {
+    check(
+        "nested",
+        "nested",
+        &[
+            "nested/outer.proto",
+            "nested/inner/inner.proto",
+            "nested/inner/deep/deep.proto",
+        ],
+        "",
+    );
+}
+
+#[test]
+fn nested_packages_without_features() {
+    check(
+        "nested_no_features",
+        "nested",
+        &[
+            "nested/outer.proto",
+            "nested/inner/inner.proto",
+            "nested/inner/deep/deep.proto",
+        ],
+        "no_features,include_file=lib.rs",
+    );
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use prost_build::Module;
 use prost_types::compiler::code_generator_response::File;
 use protoc_gen_prost::{Generator, ModuleRequestSet, Result};
@@ -6,21 +8,28 @@ const GENERATED_HEADER: &str = "// @generated\n";
 
 pub struct PbJsonGenerator {
     builder: pbjson_build::Builder,
-    prefixes: Vec<String>,
     insert_include: bool,
     feature_name: Option<String>,
+    strict: bool,
 }
 
 impl Generator for PbJsonGenerator {
     fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
-        let results = self.builder.generate(&self.prefixes, |_| Ok(Vec::new()))?;
-
-        results
+        let mut results: HashMap<Module, Vec<u8>> = self
+            .builder
+            .generate(&self.prefixes(module_request_set), |_| Ok(Vec::new()))?
             .into_iter()
-            .filter_map(|(package, bytes)| {
-                let request = module_request_set.for_module(
-                    &Module::from_protobuf_package_name(&package.to_string().replace("r#", "")),
-                )?;
+            .map(|(package, bytes)| {
+                let package = package.to_string().replace("r#", "");
+                (Module::from_protobuf_package_name(&package), bytes)
+            })
+            .collect();
+
+        // Files are written in the order of the modules, as by other generators
+        module_request_set
+            .requests()
+            .filter_map(|(module, request)| {
+                let bytes = results.remove(module)?;
 
                 let output_filename = format!("{}.serde.rs", request.proto_package_name());
 
@@ -68,9 +77,42 @@ impl PbJsonGenerator {
     ) -> Self {
         Self {
             builder,
-            prefixes: vec![".".to_owned()],
             insert_include,
             feature_name,
+            strict: false,
+        }
+    }
+
+    /// Sets whether only the types of the requested files are generated
+    ///
+    /// The types of the other files must have extern paths, as set up by
+    /// [`strict_extern_paths`](protoc_gen_prost::strict_extern_paths).
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The proto paths of the types whose implementations are generated
+    fn prefixes(&self, module_request_set: &ModuleRequestSet) -> Vec<String> {
+        if !self.strict {
+            return vec![".".to_owned()];
+        }
+
+        let mut prefixes = Vec::new();
+        for (_, request) in module_request_set.requests() {
+            for file in request.requested_files() {
+                let scope = match file.package() {
+                    "" => String::new(),
+                    package => format!(".{package}"),
+                };
+                let types = file
+                    .message_type
+                    .iter()
+                    .map(|message| message.name())
+                    .chain(file.enum_type.iter().map(|enumeration| enumeration.name()));
+                prefixes.extend(types.map(|name| format!("{scope}.{name}")));
+            }
         }
+        prefixes
     }
 }
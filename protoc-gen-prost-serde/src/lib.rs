@@ -2,36 +2,45 @@
 
 use std::str;
 
-use prost::Message;
-use prost_types::compiler::CodeGeneratorRequest;
-use protoc_gen_prost::{Generator, InvalidParameter, ModuleRequestSet, Param, Params};
+use protoc_gen_prost::{
+    strict_extern_paths, GenerationCache, Generator, InvalidParameter, ModuleRequestSet, Param,
+    ParamSpec, ParameterError, PluginOpts, PluginRequest, PluginSpec,
+};
 
 use self::generator::PbJsonGenerator;
 
 mod generator;
 
 /// Execute the core _Prost!_ generator from a raw [`CodeGeneratorRequest`]
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
 pub fn execute(raw_request: &[u8]) -> protoc_gen_prost::Result {
-    let request = CodeGeneratorRequest::decode(raw_request)?;
-    let params = request.parameter().parse::<Parameters>()?;
-
-    let mut builder = params.to_pbjson_builder();
-    for file in &request.proto_file {
-        builder.register_file_descriptor(file.clone());
-    }
+    let request = PluginRequest::decode(raw_request)?;
+    let parameter = request.parameter().to_owned();
+    let opts = PluginOpts::load(&parameter, &PLUGIN)?;
+    protoc_gen_prost::dump_request(raw_request, env!("CARGO_PKG_VERSION"), &[&opts])?;
+    let params = Parameters::from_opts(&opts)?;
 
-    let module_request_set = ModuleRequestSet::new(
-        request.file_to_generate,
-        request.proto_file,
-        raw_request,
+    let mut module_request_set = ModuleRequestSet::from_request(
+        request,
         params.default_package_filename.as_deref(),
         params.flat_output_dir,
     )?;
+    let mut generator = params.into_generator(&module_request_set)?;
 
-    let files = PbJsonGenerator::new(builder, !params.no_include, params.feature)
-        .generate(&module_request_set)?;
+    match GenerationCache::from_opts(&opts, env!("CARGO_PKG_VERSION")) {
+        Some(cache) => cache.generate(&mut module_request_set, &mut generator),
+        None => generator.generate(&module_request_set),
+    }
+}
 
-    Ok(files)
+/// Creates the generator of `protoc-gen-prost-serde` as configured by `opts`,
+/// for running as part of a combined plugin
+pub fn generator(
+    opts: &PluginOpts<'_>,
+    module_request_set: &ModuleRequestSet,
+) -> Result<impl Generator, protoc_gen_prost::Error> {
+    Parameters::from_opts(opts)?.into_generator(module_request_set)
 }
 
 /// Parameters use to configure [`Generator`]s built into `protoc-gen-prost-serde`
@@ -51,6 +60,7 @@ struct Parameters {
     flat_output_dir: bool,
     exclude: Vec<String>,
     feature: Option<String>,
+    strict: bool,
 }
 
 impl Parameters {
@@ -98,108 +108,202 @@ impl Parameters {
 /// This name will be used as a gate feature name when "feature" parameter is passed without value
 const DEFAULT_FEATURE_NAME: &str = "serde";
 
-impl str::FromStr for Parameters {
-    type Err = InvalidParameter;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut ret_val = Self::default();
-        for param in Params::from_protoc_plugin_opts(s)? {
-            match param {
-                Param::Parameter {
-                    param: "default_package_filename",
-                }
-                | Param::Value {
-                    param: "default_package_filename",
-                    ..
-                } => ret_val.default_package_filename = param.value().map(|s| s.into_owned()),
-                Param::Parameter {
-                    param: "retain_enum_prefix",
-                }
-                | Param::Value {
-                    param: "retain_enum_prefix",
-                    value: "true",
-                } => ret_val.retain_enum_prefix = true,
-                Param::Value {
-                    param: "retain_enum_prefix",
-                    value: "false",
-                } => (),
-                Param::Parameter {
-                    param: "preserve_proto_field_names",
-                }
-                | Param::Value {
-                    param: "preserve_proto_field_names",
-                    value: "true",
-                } => ret_val.preserve_proto_field_names = true,
-                Param::Value {
-                    param: "preserve_proto_field_names",
-                    value: "false",
-                } => (),
-                Param::Parameter {
-                    param: "ignore_unknown_fields",
-                }
-                | Param::Value {
-                    param: "ignore_unknown_fields",
-                    value: "true",
-                } => ret_val.ignore_unknown_fields = true,
-                Param::Parameter {
-                    param: "emit_fields",
-                }
-                | Param::Value {
-                    param: "emit_fields",
-                    value: "true",
-                } => ret_val.emit_fields = true,
-                Param::Parameter {
-                    param: "use_integers_for_enums",
-                }
-                | Param::Value {
-                    param: "use_integers_for_enums",
-                    value: "true",
-                } => ret_val.use_integers_for_enums = true,
-                Param::Parameter {
-                    param: "no_include",
-                }
-                | Param::Value {
-                    param: "no_include",
-                    value: "true",
-                } => ret_val.no_include = true,
-                Param::Value {
-                    param: "no_include",
-                    value: "false",
-                } => (),
-                Param::KeyValue {
-                    param: "extern_path",
-                    key: prefix,
-                    value: module,
-                } => ret_val.extern_path.push((prefix.to_string(), module)),
-                Param::Value {
-                    param: "btree_map",
-                    value,
-                } => ret_val.btree_map.push(value.to_string()),
-                Param::Parameter {
-                    param: "flat_output_dir",
-                }
-                | Param::Value {
-                    param: "flat_output_dir",
-                    value: "true",
-                } => ret_val.flat_output_dir = true,
-                Param::Value {
-                    param: "flat_output_dir",
-                    value: "false",
-                } => (),
-                Param::Value {
-                    param: "exclude",
-                    value: prefix,
-                } => ret_val.exclude.push(prefix.to_string()),
-                Param::Parameter { param: "feature" } => {
-                    ret_val.feature = Some(DEFAULT_FEATURE_NAME.to_string())
-                }
-                Param::Value {
-                    param: "feature",
-                    value,
-                } => ret_val.feature = Some(value.to_string()),
-                _ => return Err(InvalidParameter::from(param)),
+/// The parameters accepted by `protoc-gen-prost-serde`
+pub const PLUGIN: PluginSpec = PluginSpec {
+    name: "protoc-gen-prost-serde",
+    section: "serde",
+    params: &[
+        ParamSpec::new("btree_map", "btree_map=<proto_path>"),
+        ParamSpec::new(
+            "default_package_filename",
+            "default_package_filename=<value>",
+        ),
+        ParamSpec::new("extern_path", "extern_path=<proto_path>=<rust_path>"),
+        ParamSpec::new("retain_enum_prefix", "retain_enum_prefix(=<boolean>)"),
+        ParamSpec::new(
+            "preserve_proto_field_names",
+            "preserve_proto_field_names(=<boolean>)",
+        ),
+        ParamSpec::new("ignore_unknown_fields", "ignore_unknown_fields(=true)"),
+        ParamSpec::new("emit_fields", "emit_fields(=true)"),
+        ParamSpec::new("use_integers_for_enums", "use_integers_for_enums(=true)"),
+        ParamSpec::new("no_include", "no_include(=<boolean>)"),
+        ParamSpec::new("flat_output_dir", "flat_output_dir(=<boolean>)"),
+        ParamSpec::new("exclude", "exclude=<proto_path>"),
+        ParamSpec::new("feature", "feature(=<string>)"),
+        ParamSpec::new("strict", "strict(=<boolean>)"),
+    ],
+};
+
+impl Parameters {
+    fn handle_parameter(&mut self, param: Param<'_>) -> Result<(), ParameterError> {
+        match param {
+            Param::Parameter {
+                param: "default_package_filename",
             }
+            | Param::Value {
+                param: "default_package_filename",
+                ..
+            } => self.default_package_filename = param.value().map(|s| s.into_owned()),
+            Param::Parameter {
+                param: "retain_enum_prefix",
+            }
+            | Param::Value {
+                param: "retain_enum_prefix",
+                value: "true",
+            } => self.retain_enum_prefix = true,
+            Param::Value {
+                param: "retain_enum_prefix",
+                value: "false",
+            } => (),
+            Param::Parameter {
+                param: "preserve_proto_field_names",
+            }
+            | Param::Value {
+                param: "preserve_proto_field_names",
+                value: "true",
+            } => self.preserve_proto_field_names = true,
+            Param::Value {
+                param: "preserve_proto_field_names",
+                value: "false",
+            } => (),
+            Param::Parameter {
+                param: "ignore_unknown_fields",
+            }
+            | Param::Value {
+                param: "ignore_unknown_fields",
+                value: "true",
+            } => self.ignore_unknown_fields = true,
+            Param::Parameter {
+                param: "emit_fields",
+            }
+            | Param::Value {
+                param: "emit_fields",
+                value: "true",
+            } => self.emit_fields = true,
+            Param::Parameter {
+                param: "use_integers_for_enums",
+            }
+            | Param::Value {
+                param: "use_integers_for_enums",
+                value: "true",
+            } => self.use_integers_for_enums = true,
+            Param::Parameter {
+                param: "no_include",
+            }
+            | Param::Value {
+                param: "no_include",
+                value: "true",
+            } => self.no_include = true,
+            Param::Value {
+                param: "no_include",
+                value: "false",
+            } => (),
+            Param::KeyValue {
+                param: "extern_path",
+                key: prefix,
+                value: module,
+            } => self.extern_path.push((prefix.to_string(), module)),
+            Param::Value {
+                param: "btree_map",
+                value,
+            } => self.btree_map.push(value.to_string()),
+            Param::Parameter {
+                param: "flat_output_dir",
+            }
+            | Param::Value {
+                param: "flat_output_dir",
+                value: "true",
+            } => self.flat_output_dir = true,
+            Param::Value {
+                param: "flat_output_dir",
+                value: "false",
+            } => (),
+            Param::Value {
+                param: "exclude",
+                value: prefix,
+            } => self.exclude.push(prefix.to_string()),
+            Param::Parameter { param: "feature" } => {
+                self.feature = Some(DEFAULT_FEATURE_NAME.to_string())
+            }
+            Param::Value {
+                param: "feature",
+                value,
+            } => self.feature = Some(value.to_string()),
+            Param::Parameter { param: "strict" }
+            | Param::Value {
+                param: "strict",
+                value: "true",
+            } => self.strict = true,
+            Param::Value {
+                param: "strict",
+                value: "false",
+            } => (),
+            _ => return Err(PLUGIN.reject(param)),
         }
 
+        Ok(())
+    }
+}
+
+impl Parameters {
+    fn from_opts(opts: &PluginOpts<'_>) -> Result<Self, InvalidParameter> {
+        let mut ret_val = Self::default();
+        opts.apply(&mut ret_val, Self::handle_parameter)?;
         Ok(ret_val)
     }
+
+    fn into_generator(
+        mut self,
+        module_request_set: &ModuleRequestSet,
+    ) -> Result<PbJsonGenerator, protoc_gen_prost::Error> {
+        if self.strict {
+            self.extern_path = strict_extern_paths(module_request_set, &self.extern_path)?;
+        }
+
+        // Register the resolved descriptors, so that editions files are
+        // presented to pbjson-build in their equivalent `proto3` form
+        let mut builder = self.to_pbjson_builder();
+        for (_, request) in module_request_set.requests() {
+            for file in request.files() {
+                builder.register_file_descriptor(file.clone());
+            }
+        }
+
+        Ok(PbJsonGenerator::new(builder, !self.no_include, self.feature).strict(self.strict))
+    }
+}
+
+impl str::FromStr for Parameters {
+    type Err = InvalidParameter;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_opts(&PluginOpts::load(s, &PLUGIN)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protoc_gen_prost::testing::{file, request, FieldType};
+
+    use super::*;
+
+    #[test]
+    fn fields_of_editions_files_have_explicit_presence() {
+        let request = request()
+            .file(
+                file("example.proto")
+                    .edition(protoc_gen_prost::MAXIMUM_EDITION)
+                    .package("example")
+                    .message("Example", |m| m.field("id", FieldType::Int64, 1)),
+            )
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let content = files
+            .iter()
+            .find(|f| f.name() == "example/example.serde.rs")
+            .unwrap()
+            .content();
+        assert!(content.contains("if self.id.is_some() {"), "{content}");
+    }
 }
@@ -0,0 +1,46 @@
+//! Snapshots of the code generated for the descriptor sets in `fixtures`
+//!
+//! Run with `PROTOC_GEN_PROST_BLESS=1` to update the snapshots.
+
+use std::{fs, path::Path};
+
+use protoc_gen_prost::testing::{assert_snapshot, request_from_descriptor_set};
+
+fn check(name: &str, fixture: &str, files: &[&str], parameter: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let descriptor_set = fs::read(root.join(format!("../fixtures/{fixture}.binpb"))).unwrap();
+    let request = request_from_descriptor_set(&descriptor_set, files, parameter);
+
+    let files = protoc_gen_prost_serde::execute(&request).expect("code generation failed");
+    assert_snapshot(root.join(format!("tests/snapshots/{name}.txt")), &files);
+}
+
+#[test]
+fn helloworld() {
+    check(
+        "helloworld",
+        "helloworld",
+        &["helloworld/v1/helloworld.proto"],
+        "",
+    );
+}
+
+#[test]
+fn greeter() {
+    check(
+        "greeter",
+        "greeter",
+        &["greeter/v1/greeter.proto"],
+        "feature=json,preserve_proto_field_names",
+    );
+}
+
+#[test]
+fn well_known_types() {
+    check(
+        "wkt",
+        "wkt",
+        &["wkt/v1/types.proto"],
+        "extern_path=.google.protobuf=::pbjson_types",
+    );
+}
@@ -0,0 +1,196 @@
+//! Measures code generation for a large synthetic `CodeGeneratorRequest`
+//!
+//! Run with `cargo bench -p protoc-gen-prost`. The request is generated once
+//! with sequential generation (`jobs=1`) and once with a thread per available
+//! core (the default), reporting the fastest of several runs for each.
+
+use std::{
+    num::NonZeroUsize,
+    thread,
+    time::{Duration, Instant},
+};
+
+use prost::Message;
+use prost_types::{
+    compiler::CodeGeneratorRequest,
+    field_descriptor_proto::{Label, Type},
+    source_code_info::Location,
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FileDescriptorProto, SourceCodeInfo,
+};
+
+const PACKAGES: usize = 64;
+const FILES_PER_PACKAGE: usize = 4;
+const MESSAGES_PER_FILE: usize = 24;
+const FIELDS_PER_MESSAGE: usize = 12;
+const ENUM_VALUES: i32 = 8;
+const RUNS: usize = 5;
+
+fn main() {
+    let request = large_request();
+    println!(
+        "request: {PACKAGES} packages, {} files, {} messages, {} bytes",
+        PACKAGES * FILES_PER_PACKAGE,
+        PACKAGES * FILES_PER_PACKAGE * MESSAGES_PER_FILE,
+        request.len(),
+    );
+
+    let jobs = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let sequential = bench(1, &request);
+    let parallel = bench(jobs, &request);
+    println!(
+        "speedup: {:.2}x",
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
+
+/// Generates `request` on up to `jobs` threads for several runs, returning
+/// the fastest
+fn bench(jobs: usize, request: &[u8]) -> Duration {
+    let mut request = CodeGeneratorRequest::decode(request).unwrap();
+    request.parameter = Some(format!("jobs={jobs}"));
+    let request = request.encode_to_vec();
+
+    let fastest = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let files = protoc_gen_prost::execute(&request).expect("code generation failed");
+            let elapsed = start.elapsed();
+            assert_eq!(files.len(), PACKAGES);
+            elapsed
+        })
+        .min()
+        .unwrap();
+
+    println!("jobs={jobs:<4} {fastest:?}");
+    fastest
+}
+
+/// Packages that each import a shared `common` file and the previous package,
+/// with documented messages referencing messages from both
+fn large_request() -> Vec<u8> {
+    let mut proto_file = vec![FileDescriptorProto {
+        name: Some("common/common.proto".to_owned()),
+        package: Some("common".to_owned()),
+        message_type: vec![message("Shared", None)],
+        syntax: Some("proto3".to_owned()),
+        ..Default::default()
+    }];
+    let mut file_to_generate = Vec::new();
+
+    for package in 0..PACKAGES {
+        for file in 0..FILES_PER_PACKAGE {
+            let name = format!("pkg{package}/file{file}.proto");
+            let mut dependency = vec!["common/common.proto".to_owned()];
+            if package > 0 {
+                dependency.push(format!("pkg{}/file{file}.proto", package - 1));
+            }
+            let previous = (package > 0).then(|| format!(".pkg{}.File{file}Message0", package - 1));
+
+            let message_type: Vec<_> = (0..MESSAGES_PER_FILE)
+                .map(|index| message(&format!("File{file}Message{index}"), previous.as_deref()))
+                .collect();
+            let source_code_info = source_code_info(&name, &message_type);
+            let enum_type = vec![enumeration(&format!("File{file}Kind"))];
+
+            proto_file.push(FileDescriptorProto {
+                name: Some(name.clone()),
+                package: Some(format!("pkg{package}")),
+                dependency,
+                message_type,
+                enum_type,
+                source_code_info: Some(source_code_info),
+                syntax: Some("proto3".to_owned()),
+                ..Default::default()
+            });
+            file_to_generate.push(name);
+        }
+    }
+
+    CodeGeneratorRequest {
+        file_to_generate,
+        proto_file,
+        ..Default::default()
+    }
+    .encode_to_vec()
+}
+
+/// Documents every message, field, enum, and enum value
+///
+/// _Prost!_ expects a location for each of these, sorted by path.
+fn source_code_info(name: &str, message_type: &[DescriptorProto]) -> SourceCodeInfo {
+    let location = |path: Vec<i32>| Location {
+        leading_comments: Some(format!(
+            " Element {path:?} of {name}, with a comment long enough to be wrapped\n over several \
+             lines.\n"
+        )),
+        path,
+        span: vec![0, 0, 1],
+        ..Default::default()
+    };
+
+    let mut locations = Vec::new();
+    for (index, message) in message_type.iter().enumerate() {
+        locations.push(location(vec![4, index as i32]));
+        for field in 0..message.field.len() {
+            locations.push(location(vec![4, index as i32, 2, field as i32]));
+        }
+    }
+    locations.push(location(vec![5, 0]));
+    for value in 0..ENUM_VALUES {
+        locations.push(location(vec![5, 0, 2, value]));
+    }
+    locations.sort_by(|a, b| a.path.cmp(&b.path));
+
+    SourceCodeInfo {
+        location: locations,
+    }
+}
+
+fn message(name: &str, previous: Option<&str>) -> DescriptorProto {
+    let scalar = |number: usize| FieldDescriptorProto {
+        name: Some(format!("field{number}")),
+        number: Some(number as i32),
+        label: Some(Label::Optional as i32),
+        r#type: Some(if number.is_multiple_of(2) {
+            Type::String
+        } else {
+            Type::Int64
+        } as i32),
+        ..Default::default()
+    };
+    let reference = |number: usize, type_name: &str| FieldDescriptorProto {
+        name: Some(format!("field{number}")),
+        number: Some(number as i32),
+        label: Some(Label::Repeated as i32),
+        r#type: Some(Type::Message as i32),
+        type_name: Some(type_name.to_owned()),
+        ..Default::default()
+    };
+
+    let mut field: Vec<_> = (1..=FIELDS_PER_MESSAGE).map(scalar).collect();
+    field.push(reference(FIELDS_PER_MESSAGE + 1, ".common.Shared"));
+    if let Some(previous) = previous {
+        field.push(reference(FIELDS_PER_MESSAGE + 2, previous));
+    }
+
+    DescriptorProto {
+        name: Some(name.to_owned()),
+        field,
+        ..Default::default()
+    }
+}
+
+fn enumeration(name: &str) -> EnumDescriptorProto {
+    EnumDescriptorProto {
+        name: Some(name.to_owned()),
+        value: (0..ENUM_VALUES)
+            .map(|number| EnumValueDescriptorProto {
+                name: Some(format!("{name}_VALUE_{number}").to_uppercase()),
+                number: Some(number),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
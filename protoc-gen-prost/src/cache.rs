@@ -0,0 +1,543 @@
+//! Incremental generation, reusing the output cached by previous runs
+//!
+//! The output for each module is cached under a key derived from the plugin,
+//! its version, the effective parameters, and the encoded descriptors of the
+//! module's files and of every file that they transitively import. Modules
+//! whose key is found in the cache are not generated again. Files that do
+//! not belong to a single module are cached under a key derived from every
+//! file in the request.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use prost::Message;
+use prost_build::Module;
+use prost_types::compiler::code_generator_response::File;
+use twox_hash::XxHash3_128;
+
+use crate::{
+    generator::per_file_filename, Generator, ModuleRequest, ModuleRequestSet, PluginOpts, Result,
+};
+
+/// The imports and encoded descriptor of every file in a request, by name
+type AllFiles<'a> = BTreeMap<&'a str, (&'a [String], &'a [u8])>;
+
+/// A cache of the files generated for each module, enabled with
+/// `cache_dir=<path>`
+///
+/// Entries are never modified once written, so the cache directory can be
+/// shared between plugins and removed at any time.
+pub struct GenerationCache {
+    dir: PathBuf,
+    hasher: XxHash3_128,
+}
+
+impl GenerationCache {
+    /// Opens the cache for the plugin, if the options enable one
+    ///
+    /// `version` should be the version of the plugin, so that upgrading the
+    /// plugin invalidates any output cached by previous versions.
+    pub fn from_opts(opts: &PluginOpts<'_>, version: &str) -> Option<Self> {
+        let dir = opts.cache_dir()?;
+        let plugin = opts.plugin();
+
+        let mut hasher = XxHash3_128::new();
+        write_field(&mut hasher, version.as_bytes());
+        write_field(&mut hasher, env!("CARGO_PKG_VERSION").as_bytes());
+
+        let cache = Self {
+            dir: dir.join(plugin.name),
+            hasher,
+        };
+        Some(cache.with_opts(opts))
+    }
+
+    /// Also keys the cache on the options of another plugin, for combined
+    /// plugins that run the generators of several plugins
+    pub fn with_opts(mut self, opts: &PluginOpts<'_>) -> Self {
+        write_field(&mut self.hasher, opts.plugin().name.as_bytes());
+        for param in opts.shared_params().chain(opts.params()) {
+            write_field(&mut self.hasher, param.to_string().as_bytes());
+        }
+        self
+    }
+
+    /// Generates the modules that are not already cached with `generator`,
+    /// returning the output for every module
+    ///
+    /// The modules that are not cached are generated together, while the
+    /// other modules in `module_request_set` have no output file. Each file
+    /// is then cached with the module that it belongs to, while files that
+    /// belong to no generated module, such as a shared descriptor pool, are
+    /// cached for the whole request.
+    pub fn generate<G>(
+        &self,
+        module_request_set: &mut ModuleRequestSet,
+        generator: &mut G,
+    ) -> Result
+    where
+        G: Generator,
+    {
+        let whole_request = generator.depends_on_whole_request();
+        let all_files: AllFiles = module_request_set
+            .requests()
+            .flat_map(|(_, request)| request.files().zip(request.raw_files()))
+            .map(|(file, raw)| (file.name(), (file.dependency.as_slice(), raw)))
+            .collect();
+        let keys: BTreeMap<Module, String> = module_request_set
+            .requests()
+            .filter(|(_, request)| request.output_filename().is_some())
+            .map(|(module, request)| (module.clone(), self.key(&all_files, request, whole_request)))
+            .collect();
+        let shared_key = self.shared_key(&all_files, module_request_set);
+
+        let mut entries = BTreeMap::new();
+        for (module, key) in &keys {
+            if let Some(entry) = self.load(key) {
+                entries.insert(module.clone(), entry);
+            }
+        }
+        let shared = self.load(&shared_key);
+
+        let shared = match shared {
+            Some(shared) if entries.len() == keys.len() => shared,
+            _ => {
+                let parts = generate_misses(module_request_set, generator, &entries)?;
+
+                let owners = Owners::new(module_request_set);
+                let mut generated: BTreeMap<Module, CacheEntry> = keys
+                    .keys()
+                    .filter(|module| !entries.contains_key(*module))
+                    .map(|module| (module.clone(), CacheEntry::default()))
+                    .collect();
+                let mut shared = CacheEntry::default();
+                for (part, files) in (0u32..).zip(parts) {
+                    for file in files {
+                        match owners.owner(file.name()) {
+                            // Cached modules are generated again by generators
+                            // that ignore which modules have an output file
+                            Some(module) if entries.contains_key(module) => (),
+                            Some(module) if generated.contains_key(module) => {
+                                generated.get_mut(module).unwrap().push(part, file);
+                            }
+                            _ => shared.push(part, file),
+                        }
+                    }
+                }
+
+                for (module, entry) in generated {
+                    self.store(&keys[&module], &entry)?;
+                    entries.insert(module, entry);
+                }
+                self.store(&shared_key, &shared)?;
+                shared
+            }
+        };
+
+        Ok(assemble(module_request_set, entries, shared))
+    }
+
+    /// The key of the cached output for `request`
+    fn key(&self, all_files: &AllFiles, request: &ModuleRequest, whole_request: bool) -> String {
+        let files = if whole_request {
+            all_files.keys().copied().collect()
+        } else {
+            let mut files = HashSet::new();
+            let mut pending: Vec<&str> = request.files().map(|file| file.name()).collect();
+            while let Some(name) = pending.pop() {
+                if files.insert(name) {
+                    if let Some((dependency, _)) = all_files.get(name) {
+                        pending.extend(dependency.iter().map(String::as_str));
+                    }
+                }
+            }
+            files
+        };
+
+        let mut hasher = self.hasher.clone();
+        write_field(
+            &mut hasher,
+            request.output_filepath().unwrap_or_default().as_bytes(),
+        );
+        // Only the requested files are generated in strict mode
+        for file in request.requested_files() {
+            write_field(&mut hasher, file.name().as_bytes());
+        }
+        for (name, (_, raw)) in all_files.iter().filter(|(name, _)| files.contains(*name)) {
+            write_field(&mut hasher, name.as_bytes());
+            write_field(&mut hasher, raw);
+        }
+
+        format!("{:032x}", hasher.finish_128())
+    }
+
+    /// The key of the cached output that belongs to no module, which
+    /// depends on every file in the request
+    fn shared_key(&self, all_files: &AllFiles, module_request_set: &ModuleRequestSet) -> String {
+        let mut hasher = self.hasher.clone();
+        // No output file is named with an empty path
+        write_field(&mut hasher, b"");
+        for (_, request) in module_request_set.requests() {
+            write_field(
+                &mut hasher,
+                request.output_filepath().unwrap_or_default().as_bytes(),
+            );
+            for file in request.requested_files() {
+                write_field(&mut hasher, file.name().as_bytes());
+            }
+        }
+        for (name, (_, raw)) in all_files {
+            write_field(&mut hasher, name.as_bytes());
+            write_field(&mut hasher, raw);
+        }
+
+        format!("{:032x}", hasher.finish_128())
+    }
+
+    /// Reads the files cached under `key`
+    ///
+    /// Entries that cannot be read are treated as missing, and are replaced
+    /// once the module has been generated.
+    fn load(&self, key: &str) -> Option<CacheEntry> {
+        let encoded = fs::read(self.dir.join(key)).ok()?;
+        let entry = CacheEntry::decode(encoded.as_slice()).ok()?;
+        (entry.file.len() == entry.part.len()).then_some(entry)
+    }
+
+    /// Caches the files generated under `key`
+    fn store(&self, key: &str, entry: &CacheEntry) -> std::result::Result<(), crate::Error> {
+        write_atomically(&self.dir, key, &entry.encode_to_vec()).map_err(|e| {
+            format!("unable to write to cache_dir `{}`: {e}", self.dir.display()).into()
+        })
+    }
+}
+
+/// The files cached for a module, or for the whole request
+#[derive(Clone, PartialEq, prost::Message)]
+struct CacheEntry {
+    #[prost(message, repeated, tag = "1")]
+    file: Vec<File>,
+
+    /// The index of the generator in a chain that produced each file
+    #[prost(uint32, repeated, tag = "2")]
+    part: Vec<u32>,
+}
+
+impl CacheEntry {
+    fn push(&mut self, part: u32, file: File) {
+        self.file.push(file);
+        self.part.push(part);
+    }
+}
+
+/// Generates the modules without an entry in `entries` in a single pass,
+/// restoring the output file of every module afterwards
+fn generate_misses<G>(
+    module_request_set: &mut ModuleRequestSet,
+    generator: &mut G,
+    entries: &BTreeMap<Module, CacheEntry>,
+) -> std::result::Result<Vec<Vec<File>>, crate::Error>
+where
+    G: Generator,
+{
+    let output_filenames: Vec<(Module, String)> = module_request_set
+        .requests
+        .iter_mut()
+        .filter(|(module, _)| entries.contains_key(*module))
+        .filter_map(|(module, request)| {
+            let filename = request.output_filename.take()?;
+            Some((module.clone(), filename))
+        })
+        .collect();
+
+    let parts = generator.generate_parts(module_request_set);
+
+    for (module, filename) in output_filenames {
+        if let Some(request) = module_request_set.requests.get_mut(&module) {
+            request.output_filename = Some(filename);
+        }
+    }
+    parts
+}
+
+/// Puts the cached files back in the order that `generator` produces them
+///
+/// Each generator writes the files of one module after another, in the
+/// order of the modules, after any file that belongs to no module.
+fn assemble(
+    module_request_set: &ModuleRequestSet,
+    entries: BTreeMap<Module, CacheEntry>,
+    shared: CacheEntry,
+) -> Vec<File> {
+    let owners = Owners::new(module_request_set);
+    let mut files: Vec<(u32, Option<usize>, File)> = Vec::new();
+    for entry in entries.into_values().chain([shared]) {
+        for (part, file) in entry.part.into_iter().zip(entry.file) {
+            files.push((part, owners.position(file.name()), file));
+        }
+    }
+    // The sort is stable, so the files of each module keep their order
+    files.sort_by_key(|&(part, position, _)| (part, position));
+
+    files.into_iter().map(|(_, _, file)| file).collect()
+}
+
+/// Finds the module that a generated file belongs to from its name
+///
+/// A module owns its output file, the file generated for each of its
+/// `.proto` files with `output_layout=per_file`, and the files named after
+/// its package, such as `<package>.tonic.rs`, in its output directory.
+struct Owners<'a> {
+    modules: Vec<&'a Module>,
+    files: HashMap<String, usize>,
+    packages: HashMap<String, usize>,
+}
+
+impl<'a> Owners<'a> {
+    fn new(module_request_set: &'a ModuleRequestSet) -> Self {
+        let mut files = HashMap::new();
+        let mut packages = HashMap::new();
+        for (index, (_, request)) in module_request_set.requests().enumerate() {
+            let output_dir = request.output_dir();
+            for proto in request.files() {
+                files.insert(format!("{output_dir}{}", per_file_filename(proto)), index);
+            }
+            packages.insert(
+                format!("{output_dir}{}", request.proto_package_name()),
+                index,
+            );
+        }
+        // Output files, and the sibling files named after them, take precedence
+        // over the names derived from packages
+        for (index, (_, request)) in module_request_set.requests().enumerate() {
+            if let Some(filepath) = request.output_filepath() {
+                let stem = filepath.strip_suffix(".rs").unwrap_or(&filepath);
+                packages.insert(stem.to_owned(), index);
+                files.insert(filepath, index);
+            }
+        }
+
+        Self {
+            modules: module_request_set.modules().collect(),
+            files,
+            packages,
+        }
+    }
+
+    /// The position of the module that owns the file named `name`, in the
+    /// order of the modules
+    fn position(&self, name: &str) -> Option<usize> {
+        if let Some(&index) = self.files.get(name) {
+            return Some(index);
+        }
+
+        let (package, _) = name.strip_suffix(".rs")?.rsplit_once('.')?;
+        self.packages.get(package).copied()
+    }
+
+    /// The module that owns the file named `name`
+    fn owner(&self, name: &str) -> Option<&'a Module> {
+        self.position(name).map(|index| self.modules[index])
+    }
+}
+
+/// Writes a length-delimited field, so that adjacent fields cannot be
+/// confused for one another
+fn write_field(hasher: &mut XxHash3_128, bytes: &[u8]) {
+    hasher.write(&(bytes.len() as u64).to_le_bytes());
+    hasher.write(bytes);
+}
+
+/// Writes `contents` to a temporary file before moving it into place, so
+/// that concurrent runs never read a partially written entry
+fn write_atomically(dir: &Path, name: &str, contents: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let temporary = dir.join(format!("{name}.{}.tmp", std::process::id()));
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, dir.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::FileDescriptorProto;
+
+    use super::*;
+    use crate::{PluginRequest, PluginSpec};
+
+    const PLUGIN: PluginSpec = PluginSpec {
+        name: "protoc-gen-test",
+        section: "prost",
+        params: &[],
+    };
+
+    fn module_request_set(files: &[(&str, &[&str], &str)]) -> ModuleRequestSet {
+        let request = prost_types::compiler::CodeGeneratorRequest {
+            file_to_generate: files
+                .iter()
+                .map(|(name, ..)| format!("{name}.proto"))
+                .collect(),
+            proto_file: files
+                .iter()
+                .map(|&(name, dependency, go_package)| FileDescriptorProto {
+                    name: Some(format!("{name}.proto")),
+                    package: Some(name.to_owned()),
+                    dependency: dependency.iter().map(|d| format!("{d}.proto")).collect(),
+                    // Stands in for any change to the file
+                    options: Some(prost_types::FileOptions {
+                        go_package: Some(go_package.to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let request = PluginRequest::decode(&request.encode_to_vec()).unwrap();
+        ModuleRequestSet::from_request(request, None, false).unwrap()
+    }
+
+    /// Writes a file for each module, recording the packages generated
+    #[derive(Default)]
+    struct Recorder {
+        generated: Vec<String>,
+    }
+
+    impl Generator for Recorder {
+        fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
+            let mut files = Vec::new();
+            for (_, request) in module_request_set.requests() {
+                if let Some(file) = request.write_to_file(|buffer| buffer.push_str("// code\n")) {
+                    self.generated.push(request.proto_package_name().to_owned());
+                    files.push(file);
+                }
+            }
+            Ok(files)
+        }
+    }
+
+    /// Generates `files`, returning the output and the packages generated
+    fn generate(
+        cache: &GenerationCache,
+        files: &[(&str, &[&str], &str)],
+    ) -> (Vec<File>, Vec<String>) {
+        let mut module_request_set = module_request_set(files);
+        let mut recorder = Recorder::default();
+        let files = cache
+            .generate(&mut module_request_set, &mut recorder)
+            .unwrap();
+
+        // Every output file is restored once generation is complete
+        assert!(module_request_set
+            .requests()
+            .all(|(_, request)| request.output_filename().is_some()));
+        (files, recorder.generated)
+    }
+
+    fn open(dir: &Path, parameter: &str, version: &str) -> GenerationCache {
+        let parameter = format!("cache_dir={},{parameter}", dir.display());
+        let opts = PluginOpts::load(&parameter, &PLUGIN).unwrap();
+        GenerationCache::from_opts(&opts, version).unwrap()
+    }
+
+    #[test]
+    fn only_modules_with_changed_imports_are_generated() {
+        let dir =
+            std::env::temp_dir().join(format!("protoc-gen-prost-cache-{}", std::process::id()));
+        let cache = open(&dir, "", "1.0.0");
+
+        let files: &[(&str, &[&str], &str)] =
+            &[("a", &[], "1"), ("b", &["a"], "1"), ("c", &[], "1")];
+        let (output, generated) = generate(&cache, files);
+        assert_eq!(generated, ["a", "b", "c"]);
+
+        let (cached, generated) = generate(&cache, files);
+        assert!(generated.is_empty());
+        assert_eq!(cached, output);
+
+        let files: &[(&str, &[&str], &str)] =
+            &[("a", &[], "1"), ("b", &["a"], "1"), ("c", &[], "2")];
+        let (_, generated) = generate(&cache, files);
+        assert_eq!(generated, ["c"]);
+
+        let files: &[(&str, &[&str], &str)] =
+            &[("a", &[], "2"), ("b", &["a"], "1"), ("c", &[], "2")];
+        let (_, generated) = generate(&cache, files);
+        assert_eq!(generated, ["a", "b"]);
+
+        let (_, generated) = generate(&open(&dir, "", "1.0.1"), files);
+        assert_eq!(
+            generated,
+            ["a", "b", "c"],
+            "a new version invalidates the cache"
+        );
+
+        let (_, generated) = generate(&open(&dir, "flat_output_dir", "1.0.0"), files);
+        assert_eq!(
+            generated,
+            ["a", "b", "c"],
+            "new parameters invalidate the cache"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Writes a file for every package, whether it has an output file or
+    /// not, along with a file listing every package
+    struct Everything;
+
+    impl Generator for Everything {
+        fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
+            let go_package = |request: &ModuleRequest| {
+                let file = request.files().next().unwrap();
+                file.options.as_ref().unwrap().go_package().to_owned()
+            };
+            let file = |name: String, content: String| File {
+                name: Some(name),
+                content: Some(content),
+                ..Default::default()
+            };
+
+            let mut index = String::new();
+            let mut files = Vec::new();
+            for (_, request) in module_request_set.requests() {
+                let package = request.proto_package_name();
+                index.push_str(&format!("{package}={}\n", go_package(request)));
+                files.push(file(
+                    format!("{}{package}.everything.rs", request.output_dir()),
+                    go_package(request),
+                ));
+            }
+            files.insert(0, file("index.rs".to_owned(), index));
+            Ok(files)
+        }
+    }
+
+    #[test]
+    fn files_of_other_modules_are_never_stale() {
+        let dir = std::env::temp_dir().join(format!(
+            "protoc-gen-prost-cache-shared-{}",
+            std::process::id()
+        ));
+        let cache = open(&dir, "", "1.0.0");
+        let generate = |files: &[(&str, &[&str], &str)]| {
+            let mut module_request_set = module_request_set(files);
+            let cached = cache
+                .generate(&mut module_request_set, &mut Everything)
+                .unwrap();
+            let generated = Everything.generate(&module_request_set).unwrap();
+            assert_eq!(cached, generated);
+            cached
+        };
+
+        let cold = generate(&[("a", &[], "1"), ("b", &[], "1")]);
+        assert_eq!(cold.len(), 3);
+        generate(&[("a", &[], "1"), ("b", &[], "1")]);
+        generate(&[("a", &[], "1"), ("b", &[], "2")]);
+        generate(&[("a", &[], "2"), ("b", &[], "2")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+//! Running a plugin without `protoc`
+//!
+//! `<plugin> generate` builds the [`CodeGeneratorRequest`] from encoded
+//! [`FileDescriptorSet`]s, such as those written by `protoc --descriptor_set_out`
+//! or `buf build`, and writes the generated files to disk, applying insertion
+//! points as `protoc` would.
+//!
+//! [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+//! [`FileDescriptorSet`]: prost_types::FileDescriptorSet
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashSet},
+    env, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use prost::{bytes::Bytes, Message};
+use prost_types::{compiler::code_generator_response::File, FileDescriptorProto};
+
+use crate::{replay_request, Error, GeneratorResultExt, RawProtos, Result};
+
+const USAGE: &str = "\
+usage: <plugin> generate --descriptor-set-in <path> --files <file,...>
+                         [--opt <parameter>] --out <dir>
+
+Generates code for the named files from encoded FileDescriptorSets, such as those
+written by `protoc --include_imports --descriptor_set_out` or `buf build`.
+
+options:
+  --descriptor-set-in <path>  An encoded FileDescriptorSet, which may be repeated
+  --files <file,...>          The proto files to generate code for, which may be repeated
+  --opt <parameter>           A parameter passed to the plugin, which may be repeated
+  --out <dir>                 The directory to write the generated files to";
+
+/// A [`FileDescriptorSet`] whose files are left encoded, so that any unknown
+/// fields, such as custom options, are passed along unchanged
+///
+/// [`FileDescriptorSet`]: prost_types::FileDescriptorSet
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub(crate) struct RawFileDescriptorSet {
+    #[prost(bytes = "bytes", repeated, tag = "1")]
+    pub(crate) file: Vec<Bytes>,
+}
+
+/// The arguments of the `generate` command
+#[derive(Debug, Default)]
+struct GenerateArgs {
+    descriptor_sets: Vec<PathBuf>,
+    files: Vec<String>,
+    opts: Vec<String>,
+    out: Option<PathBuf>,
+}
+
+impl GenerateArgs {
+    fn parse<I>(args: I) -> std::result::Result<Self, Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut ret_val = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(USAGE.into());
+            }
+
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), value.to_owned()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("`{arg}` requires a value\n\n{USAGE}"))?;
+                    (arg, value)
+                }
+            };
+
+            match flag.as_str() {
+                "--descriptor-set-in" => ret_val.descriptor_sets.push(value.into()),
+                "--files" => ret_val.files.extend(
+                    value
+                        .split(',')
+                        .filter(|file| !file.is_empty())
+                        .map(str::to_owned),
+                ),
+                "--opt" => ret_val.opts.push(value),
+                "--out" => ret_val.out = Some(value.into()),
+                _ => return Err(format!("unknown argument `{flag}`\n\n{USAGE}").into()),
+            }
+        }
+
+        if ret_val.descriptor_sets.is_empty() {
+            return Err(format!("`--descriptor-set-in` is required\n\n{USAGE}").into());
+        }
+        if ret_val.files.is_empty() {
+            return Err(format!("`--files` is required\n\n{USAGE}").into());
+        }
+        if ret_val.out.is_none() {
+            return Err(format!("`--out` is required\n\n{USAGE}").into());
+        }
+
+        Ok(ret_val)
+    }
+
+    /// Builds the encoded request for the files to generate
+    fn request(&self) -> std::result::Result<Vec<u8>, Error> {
+        let mut descriptor_sets = Vec::with_capacity(self.descriptor_sets.len());
+        for path in &self.descriptor_sets {
+            let encoded =
+                fs::read(path).map_err(|e| format!("unable to read `{}`: {e}", path.display()))?;
+            let set = RawFileDescriptorSet::decode(encoded.as_slice())
+                .map_err(|e| format!("`{}` is not a FileDescriptorSet: {e}", path.display()))?;
+            descriptor_sets.push(set);
+        }
+
+        encode_request(descriptor_sets, &self.files, &self.opts.join(","))
+    }
+}
+
+/// Builds an encoded request to generate `files` from the files of
+/// `descriptor_sets`
+///
+/// Files that appear in more than one descriptor set are only included
+/// once, in the order that they are first seen.
+pub(crate) fn encode_request(
+    descriptor_sets: Vec<RawFileDescriptorSet>,
+    files: &[String],
+    parameter: &str,
+) -> std::result::Result<Vec<u8>, Error> {
+    let mut names = HashSet::new();
+    let mut proto_file = Vec::new();
+    for raw in descriptor_sets.into_iter().flat_map(|set| set.file) {
+        let file = FileDescriptorProto::decode(raw.as_ref())?;
+        if names.insert(file.name().to_owned()) {
+            proto_file.push(raw);
+        }
+    }
+
+    if let Some(missing) = files.iter().find(|file| !names.contains(*file)) {
+        return Err(format!("`{missing}` is not in any descriptor set").into());
+    }
+
+    let request = RawProtos {
+        file_to_generate: files.to_vec(),
+        parameter: (!parameter.is_empty()).then(|| parameter.to_owned()),
+        proto_file,
+    };
+    Ok(request.encode_to_vec())
+}
+
+/// The `main` function of each plugin, using `execute` to generate the code
+///
+/// Answers `--version` with `version`, runs the `generate` command or
+/// `--replay <path>` when given, and otherwise reads an encoded
+/// [`CodeGeneratorRequest`] from stdin and writes the encoded response to
+/// stdout, as `protoc` expects.
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+pub fn plugin_main<F>(version: &str, execute: F) -> io::Result<()>
+where
+    F: FnOnce(&[u8]) -> Result,
+{
+    if env::args().any(|x| x == "--version") {
+        println!("{version}");
+        exit(0);
+    }
+
+    if env::args().nth(1).as_deref() == Some("generate") {
+        if let Err(error) = run_generate_command(env::args().skip(2), execute) {
+            eprintln!("{error}");
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if env::args().nth(1).as_deref() == Some("--replay") {
+        let path = env::args().nth(2).unwrap_or_default();
+        match replay_request(Path::new(&path), execute) {
+            Ok(description) => print!("{description}"),
+            Err(error) => {
+                eprintln!("{error}");
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    let mut buf = Vec::new();
+    io::stdin().read_to_end(&mut buf)?;
+
+    let response = execute(buf.as_slice()).encode_codegen_response();
+    io::stdout().write_all(&response)?;
+
+    Ok(())
+}
+
+/// Runs the `generate` command with the arguments that follow it, using
+/// `execute` to generate the code
+///
+/// Used by [`plugin_main`], so that any plugin can be run without `protoc`.
+pub fn run_generate_command<I, F>(args: I, execute: F) -> std::result::Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+    F: FnOnce(&[u8]) -> Result,
+{
+    let args = GenerateArgs::parse(args)?;
+    let request = args.request()?;
+    let files = execute(&request)?;
+    write_files(files, args.out.as_deref().unwrap_or(Path::new(".")))
+}
+
+/// Writes the files generated by a plugin to `out_dir`, applying insertion
+/// points as `protoc` would
+///
+/// Files that are inserted into a file that was not generated are inserted
+/// into the file already in `out_dir`, so that plugins can be run one after
+/// another.
+pub fn write_files(files: Vec<File>, out_dir: &Path) -> std::result::Result<(), Error> {
+    let contents =
+        apply_insertion_points(files, |name| fs::read_to_string(out_dir.join(name)).ok())?;
+
+    for (name, content) in contents {
+        let path = out_dir.join(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("unable to create `{}`: {e}", parent.display()))?;
+        }
+        fs::write(&path, content)
+            .map_err(|e| format!("unable to write `{}`: {e}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Resolves the content of every file, inserting the files with an
+/// insertion point into the file that they name
+///
+/// Inserted content is placed before the line holding the insertion point,
+/// with each line indented to match it. `existing` reads the content of files
+/// that were not generated.
+fn apply_insertion_points<F>(
+    files: Vec<File>,
+    existing: F,
+) -> std::result::Result<BTreeMap<String, String>, Error>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut contents = BTreeMap::new();
+    for file in files {
+        let name = file.name.unwrap_or_default();
+        let content = file.content.unwrap_or_default();
+
+        let insertion_point = match file.insertion_point.filter(|i| !i.is_empty()) {
+            Some(insertion_point) => insertion_point,
+            None => {
+                if contents.insert(name.clone(), content).is_some() {
+                    return Err(format!("`{name}` was generated more than once").into());
+                }
+                continue;
+            }
+        };
+
+        let target = match contents.entry(name) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match existing(entry.key()) {
+                Some(content) => entry.insert(content),
+                None => {
+                    return Err(format!(
+                        "tried to insert into `{}`, which does not exist",
+                        entry.key()
+                    )
+                    .into())
+                }
+            },
+        };
+
+        let marker = format!("@@protoc_insertion_point({insertion_point})");
+        let position = target.find(&marker).ok_or_else(|| {
+            format!("insertion point `{insertion_point}` not found in the target file")
+        })?;
+
+        let line_start = target[..position].rfind('\n').map_or(0, |i| i + 1);
+        let indent_len = target[line_start..]
+            .find(|c| c != ' ' && c != '\t')
+            .unwrap_or(0);
+        let indent = &target[line_start..line_start + indent_len];
+
+        let mut inserted = String::with_capacity(content.len());
+        for line in content.split_inclusive('\n') {
+            inserted.push_str(indent);
+            inserted.push_str(line);
+        }
+        if !inserted.ends_with('\n') {
+            inserted.push('\n');
+        }
+
+        target.insert_str(line_start, &inserted);
+    }
+
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{DescriptorProto, FileDescriptorSet};
+
+    use super::*;
+
+    fn file(name: &str, insertion_point: Option<&str>, content: &str) -> File {
+        File {
+            name: Some(name.to_owned()),
+            insertion_point: insertion_point.map(str::to_owned),
+            content: Some(content.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn insertions_are_indented_and_kept_in_order() {
+        let files = vec![
+            file(
+                "a.rs",
+                None,
+                "mod a {\n    // @@protoc_insertion_point(module)\n}\n",
+            ),
+            file("a.rs", Some("module"), "const A: u8 = 1;\n"),
+            file("a.rs", Some("module"), "const B: u8 = 2;\nconst C: u8 = 3;"),
+            file("b.rs", Some("module"), "const D: u8 = 4;\n"),
+        ];
+        let existing = |name: &str| {
+            (name == "b.rs").then(|| "// @@protoc_insertion_point(module)\n".to_owned())
+        };
+
+        let contents = apply_insertion_points(files, existing).unwrap();
+
+        assert_eq!(
+            contents["a.rs"],
+            "mod a {\n    const A: u8 = 1;\n    const B: u8 = 2;\n    const C: u8 = 3;\n    // \
+             @@protoc_insertion_point(module)\n}\n"
+        );
+        assert_eq!(
+            contents["b.rs"],
+            "const D: u8 = 4;\n// @@protoc_insertion_point(module)\n"
+        );
+    }
+
+    #[test]
+    fn inserting_into_a_missing_file_is_an_error() {
+        let error =
+            apply_insertion_points(vec![file("a.rs", Some("module"), "")], |_| None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tried to insert into `a.rs`, which does not exist"
+        );
+    }
+
+    #[test]
+    fn generates_from_a_descriptor_set() {
+        let dir = std::env::temp_dir().join(format!("protoc-gen-prost-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let set = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("greeter/v1/greeter.proto".to_owned()),
+                package: Some("greeter.v1".to_owned()),
+                message_type: vec![DescriptorProto {
+                    name: Some("Greeting".to_owned()),
+                    ..Default::default()
+                }],
+                syntax: Some("proto3".to_owned()),
+                ..Default::default()
+            }],
+        };
+        let descriptor_set = dir.join("api.binpb");
+        fs::write(&descriptor_set, set.encode_to_vec()).unwrap();
+
+        let out = dir.join("gen");
+        let args = [
+            format!("--descriptor-set-in={}", descriptor_set.display()),
+            "--files".to_owned(),
+            "greeter/v1/greeter.proto".to_owned(),
+            "--opt".to_owned(),
+            "file_descriptor_set=separate".to_owned(),
+            "--out".to_owned(),
+            out.display().to_string(),
+        ];
+        run_generate_command(args, crate::execute).unwrap();
+
+        let module = fs::read_to_string(out.join("greeter/v1/greeter.v1.rs")).unwrap();
+        assert!(module.contains("pub struct Greeting {\n}"));
+        assert!(module
+            .contains("include!(\"greeter.v1.fds.rs\");\n// @@protoc_insertion_point(module)\n"));
+        assert!(out.join("greeter/v1/greeter.v1.fds.rs").exists());
+
+        let error = run_generate_command(
+            [
+                format!("--descriptor-set-in={}", descriptor_set.display()),
+                "--files=greeter/v2/greeter.proto".to_owned(),
+                format!("--out={}", out.display()),
+            ],
+            crate::execute,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`greeter/v2/greeter.proto` is not in any descriptor set"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,558 @@
+//! Structured options files referenced by the `config=<path>` parameter
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::{InvalidParameter, Param, ParameterError, Params, PluginSpec};
+
+/// The name of the parameter used to reference an options file
+const CONFIG_PARAMETER: &str = "config";
+
+/// The name of the parameter used to enable the generation cache
+const CACHE_DIR_PARAMETER: &str = "cache_dir";
+
+/// The name of the parameter used to record requests for replay
+const DUMP_REQUEST_PARAMETER: &str = "dump_request";
+
+/// The name of the section whose options are shared by every plugin
+const SHARED_SECTION: &str = "default";
+
+/// Plugin options, combining any referenced options files with the inline options
+///
+/// Options are layered in the following order, with later options taking
+/// precedence over earlier ones for single-valued parameters and being
+/// appended for repeated parameters:
+///
+/// 1. The `[default]` section of each options file
+/// 2. The plugin's own section of each options file
+/// 3. Inline options passed to the plugin
+///
+/// A sample options file in TOML:
+///
+/// ```toml
+/// [default]
+/// extern_path = { ".google.protobuf" = "::pbjson_types" }
+/// btree_map = ["."]
+///
+/// [prost]
+/// file_descriptor_set = true
+/// type_attribute = { ".helloworld.v1.HelloWorld" = "#[derive(Eq, Hash)]" }
+///
+/// [tonic]
+/// no_server = true
+/// ```
+pub struct PluginOpts<'a> {
+    plugin: &'static PluginSpec,
+    shared: Vec<OwnedParam>,
+    specific: Vec<OwnedParam>,
+    inline: Params<'a>,
+    cache_dir: Option<PathBuf>,
+    dump_request: Option<PathBuf>,
+    /// Errors for the inline options that could not be parsed, reported
+    /// along with the options rejected by [`apply`](Self::apply)
+    malformed: Vec<ParameterError>,
+}
+
+impl<'a> PluginOpts<'a> {
+    /// Parses the inline options for a plugin, loading the sections relevant
+    /// to that plugin from any referenced options files
+    pub fn load(s: &'a str, plugin: &'static PluginSpec) -> Result<Self, InvalidParameter> {
+        Self::load_routed(s, plugin, |param| Ok(Some(param)))
+    }
+
+    /// Parses the inline options for one of several plugins run together
+    /// by a combined plugin
+    ///
+    /// An option prefixed with the section of a plugin, such as
+    /// `tonic.no_server`, only applies to that plugin. An option without a
+    /// prefix applies to every plugin that accepts it, and is rejected if
+    /// none of `plugins` do, as are options in the shared `[default]`
+    /// section of any options files.
+    pub fn load_namespaced(
+        s: &'a str,
+        plugin: &'static PluginSpec,
+        plugins: &[&'static PluginSpec],
+    ) -> Result<Self, InvalidParameter> {
+        let opts = Self::load_routed(s, plugin, |param| match param.name().split_once('.') {
+            Some((section, name)) if section == plugin.section => Ok(Some(param.with_name(name))),
+            Some((section, _)) if plugins.iter().any(|p| p.section == section) => Ok(None),
+            Some(_) => Err(ParameterError::unknown(param, None)),
+            None if plugin.accepts(param.name()) => Ok(Some(param)),
+            None if plugins.iter().any(|p| p.accepts(param.name())) => Ok(None),
+            None => Err(unknown_to_all(param, plugins)),
+        })?;
+
+        let errors: Vec<_> = opts
+            .shared_params()
+            .filter(|param| !plugins.iter().any(|p| p.accepts(param.name())))
+            .map(|param| unknown_to_all(param, plugins))
+            .collect();
+        if errors.is_empty() {
+            Ok(opts)
+        } else {
+            Err(plugin.error(errors))
+        }
+    }
+
+    /// Parses the inline options, keeping those that `route` returns
+    fn load_routed<F>(
+        s: &'a str,
+        plugin: &'static PluginSpec,
+        route: F,
+    ) -> Result<Self, InvalidParameter>
+    where
+        F: Fn(Param<'a>) -> Result<Option<Param<'a>>, ParameterError>,
+    {
+        let mut shared = Vec::new();
+        let mut specific = Vec::new();
+        let mut inline = Vec::new();
+        let mut inline_cache_dir = None;
+        let mut inline_dump_request = None;
+        let (params, malformed) = Params::parse(s);
+        let mut errors = Vec::new();
+        for param in params {
+            match param {
+                Param::Value {
+                    param: CONFIG_PARAMETER,
+                    value: path,
+                } => {
+                    let mut file = ConfigFile::load(param.clone(), Path::new(path))
+                        .map_err(|e| plugin.error(vec![e]))?;
+                    if let Some(section) = file.sections.shift_remove(SHARED_SECTION) {
+                        shared.extend(section.into_params(param.clone(), plugin)?);
+                    }
+                    if let Some(section) = file.sections.shift_remove(plugin.section) {
+                        specific.extend(section.into_params(param.clone(), plugin)?);
+                    }
+                }
+                Param::Value {
+                    param: CACHE_DIR_PARAMETER,
+                    value: path,
+                } => inline_cache_dir = Some(PathBuf::from(path)),
+                Param::Value {
+                    param: DUMP_REQUEST_PARAMETER,
+                    value: path,
+                } => inline_dump_request = Some(PathBuf::from(path)),
+                param => match route(param) {
+                    Ok(Some(param)) => inline.push(param),
+                    Ok(None) => {}
+                    Err(error) => errors.push(error),
+                },
+            }
+        }
+        if !errors.is_empty() {
+            return Err(plugin.error(malformed.into_iter().chain(errors).collect()));
+        }
+
+        let shared_cache_dir = take_path(&mut shared, CACHE_DIR_PARAMETER);
+        let specific_cache_dir = take_path(&mut specific, CACHE_DIR_PARAMETER);
+        let cache_dir = inline_cache_dir.or(specific_cache_dir).or(shared_cache_dir);
+
+        let shared_dump_request = take_path(&mut shared, DUMP_REQUEST_PARAMETER);
+        let specific_dump_request = take_path(&mut specific, DUMP_REQUEST_PARAMETER);
+        let dump_request = inline_dump_request
+            .or(specific_dump_request)
+            .or(shared_dump_request);
+
+        Ok(Self {
+            plugin,
+            shared,
+            specific,
+            inline: Params { params: inline },
+            cache_dir,
+            dump_request,
+            malformed,
+        })
+    }
+
+    /// The plugin that these options are for
+    pub fn plugin(&self) -> &'static PluginSpec {
+        self.plugin
+    }
+
+    /// The directory in which generated files are cached, if enabled with
+    /// `cache_dir=<path>`
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    /// The path to which the request is written, if enabled with
+    /// `dump_request=<path>`
+    pub fn dump_request(&self) -> Option<&Path> {
+        self.dump_request.as_deref()
+    }
+
+    /// Options from the shared `[default]` section of any options files
+    ///
+    /// Not every plugin understands every option, so plugins should ignore
+    /// shared options that they do not recognize.
+    pub fn shared_params(&self) -> impl Iterator<Item = Param<'_>> {
+        self.shared.iter().map(OwnedParam::as_param)
+    }
+
+    /// Options from the plugin's own section of any options files, followed
+    /// by the inline options
+    pub fn params(&self) -> impl Iterator<Item = Param<'_>> {
+        self.specific
+            .iter()
+            .map(OwnedParam::as_param)
+            .chain(self.inline.params.iter().cloned())
+    }
+
+    /// Applies every option to `target` in order, collecting the errors for
+    /// any options that `handle` rejects
+    ///
+    /// Shared options that the plugin does not recognize are meant for other
+    /// plugins, so `handle` rejecting them is ignored.
+    pub fn apply<T, F>(&self, target: &mut T, mut handle: F) -> Result<(), InvalidParameter>
+    where
+        F: FnMut(&mut T, Param<'_>) -> Result<(), ParameterError>,
+    {
+        let mut errors = self.malformed.clone();
+        for param in self.shared_params() {
+            let recognized = self.plugin.accepts(param.name());
+            match handle(target, param) {
+                Err(error) if recognized => errors.push(error),
+                _ => {}
+            }
+        }
+        errors.extend(
+            self.params()
+                .filter_map(|param| handle(target, param).err()),
+        );
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.plugin.error(errors))
+        }
+    }
+}
+
+/// Reports that none of `plugins` accept `param`, suggesting the closest
+/// parameter that one of them does
+fn unknown_to_all(param: Param<'_>, plugins: &[&'static PluginSpec]) -> ParameterError {
+    let suggestion = plugins
+        .iter()
+        .filter_map(|p| p.closest(param.name()))
+        .min()
+        .map(|(_, name)| name);
+    ParameterError::unknown(param, suggestion)
+}
+
+/// Removes the options named `name` read from an options file, returning
+/// the path given by the last one
+fn take_path(params: &mut Vec<OwnedParam>, name: &str) -> Option<PathBuf> {
+    let mut path = None;
+    params.retain(|param| match param {
+        OwnedParam::Value { param, value } if param == name => {
+            path = Some(PathBuf::from(value));
+            false
+        }
+        _ => true,
+    });
+    path
+}
+
+/// A parameter read from an options file
+#[derive(Debug)]
+enum OwnedParam {
+    Parameter {
+        param: String,
+    },
+    Value {
+        param: String,
+        value: String,
+    },
+    KeyValue {
+        param: String,
+        key: String,
+        value: String,
+    },
+}
+
+impl OwnedParam {
+    fn as_param(&self) -> Param<'_> {
+        match self {
+            Self::Parameter { param } => Param::Parameter { param },
+            Self::Value { param, value } => Param::Value { param, value },
+            Self::KeyValue { param, key, value } => Param::KeyValue {
+                param,
+                key,
+                value: value.clone(),
+            },
+        }
+    }
+}
+
+/// The contents of an options file, keyed by section name
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+struct ConfigFile {
+    sections: IndexMap<String, Section>,
+}
+
+impl ConfigFile {
+    fn load(param: Param<'_>, path: &Path) -> Result<Self, ParameterError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => return Err(ParameterError::invalid_value(param, e.to_string())),
+        };
+
+        let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(|e| e.to_string()),
+            Some("yaml" | "yml") => serde_yaml_ng::from_str(&contents).map_err(|e| e.to_string()),
+            _ => Err(
+                "unrecognized options file format, expected `.toml`, `.yaml`, or `.yml`".to_owned(),
+            ),
+        };
+
+        parsed.map_err(|reason| ParameterError::invalid_value(param, reason))
+    }
+}
+
+/// The options in one section of an options file
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+struct Section {
+    options: IndexMap<String, OptionValue>,
+}
+
+impl Section {
+    fn into_params(
+        self,
+        config: Param<'_>,
+        plugin: &PluginSpec,
+    ) -> Result<Vec<OwnedParam>, InvalidParameter> {
+        let mut params = Vec::new();
+        for (param, value) in self.options {
+            if param == CONFIG_PARAMETER {
+                return Err(plugin.error(vec![ParameterError::invalid_value(
+                    config,
+                    "options files cannot reference other options files",
+                )]));
+            }
+
+            match value {
+                OptionValue::Flag(true) => params.push(OwnedParam::Parameter { param }),
+                OptionValue::Flag(false) => params.push(OwnedParam::Value {
+                    param,
+                    value: "false".to_owned(),
+                }),
+                OptionValue::Integer(value) => params.push(OwnedParam::Value {
+                    param,
+                    value: value.to_string(),
+                }),
+                OptionValue::Float(value) => params.push(OwnedParam::Value {
+                    param,
+                    value: value.to_string(),
+                }),
+                OptionValue::Value(value) => params.push(OwnedParam::Value { param, value }),
+                OptionValue::Values(values) => {
+                    params.extend(values.into_iter().map(|value| OwnedParam::Value {
+                        param: param.clone(),
+                        value,
+                    }))
+                }
+                OptionValue::KeyValues(entries) => {
+                    for (key, values) in entries {
+                        params.extend(values.into_vec().into_iter().map(|value| {
+                            OwnedParam::KeyValue {
+                                param: param.clone(),
+                                key: key.clone(),
+                                value,
+                            }
+                        }))
+                    }
+                }
+            }
+        }
+
+        Ok(params)
+    }
+}
+
+/// The value of an option in an options file
+///
+/// * `flag = true` is equivalent to the inline `flag`
+/// * `param = "value"` is equivalent to the inline `param=value`
+/// * `param = 4` is equivalent to the inline `param=4`
+/// * `param = ["a", "b"]` is equivalent to the inline `param=a,param=b`
+/// * `param = { key = "value" }` is equivalent to the inline `param=key=value`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OptionValue {
+    Flag(bool),
+    Integer(i64),
+    Float(f64),
+    Value(String),
+    Values(Vec<String>),
+    KeyValues(IndexMap<String, OneOrMany>),
+}
+
+/// One or more values associated with the key of a key-value option
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Self::One(value) => vec![value],
+            Self::Many(values) => values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUGIN: PluginSpec = PluginSpec {
+        name: "protoc-gen-test",
+        section: "prost",
+        params: &[],
+    };
+
+    #[test]
+    fn options_file_sections_are_layered_under_inline_options() {
+        let dir = std::env::temp_dir().join(format!("protoc-gen-prost-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prost-gen.toml");
+        fs::write(
+            &path,
+            r##"
+[default]
+extern_path = { ".google.protobuf" = "::pbjson_types" }
+btree_map = ["."]
+
+[prost]
+type_attribute = { ".a.B" = ["#[derive(Eq, Hash)]", "#[derive(Copy)]"] }
+flat_output_dir = false
+
+[tonic]
+no_server = true
+"##,
+        )
+        .unwrap();
+
+        let inline = format!("config={},flat_output_dir", path.display());
+        let opts = PluginOpts::load(&inline, &PLUGIN).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let shared: Vec<_> = opts.shared_params().collect();
+        assert_eq!(
+            shared,
+            [
+                Param::KeyValue {
+                    param: "extern_path",
+                    key: ".google.protobuf",
+                    value: "::pbjson_types".into(),
+                },
+                Param::Value {
+                    param: "btree_map",
+                    value: ".",
+                },
+            ]
+        );
+
+        let params: Vec<_> = opts.params().collect();
+        assert_eq!(
+            params,
+            [
+                Param::KeyValue {
+                    param: "type_attribute",
+                    key: ".a.B",
+                    value: "#[derive(Eq, Hash)]".into(),
+                },
+                Param::KeyValue {
+                    param: "type_attribute",
+                    key: ".a.B",
+                    value: "#[derive(Copy)]".into(),
+                },
+                Param::Value {
+                    param: "flat_output_dir",
+                    value: "false",
+                },
+                Param::Parameter {
+                    param: "flat_output_dir",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn numeric_options_are_passed_as_their_inline_form() {
+        let dir =
+            std::env::temp_dir().join(format!("protoc-gen-prost-numeric-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prost-gen.toml");
+        fs::write(&path, "[prost]\njobs = 4\nratio = 0.5\n").unwrap();
+
+        let inline = format!("config={}", path.display());
+        let opts = PluginOpts::load(&inline, &PLUGIN).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let params: Vec<_> = opts.params().collect();
+        assert_eq!(
+            params,
+            [
+                Param::Value {
+                    param: "jobs",
+                    value: "4",
+                },
+                Param::Value {
+                    param: "ratio",
+                    value: "0.5",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_shared_options_are_reported_if_recognized() {
+        const FLAG_PLUGIN: PluginSpec = PluginSpec {
+            name: "protoc-gen-test",
+            section: "prost",
+            params: &[crate::ParamSpec::new("flag", "flag(=<boolean>)")],
+        };
+
+        let dir =
+            std::env::temp_dir().join(format!("protoc-gen-prost-shared-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prost-gen.yaml");
+        fs::write(&path, "default:\n  flag: maybe\n  no_server: true\n").unwrap();
+
+        let inline = format!("config={}", path.display());
+        let opts = PluginOpts::load(&inline, &FLAG_PLUGIN).unwrap();
+        let error = opts
+            .apply(&mut false, |flag, param| match param {
+                Param::Parameter { param: "flag" } => {
+                    *flag = true;
+                    Ok(())
+                }
+                param => Err(FLAG_PLUGIN.reject(param)),
+            })
+            .unwrap_err();
+        assert_eq!(error.errors().len(), 1, "{error}");
+        assert!(error.to_string().contains("`flag=maybe`"), "{error}");
+
+        let error = PluginOpts::load_namespaced(&inline, &FLAG_PLUGIN, &[&FLAG_PLUGIN])
+            .err()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(error.errors().len(), 1, "{error}");
+        assert!(
+            error.to_string().contains("`no_server`: unknown parameter"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Diagnostics for parameters that a plugin could not accept
+
+use std::fmt;
+
+use crate::Param;
+
+/// A description of a plugin and the parameters that it accepts
+#[derive(Debug)]
+pub struct PluginSpec {
+    /// The name of the plugin binary, used when reporting errors
+    pub name: &'static str,
+
+    /// The name of the section read by this plugin in an options file
+    pub section: &'static str,
+
+    /// The parameters accepted by this plugin
+    pub params: &'static [ParamSpec],
+}
+
+/// A description of a single parameter accepted by a plugin
+#[derive(Debug)]
+pub struct ParamSpec {
+    /// The name of the parameter
+    pub name: &'static str,
+
+    /// The accepted forms of the parameter, as documented for the plugin
+    ///
+    /// For example, `retain_enum_prefix(=<boolean>)` or
+    /// `extern_path=<proto_path>=<rust_path>`.
+    pub usage: &'static str,
+}
+
+impl ParamSpec {
+    pub const fn new(name: &'static str, usage: &'static str) -> Self {
+        Self { name, usage }
+    }
+}
+
+/// Parameters that are understood by every plugin
+const COMMON_PARAMS: &[ParamSpec] = &[
+    ParamSpec::new("cache_dir", "cache_dir=<path>"),
+    ParamSpec::new("config", "config=<path>"),
+    ParamSpec::new("dump_request", "dump_request=<path>"),
+];
+
+impl PluginSpec {
+    /// Explains why the plugin rejected a parameter
+    ///
+    /// If the parameter name is known to the plugin, then the value is
+    /// reported as invalid. Otherwise, the closest known parameter name is
+    /// suggested.
+    pub fn reject(&self, param: Param<'_>) -> ParameterError {
+        let name = param.name();
+        match self.all_params().find(|spec| spec.name == name) {
+            Some(spec) => {
+                ParameterError::invalid_value(param, format!("expected `{}`", spec.usage))
+            }
+            None => {
+                let suggestion = self.closest(name).map(|(_, name)| name);
+                ParameterError::unknown(param, suggestion)
+            }
+        }
+    }
+
+    /// Whether the plugin accepts a parameter with the given name
+    pub fn accepts(&self, name: &str) -> bool {
+        self.all_params().any(|spec| spec.name == name)
+    }
+
+    /// The known parameter name closest to `name`, along with its distance,
+    /// if any is close enough to suggest
+    pub(crate) fn closest(&self, name: &str) -> Option<(usize, &'static str)> {
+        self.all_params()
+            .map(|spec| (edit_distance(name, spec.name), spec.name))
+            .filter(|&(distance, _)| distance <= name.len().max(3) / 3)
+            .min_by_key(|&(distance, _)| distance)
+    }
+
+    /// Creates an error reporting that this plugin rejected the given parameters
+    pub fn error(&self, errors: Vec<ParameterError>) -> InvalidParameter {
+        InvalidParameter {
+            plugin: self.name,
+            errors,
+        }
+    }
+
+    fn all_params(&self) -> impl Iterator<Item = &'static ParamSpec> {
+        self.params.iter().chain(COMMON_PARAMS)
+    }
+}
+
+/// One or more parameters that a plugin could not accept
+#[derive(Debug)]
+pub struct InvalidParameter {
+    plugin: &'static str,
+    errors: Vec<ParameterError>,
+}
+
+impl InvalidParameter {
+    /// The name of the plugin that rejected the parameters
+    pub fn plugin(&self) -> &str {
+        self.plugin
+    }
+
+    /// The reasons that each parameter was rejected
+    pub fn errors(&self) -> &[ParameterError] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for InvalidParameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.errors.as_slice() {
+            [error] => write!(f, "{}: invalid parameter {error}", self.plugin),
+            errors => {
+                write!(f, "{}: {} invalid parameters", self.plugin, errors.len())?;
+                for error in errors {
+                    write!(f, "\n  * {error}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidParameter {}
+
+/// A single parameter that a plugin could not accept
+#[derive(Clone, Debug)]
+pub struct ParameterError {
+    param: String,
+    kind: ParameterErrorKind,
+}
+
+/// The reason that a parameter could not be accepted
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ParameterErrorKind {
+    /// The parameter could not be parsed
+    Malformed,
+
+    /// The parameter is not known to the plugin
+    Unknown {
+        /// The closest known parameter name, if any is close enough
+        suggestion: Option<&'static str>,
+    },
+
+    /// The parameter is known to the plugin, but its value is not valid
+    InvalidValue {
+        /// Why the value is not valid
+        reason: String,
+    },
+}
+
+impl ParameterError {
+    /// Reports that the value given for a known parameter is not valid
+    pub fn invalid_value(param: Param<'_>, reason: impl Into<String>) -> Self {
+        Self {
+            param: param.to_string(),
+            kind: ParameterErrorKind::InvalidValue {
+                reason: reason.into(),
+            },
+        }
+    }
+
+    pub(crate) fn unknown(param: Param<'_>, suggestion: Option<&'static str>) -> Self {
+        Self {
+            param: param.to_string(),
+            kind: ParameterErrorKind::Unknown { suggestion },
+        }
+    }
+
+    pub(crate) fn malformed(param: &str) -> Self {
+        Self {
+            param: param.to_owned(),
+            kind: ParameterErrorKind::Malformed,
+        }
+    }
+
+    /// The parameter, as it was given to the plugin
+    pub fn param(&self) -> &str {
+        &self.param
+    }
+
+    /// The reason that the parameter could not be accepted
+    pub fn kind(&self) -> &ParameterErrorKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}`: ", self.param)?;
+        match &self.kind {
+            ParameterErrorKind::Malformed => f.write_str("malformed parameter"),
+            ParameterErrorKind::Unknown {
+                suggestion: Some(suggestion),
+            } => write!(f, "unknown parameter, did you mean `{suggestion}`?"),
+            ParameterErrorKind::Unknown { suggestion: None } => f.write_str("unknown parameter"),
+            ParameterErrorKind::InvalidValue { reason } => f.write_str(reason),
+        }
+    }
+}
+
+/// The Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUGIN: PluginSpec = PluginSpec {
+        name: "protoc-gen-test",
+        section: "test",
+        params: &[
+            ParamSpec::new("retain_enum_prefix", "retain_enum_prefix(=<boolean>)"),
+            ParamSpec::new("bytes", "bytes=<proto_path>"),
+        ],
+    };
+
+    #[test]
+    fn rejected_parameters_are_explained() {
+        let error = PLUGIN.error(vec![
+            PLUGIN.reject(Param::Parameter {
+                param: "retain_enum_prefx",
+            }),
+            PLUGIN.reject(Param::Value {
+                param: "retain_enum_prefix",
+                value: "yes",
+            }),
+            PLUGIN.reject(Param::Value {
+                param: "confg",
+                value: "prost-gen.toml",
+            }),
+            PLUGIN.reject(Param::Parameter {
+                param: "something_else",
+            }),
+        ]);
+
+        assert_eq!(
+            error.to_string(),
+            "protoc-gen-test: 4 invalid parameters\n  * `retain_enum_prefx`: unknown parameter, \
+             did you mean `retain_enum_prefix`?\n  * `retain_enum_prefix=yes`: expected \
+             `retain_enum_prefix(=<boolean>)`\n  * `confg=prost-gen.toml`: unknown parameter, did \
+             you mean `config`?\n  * `something_else`: unknown parameter"
+        );
+    }
+}
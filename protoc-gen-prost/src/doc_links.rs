@@ -0,0 +1,355 @@
+//! Rewriting references to protobuf types in comments into rustdoc
+//! intra-doc links
+
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use prost_build::Module;
+use prost_types::{DescriptorProto, FileDescriptorProto};
+use regex::{Captures, Regex};
+
+use crate::{ModuleRequestSet, Resolver};
+
+/// A reference to a type, either as `[foo.v1.Bar]` or as `` `Bar` ``
+static REFERENCE: Lazy<Regex> = Lazy::new(|| {
+    let name = r"\.?[A-Za-z_][A-Za-z0-9_]*(?:\.[A-Za-z_][A-Za-z0-9_]*)*";
+    Regex::new(&format!(r"\[(?P<bracket>{name})\]|`(?P<code>{name})`")).unwrap()
+});
+
+/// Rewrites the comments of a request for rustdoc
+///
+/// References to the messages and enums of the request are turned into
+/// intra-doc links to the generated types, resolved as [`Resolver`] does.
+/// Indented code blocks, which rustdoc would otherwise run as doctests, are
+/// turned into fenced `text` blocks.
+#[derive(Debug)]
+pub struct DocLinks<'a> {
+    resolver: &'a Resolver,
+    types: HashSet<String>,
+}
+
+impl<'a> DocLinks<'a> {
+    /// Collects the messages and enums of every file in `module_request_set`
+    pub fn new(module_request_set: &ModuleRequestSet, resolver: &'a Resolver) -> Self {
+        let mut types = HashSet::new();
+        for (_, request) in module_request_set.requests() {
+            for file in request.files() {
+                let scope = package_scope(file);
+                collect_types(&scope, &file.message_type, &mut types);
+                for enumeration in &file.enum_type {
+                    types.insert(format!("{scope}.{}", enumeration.name()));
+                }
+            }
+        }
+
+        Self { resolver, types }
+    }
+
+    /// Rewrites a comment on an item within `scope`, such as `.foo.v1.Bar`,
+    /// whose documentation is generated `depth` modules below `from`
+    pub fn rewrite(&self, comment: &str, from: &Module, scope: &str, depth: usize) -> String {
+        let mut rewritten = String::with_capacity(comment.len());
+        let mut fenced = false;
+        let mut indented = false;
+        let mut list = false;
+        let mut previous_blank = true;
+        let mut blanks = 0;
+
+        for line in comment.lines() {
+            // `protoc` keeps the space that follows `//`
+            let body = line.strip_prefix(' ').unwrap_or(line);
+            // Only spaces indent code blocks, as in CommonMark
+            let indent = body.len() - body.trim_start_matches(' ').len();
+            let blank = body.trim().is_empty();
+
+            if indented {
+                if blank {
+                    blanks += 1;
+                    continue;
+                }
+                if indent >= 4 {
+                    push_blanks(&mut rewritten, &mut blanks);
+                    rewritten.push(' ');
+                    rewritten.push_str(&body[4..]);
+                    rewritten.push('\n');
+                    continue;
+                }
+                rewritten.push_str(" ```\n");
+                indented = false;
+            }
+            push_blanks(&mut rewritten, &mut blanks);
+
+            if body.trim_start().starts_with("```") || body.trim_start().starts_with("~~~") {
+                fenced = !fenced;
+                rewritten.push_str(line);
+            } else if fenced {
+                rewritten.push_str(line);
+            } else if indent >= 4 && previous_blank && !list {
+                indented = true;
+                rewritten.push_str(" ```text\n ");
+                rewritten.push_str(&body[4..]);
+            } else {
+                rewritten.push_str(&self.link(line, from, scope, depth));
+            }
+            rewritten.push('\n');
+
+            if is_list_item(body) {
+                list = true;
+            } else if indent == 0 && !blank {
+                list = false;
+            }
+            previous_blank = blank;
+        }
+
+        if indented {
+            rewritten.push_str(" ```\n");
+        }
+        push_blanks(&mut rewritten, &mut blanks);
+        if !comment.ends_with('\n') && rewritten.ends_with('\n') {
+            rewritten.pop();
+        }
+
+        rewritten
+    }
+
+    /// Rewrites the comments of the messages and enums of `file`, as _Prost!_
+    /// documents them
+    pub(crate) fn link_file(&self, file: &mut FileDescriptorProto) {
+        let mut source_code_info = match file.source_code_info.take() {
+            Some(source_code_info) => source_code_info,
+            None => return,
+        };
+
+        let from = Module::from_protobuf_package_name(file.package());
+        for location in &mut source_code_info.location {
+            if let Some((scope, depth)) = placement(file, &location.path) {
+                for comment in [
+                    &mut location.leading_comments,
+                    &mut location.trailing_comments,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    *comment = self.rewrite(comment, &from, &scope, depth);
+                }
+            }
+        }
+
+        file.source_code_info = Some(source_code_info);
+    }
+
+    fn link(&self, line: &str, from: &Module, scope: &str, depth: usize) -> String {
+        REFERENCE
+            .replace_all(line, |captures: &Captures<'_>| {
+                let reference = captures.get(0).unwrap();
+                let before = line[..reference.start()].chars().last();
+                let after = line[reference.end()..].chars().next();
+                let link = match (captures.name("bracket"), captures.name("code")) {
+                    // Already a link, or escaped
+                    (Some(_), _) if matches!(before, Some('\\' | ']')) => None,
+                    (Some(_), _) if matches!(after, Some('(' | '[')) => None,
+                    (Some(name), _) => Some((name.as_str(), name.as_str())),
+                    (_, Some(_)) if before == Some('[') => None,
+                    (_, Some(name)) => Some((name.as_str(), reference.as_str())),
+                    _ => None,
+                };
+
+                link.and_then(|(name, text)| {
+                    let path = self.path(name, from, scope, depth)?;
+                    Some(format!("[{text}]({path})"))
+                })
+                .unwrap_or_else(|| reference.as_str().to_owned())
+            })
+            .into_owned()
+    }
+
+    /// The Rust path to the type `name` from an item within `scope`, looked
+    /// up as `protoc` resolves relative names
+    fn path(&self, name: &str, from: &Module, scope: &str, depth: usize) -> Option<String> {
+        let fq_name = if name.starts_with('.') {
+            Some(name.to_owned()).filter(|name| self.types.contains(name))
+        } else {
+            let mut scope = scope;
+            loop {
+                let candidate = format!("{scope}.{name}");
+                if self.types.contains(&candidate) {
+                    break Some(candidate);
+                }
+                match scope.rsplit_once('.') {
+                    Some((parent, _)) => scope = parent,
+                    None => break None,
+                }
+            }
+        }?;
+
+        let path = self.resolver.resolve_ident(from, &fq_name);
+        let is_path = path
+            .split("::")
+            .enumerate()
+            .all(|(index, part)| (index == 0 && part.is_empty()) || is_ident(part));
+        if !is_path {
+            // Such as `()` for `google.protobuf.Empty`
+            return None;
+        }
+
+        if path.starts_with("::") || path.starts_with("crate::") {
+            Some(path)
+        } else {
+            Some("super::".repeat(depth) + &path)
+        }
+    }
+}
+
+/// The scope of the item documented at `path` in `file`, and how many
+/// modules below the package module its documentation is generated
+///
+/// The comment on a oneof documents both the field of the message and the
+/// enum in its nested module, and is linked from the field.
+fn placement(file: &FileDescriptorProto, path: &[i32]) -> Option<(String, usize)> {
+    let scope = package_scope(file);
+    match path {
+        [4, index, rest @ ..] => {
+            let message = file.message_type.get(usize::try_from(*index).ok()?)?;
+            message_placement(message, &scope, 0, rest)
+        }
+        [5, ..] => Some((scope, 0)),
+        _ => None,
+    }
+}
+
+fn message_placement(
+    message: &DescriptorProto,
+    parent: &str,
+    depth: usize,
+    path: &[i32],
+) -> Option<(String, usize)> {
+    let scope = format!("{parent}.{}", message.name());
+    match path {
+        [] | [8, _] => Some((scope, depth)),
+        [2, index] => {
+            let field = message.field.get(usize::try_from(*index).ok()?)?;
+            let in_oneof = field.oneof_index.is_some() && !field.proto3_optional();
+            Some((scope, depth + usize::from(in_oneof)))
+        }
+        [3, index, rest @ ..] => {
+            let nested = message.nested_type.get(usize::try_from(*index).ok()?)?;
+            message_placement(nested, &scope, depth + 1, rest)
+        }
+        [4, ..] => Some((scope, depth + 1)),
+        _ => None,
+    }
+}
+
+fn package_scope(file: &FileDescriptorProto) -> String {
+    match file.package() {
+        "" => String::new(),
+        package => format!(".{package}"),
+    }
+}
+
+fn collect_types(scope: &str, messages: &[DescriptorProto], types: &mut HashSet<String>) {
+    for message in messages {
+        let name = format!("{scope}.{}", message.name());
+        collect_types(&name, &message.nested_type, types);
+        for enumeration in &message.enum_type {
+            types.insert(format!("{name}.{}", enumeration.name()));
+        }
+        types.insert(name);
+    }
+}
+
+fn push_blanks(rewritten: &mut String, blanks: &mut usize) {
+    for _ in 0..*blanks {
+        rewritten.push('\n');
+    }
+    *blanks = 0;
+}
+
+fn is_list_item(body: &str) -> bool {
+    let item = body.trim_start();
+    item.starts_with("- ")
+        || item.starts_with("* ")
+        || item.starts_with("+ ")
+        || item.split_once(". ").is_some_and(|(number, _)| {
+            !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+        })
+}
+
+fn is_ident(part: &str) -> bool {
+    let part = part.strip_prefix("r#").unwrap_or(part);
+    part.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file, request, FieldType};
+
+    fn rewrite(comment: &str, scope: &str, depth: usize) -> String {
+        let module_request_set = request()
+            .file(
+                file("foo/v1/bar.proto")
+                    .package("foo.v1")
+                    .message("Bar", |m| {
+                        m.message("Baz", |m| m.field("id", FieldType::Int64, 1))
+                    })
+                    .enumeration("Kind", |e| e.value("KIND_UNSPECIFIED", 0)),
+            )
+            .file(
+                file("foo/v2/qux.proto")
+                    .package("foo.v2")
+                    .message("Qux", |m| m),
+            )
+            .module_request_set();
+        let resolver = Resolver::new(Vec::new(), false);
+        let doc_links = DocLinks::new(&module_request_set, &resolver);
+        let from = Module::from_protobuf_package_name("foo.v1");
+        doc_links.rewrite(comment, &from, scope, depth)
+    }
+
+    #[test]
+    fn references_become_intra_doc_links() {
+        assert_eq!(
+            rewrite(
+                " See [foo.v2.Qux], `Baz` and `Kind`, but not `id` or [Bar](https://x).\n",
+                ".foo.v1.Bar",
+                0
+            ),
+            " See [foo.v2.Qux](super::v2::Qux), [`Baz`](bar::Baz) and [`Kind`](Kind), but not \
+             `id` or [Bar](https://x).\n"
+        );
+        assert_eq!(
+            rewrite(" A [.foo.v1.Bar] or [`Bar`](x).", ".foo.v1.Bar.Baz", 1),
+            " A [.foo.v1.Bar](super::Bar) or [`Bar`](x)."
+        );
+    }
+
+    #[test]
+    fn indented_code_blocks_are_fenced() {
+        assert_eq!(
+            rewrite(
+                " Example:\n\n     let bar = Bar::new();\n\n     bar.run();\n\n Done.\n\n - a \
+                 list\n\n     continued\n",
+                ".foo.v1",
+                0
+            ),
+            " Example:\n\n ```text\n let bar = Bar::new();\n\n bar.run();\n ```\n\n Done.\n\n - a \
+             list\n\n     continued\n"
+        );
+        assert_eq!(
+            rewrite(" ```\n     `Bar`\n ```\n", ".foo.v1", 0),
+            " ```\n     `Bar`\n ```\n"
+        );
+    }
+
+    #[test]
+    fn other_whitespace_does_not_indent_code_blocks() {
+        assert_eq!(
+            rewrite(" Example:\n\n \u{3000}\u{3000}`Bar`\n", ".foo.v1", 0),
+            " Example:\n\n \u{3000}\u{3000}[`Bar`](Bar)\n"
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Resolution of Protobuf Editions features
+//!
+//! Neither `prost-build` nor `pbjson-build` understand files declared with
+//! `edition = "2023"`. Before these files are handed to any generator, the
+//! resolved features for each field are lowered into the equivalent
+//! `proto3` form:
+//!
+//! * `field_presence = EXPLICIT` marks singular fields as `proto3_optional`
+//! * `field_presence = LEGACY_REQUIRED` marks singular fields as `required`
+//! * `repeated_field_encoding = EXPANDED` marks repeated fields as `packed = false`
+//! * `message_encoding = DELIMITED` encodes message fields as groups
+//!
+//! _Prost!_ represents closed enums the same way as open enums and always
+//! validates UTF-8 in `string` fields, so `enum_type` and `utf8_validation`
+//! are resolved but require no lowering.
+//!
+//! The decoded descriptors from `prost-types` drop the `edition` and
+//! `features` fields, so these are read from the raw file descriptors using
+//! the wire-compatible types below.
+
+use std::collections::HashSet;
+
+use prost::Message;
+use prost_types::{
+    field_descriptor_proto::{Label, Type},
+    DescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+};
+
+use crate::Error;
+
+/// `EDITION_PROTO2`, the earliest edition supported by these plugins
+pub const MINIMUM_EDITION: i32 = EDITION_PROTO2;
+
+/// `EDITION_2023`, the latest edition supported by these plugins
+pub const MAXIMUM_EDITION: i32 = EDITION_2023;
+
+const EDITION_PROTO2: i32 = 998;
+const EDITION_2023: i32 = 1000;
+
+/// The `syntax` reported for files that declare an edition
+const EDITIONS_SYNTAX: &str = "editions";
+
+/// Lowers the features of an editions file into an equivalent `proto3` descriptor
+///
+/// Files which do not declare an edition are left unchanged.
+pub(crate) fn resolve(file: &mut FileDescriptorProto, raw: &[u8]) -> Result<(), Error> {
+    if file.syntax() != EDITIONS_SYNTAX {
+        return Ok(());
+    }
+
+    let raw = EditionsFile::decode(raw)?;
+    let defaults = match raw.edition {
+        Some(EDITION_2023) => FeatureSet::EDITION_2023,
+        _ => {
+            return Err(format!(
+                "{}: unsupported edition, editions up to 2023 are supported",
+                file.name(),
+            )
+            .into())
+        }
+    };
+
+    let features = defaults.merged(raw.options.and_then(|o| o.features).as_ref());
+
+    for (message, raw) in file.message_type.iter_mut().zip(&raw.message_type) {
+        resolve_message(message, raw, &features);
+    }
+    for (field, raw) in file.extension.iter_mut().zip(&raw.extension) {
+        resolve_field(field, raw, &features, false);
+    }
+
+    file.syntax = Some("proto3".to_owned());
+
+    Ok(())
+}
+
+fn resolve_message(message: &mut DescriptorProto, raw: &EditionsMessage, parent: &FeatureSet) {
+    let features = parent.merged(raw.options.as_ref().and_then(|o| o.features.as_ref()));
+
+    let map_entries: HashSet<String> = message
+        .nested_type
+        .iter()
+        .filter(|nested| nested.options.as_ref().is_some_and(|o| o.map_entry()))
+        .map(|nested| format!(".{}", nested.name()))
+        .collect();
+
+    for (field, raw_field) in message.field.iter_mut().zip(&raw.field) {
+        let is_map = field.label() == Label::Repeated
+            && map_entries
+                .iter()
+                .any(|suffix| field.type_name().ends_with(suffix.as_str()));
+
+        let oneof_features = field
+            .oneof_index
+            .and_then(|index| raw.oneof_decl.get(usize::try_from(index).ok()?))
+            .and_then(|oneof| oneof.options.as_ref())
+            .and_then(|o| o.features.as_ref());
+        let parent = features.merged(oneof_features);
+
+        resolve_field(field, raw_field, &parent, is_map);
+    }
+
+    for (field, raw) in message.extension.iter_mut().zip(&raw.extension) {
+        resolve_field(field, raw, &features, false);
+    }
+
+    for (nested, raw) in message.nested_type.iter_mut().zip(&raw.nested_type) {
+        resolve_message(nested, raw, &features);
+    }
+}
+
+fn resolve_field(
+    field: &mut FieldDescriptorProto,
+    raw: &EditionsField,
+    parent: &FeatureSet,
+    is_map: bool,
+) {
+    let features = parent.merged(raw.options.as_ref().and_then(|o| o.features.as_ref()));
+    let is_message = matches!(field.r#type(), Type::Message | Type::Group);
+    let delimited = features.message_encoding() == MessageEncoding::Delimited;
+
+    if field.label() == Label::Repeated {
+        if is_message {
+            if delimited && !is_map {
+                field.set_type(Type::Group);
+            }
+        } else if is_packable(field.r#type())
+            && features.repeated_field_encoding() == RepeatedFieldEncoding::Expanded
+        {
+            field.options.get_or_insert_with(Default::default).packed = Some(false);
+        }
+
+        return;
+    }
+
+    if is_message && delimited {
+        field.set_type(Type::Group);
+    }
+
+    // Members of a oneof always have explicit presence
+    if field.oneof_index.is_some() {
+        return;
+    }
+
+    match features.field_presence() {
+        FieldPresence::LegacyRequired => field.set_label(Label::Required),
+        // Message fields always have explicit presence in `proto3`, except
+        // for groups, which `prost-build` only treats as optional in `proto2`
+        FieldPresence::Explicit if !is_message || field.r#type() == Type::Group => {
+            field.proto3_optional = Some(true)
+        }
+        _ => (),
+    }
+}
+
+fn is_packable(ty: Type) -> bool {
+    !matches!(ty, Type::String | Type::Bytes | Type::Message | Type::Group)
+}
+
+/// Wire-compatible `FileDescriptorProto` retaining only the edition and features
+#[derive(Clone, PartialEq, Message)]
+struct EditionsFile {
+    #[prost(message, repeated, tag = "4")]
+    message_type: Vec<EditionsMessage>,
+    #[prost(message, repeated, tag = "7")]
+    extension: Vec<EditionsField>,
+    #[prost(message, optional, tag = "8")]
+    options: Option<FileOptions>,
+    #[prost(int32, optional, tag = "14")]
+    edition: Option<i32>,
+}
+
+/// Wire-compatible `DescriptorProto` retaining only features
+#[derive(Clone, PartialEq, Message)]
+struct EditionsMessage {
+    #[prost(message, repeated, tag = "2")]
+    field: Vec<EditionsField>,
+    #[prost(message, repeated, tag = "3")]
+    nested_type: Vec<EditionsMessage>,
+    #[prost(message, repeated, tag = "6")]
+    extension: Vec<EditionsField>,
+    #[prost(message, optional, tag = "7")]
+    options: Option<MessageOptions>,
+    #[prost(message, repeated, tag = "8")]
+    oneof_decl: Vec<EditionsOneof>,
+}
+
+/// Wire-compatible `FieldDescriptorProto` retaining only features
+#[derive(Clone, PartialEq, Message)]
+struct EditionsField {
+    #[prost(message, optional, tag = "8")]
+    options: Option<FieldOptions>,
+}
+
+/// Wire-compatible `OneofDescriptorProto` retaining only features
+#[derive(Clone, PartialEq, Message)]
+struct EditionsOneof {
+    #[prost(message, optional, tag = "2")]
+    options: Option<OneofOptions>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FileOptions {
+    #[prost(message, optional, tag = "50")]
+    features: Option<FeatureSet>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MessageOptions {
+    #[prost(message, optional, tag = "12")]
+    features: Option<FeatureSet>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FieldOptions {
+    #[prost(message, optional, tag = "21")]
+    features: Option<FeatureSet>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct OneofOptions {
+    #[prost(message, optional, tag = "1")]
+    features: Option<FeatureSet>,
+}
+
+/// Wire-compatible `google.protobuf.FeatureSet`
+#[derive(Clone, Copy, PartialEq, Message)]
+struct FeatureSet {
+    #[prost(enumeration = "FieldPresence", optional, tag = "1")]
+    field_presence: Option<i32>,
+    #[prost(enumeration = "EnumType", optional, tag = "2")]
+    enum_type: Option<i32>,
+    #[prost(enumeration = "RepeatedFieldEncoding", optional, tag = "3")]
+    repeated_field_encoding: Option<i32>,
+    #[prost(enumeration = "Utf8Validation", optional, tag = "4")]
+    utf8_validation: Option<i32>,
+    #[prost(enumeration = "MessageEncoding", optional, tag = "5")]
+    message_encoding: Option<i32>,
+    #[prost(enumeration = "JsonFormat", optional, tag = "6")]
+    json_format: Option<i32>,
+}
+
+impl FeatureSet {
+    /// The default features for `edition = "2023"`
+    const EDITION_2023: Self = Self {
+        field_presence: Some(FieldPresence::Explicit as i32),
+        enum_type: Some(EnumType::Open as i32),
+        repeated_field_encoding: Some(RepeatedFieldEncoding::Packed as i32),
+        utf8_validation: Some(Utf8Validation::Verify as i32),
+        message_encoding: Some(MessageEncoding::LengthPrefixed as i32),
+        json_format: Some(JsonFormat::Allow as i32),
+    };
+
+    /// Resolves the features set in `overrides` on top of these features
+    fn merged(&self, overrides: Option<&Self>) -> Self {
+        let overrides = match overrides {
+            Some(overrides) => overrides,
+            None => return *self,
+        };
+
+        Self {
+            field_presence: overrides.field_presence.or(self.field_presence),
+            enum_type: overrides.enum_type.or(self.enum_type),
+            repeated_field_encoding: overrides
+                .repeated_field_encoding
+                .or(self.repeated_field_encoding),
+            utf8_validation: overrides.utf8_validation.or(self.utf8_validation),
+            message_encoding: overrides.message_encoding.or(self.message_encoding),
+            json_format: overrides.json_format.or(self.json_format),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+enum FieldPresence {
+    Unknown = 0,
+    Explicit = 1,
+    Implicit = 2,
+    LegacyRequired = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+enum EnumType {
+    Unknown = 0,
+    Open = 1,
+    Closed = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+enum RepeatedFieldEncoding {
+    Unknown = 0,
+    Packed = 1,
+    Expanded = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+enum Utf8Validation {
+    Unknown = 0,
+    Verify = 2,
+    None = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+enum MessageEncoding {
+    Unknown = 0,
+    LengthPrefixed = 1,
+    Delimited = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+enum JsonFormat {
+    Unknown = 0,
+    Allow = 1,
+    LegacyBestEffort = 2,
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{compiler::CodeGeneratorRequest, DescriptorProto};
+
+    use super::*;
+    use crate::RawProtos;
+
+    /// Wire-compatible `FileDescriptorProto` carrying pre-encoded messages
+    #[derive(Clone, PartialEq, Message)]
+    struct RawMessages {
+        #[prost(bytes = "vec", repeated, tag = "4")]
+        message_type: Vec<Vec<u8>>,
+    }
+
+    /// Wire-compatible `DescriptorProto` carrying pre-encoded fields
+    #[derive(Clone, PartialEq, Message)]
+    struct RawFields {
+        #[prost(bytes = "vec", repeated, tag = "2")]
+        field: Vec<Vec<u8>>,
+    }
+
+    fn field(name: &str, number: i32, label: Label, ty: Type) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            label: Some(label as i32),
+            r#type: Some(ty as i32),
+            ..Default::default()
+        }
+    }
+
+    fn with_features(field: FieldDescriptorProto, features: FeatureSet) -> Vec<u8> {
+        let mut encoded = field.encode_to_vec();
+        EditionsField {
+            options: Some(FieldOptions {
+                features: Some(features),
+            }),
+        }
+        .encode(&mut encoded)
+        .unwrap();
+        encoded
+    }
+
+    #[test]
+    fn editions_features_are_lowered_for_prost() {
+        let no_features = FeatureSet::default();
+
+        let mut inner = DescriptorProto {
+            name: Some("Inner".to_owned()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        RawFields {
+            field: vec![field("value", 1, Label::Optional, Type::Int32).encode_to_vec()],
+        }
+        .encode(&mut inner)
+        .unwrap();
+
+        let mut message = DescriptorProto {
+            name: Some("Example".to_owned()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let mut inner_field = field("inner", 6, Label::Optional, Type::Message);
+        inner_field.type_name = Some(".example.Inner".to_owned());
+        RawFields {
+            field: vec![
+                field("id", 1, Label::Optional, Type::Int64).encode_to_vec(),
+                with_features(
+                    field("name", 2, Label::Optional, Type::String),
+                    FeatureSet {
+                        field_presence: Some(FieldPresence::Implicit as i32),
+                        ..no_features
+                    },
+                ),
+                with_features(
+                    field("code", 3, Label::Optional, Type::Int32),
+                    FeatureSet {
+                        field_presence: Some(FieldPresence::LegacyRequired as i32),
+                        ..no_features
+                    },
+                ),
+                with_features(
+                    field("values", 4, Label::Repeated, Type::Int32),
+                    FeatureSet {
+                        repeated_field_encoding: Some(RepeatedFieldEncoding::Expanded as i32),
+                        ..no_features
+                    },
+                ),
+                field("packed", 5, Label::Repeated, Type::Int32).encode_to_vec(),
+                with_features(
+                    inner_field,
+                    FeatureSet {
+                        message_encoding: Some(MessageEncoding::Delimited as i32),
+                        ..no_features
+                    },
+                ),
+            ],
+        }
+        .encode(&mut message)
+        .unwrap();
+
+        let mut file = FileDescriptorProto {
+            name: Some("example.proto".to_owned()),
+            package: Some("example".to_owned()),
+            syntax: Some(EDITIONS_SYNTAX.to_owned()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        EditionsFile {
+            edition: Some(EDITION_2023),
+            ..Default::default()
+        }
+        .encode(&mut file)
+        .unwrap();
+        RawMessages {
+            message_type: vec![message, inner],
+        }
+        .encode(&mut file)
+        .unwrap();
+
+        let mut request = CodeGeneratorRequest {
+            file_to_generate: vec!["example.proto".to_owned()],
+            ..Default::default()
+        }
+        .encode_to_vec();
+        RawProtos {
+            proto_file: vec![file.into()],
+            ..Default::default()
+        }
+        .encode(&mut request)
+        .unwrap();
+
+        let files = crate::execute(&request).expect("code generation failed");
+        let example_rs = files
+            .iter()
+            .find(|f| f.name.as_deref() == Some("example/example.rs"))
+            .and_then(|f| f.content.as_deref())
+            .expect("example/example.rs not generated");
+
+        for expected in [
+            "#[prost(int64, optional, tag=\"1\")]\n    pub id: ::core::option::Option<i64>,",
+            "#[prost(string, tag=\"2\")]\n    pub name: ::prost::alloc::string::String,",
+            "#[prost(int32, required, tag=\"3\")]\n    pub code: i32,",
+            "#[prost(int32, repeated, packed=\"false\", tag=\"4\")]",
+            "#[prost(int32, repeated, tag=\"5\")]",
+            "#[prost(group, optional, tag=\"6\")]\n    pub inner: ::core::option::Option<Inner>,",
+            "#[prost(int32, optional, tag=\"1\")]\n    pub value: ::core::option::Option<i32>,",
+        ] {
+            assert!(
+                example_rs.contains(expected),
+                "expected `{expected}` in:\n{example_rs}"
+            );
+        }
+    }
+}
@@ -1,26 +1,49 @@
 //! Code generator modules
 
-use prost_types::compiler::{
-    code_generator_response::{Feature, File},
-    CodeGeneratorResponse,
-};
+use prost::Message;
+use prost_types::compiler::code_generator_response::{Feature, File};
 
-use crate::ModuleRequestSet;
+use crate::{ModuleRequestSet, MAXIMUM_EDITION, MINIMUM_EDITION};
 
 mod core;
+mod deprecations;
+mod descriptor_pool;
 mod file_descriptor_set;
+mod insertion_points;
+mod minimize;
 
-pub(crate) use self::{core::CoreProstGenerator, file_descriptor_set::FileDescriptorSetGenerator};
+pub use self::deprecations::{deprecation_note, has_deprecated_top_level_types};
+pub(crate) use self::{
+    core::{per_file_filename, CoreProstGenerator, OutputLayout},
+    descriptor_pool::DescriptorPoolGenerator,
+    file_descriptor_set::{Compression, FileDescriptorSetGenerator},
+    minimize::Minimize,
+};
 
 /// A code generation result
 pub type Result = std::result::Result<Vec<File>, Error>;
 /// A code generation error
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+/// `FEATURE_SUPPORTS_EDITIONS`, which is not yet known to `prost-types`
+const FEATURE_SUPPORTS_EDITIONS: u64 = 2;
+
+/// The features supported by all generators
+const SUPPORTED_FEATURES: u64 = Feature::Proto3Optional as u64 | FEATURE_SUPPORTS_EDITIONS;
+
 /// Extension function to assist in converting [`Result`] into a [`CodeGeneratorResponse`]
 pub trait GeneratorResultExt {
     /// Unwrap a [`Result`], producing the relevant [`CodeGeneratorResponse`]
+    /// including the range of supported editions
     fn unwrap_codegen_response(self) -> CodeGeneratorResponse;
+
+    /// Unwrap a [`Result`], producing the relevant encoded [`CodeGeneratorResponse`]
+    fn encode_codegen_response(self) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        self.unwrap_codegen_response().encode_to_vec()
+    }
 }
 
 impl GeneratorResultExt for Result {
@@ -28,8 +51,7 @@ impl GeneratorResultExt for Result {
         match self {
             Ok(file) => CodeGeneratorResponse {
                 file,
-                supported_features: Some(Feature::Proto3Optional as u64),
-                ..Default::default()
+                ..CodeGeneratorResponse::supported()
             },
             Err(error) => error_to_codegen_response(&*error),
         }
@@ -39,8 +61,45 @@ impl GeneratorResultExt for Result {
 fn error_to_codegen_response(error: &dyn std::error::Error) -> CodeGeneratorResponse {
     CodeGeneratorResponse {
         error: Some(error.to_string()),
-        supported_features: Some(Feature::Proto3Optional as u64),
-        ..Default::default()
+        ..CodeGeneratorResponse::supported()
+    }
+}
+
+/// The response of a plugin to `protoc`
+///
+/// Wire-compatible with [`prost_types::compiler::CodeGeneratorResponse`],
+/// along with the range of supported editions that `protoc` requires when a
+/// plugin advertises support for editions, which `prost-types` cannot yet
+/// represent.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CodeGeneratorResponse {
+    /// The error that prevented code generation, if any
+    #[prost(string, optional, tag = "1")]
+    pub error: Option<String>,
+    /// A bitwise-or of the supported [`Feature`]s
+    #[prost(uint64, optional, tag = "2")]
+    pub supported_features: Option<u64>,
+    /// The earliest supported edition
+    #[prost(int32, optional, tag = "3")]
+    pub minimum_edition: Option<i32>,
+    /// The latest supported edition
+    #[prost(int32, optional, tag = "4")]
+    pub maximum_edition: Option<i32>,
+    /// The generated files
+    #[prost(message, repeated, tag = "15")]
+    pub file: Vec<File>,
+}
+
+impl CodeGeneratorResponse {
+    /// An empty response advertising the features and editions supported by
+    /// all generators
+    fn supported() -> Self {
+        Self {
+            supported_features: Some(SUPPORTED_FEATURES),
+            minimum_edition: Some(MINIMUM_EDITION),
+            maximum_edition: Some(MAXIMUM_EDITION),
+            ..Default::default()
+        }
     }
 }
 
@@ -49,6 +108,30 @@ pub trait Generator {
     /// Generate one or more files based on the input request
     fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result;
 
+    /// Generate the same files as [`generate()`][Self::generate()], keeping
+    /// the output of each generator in a chain apart
+    ///
+    /// Used by [`GenerationCache`] to put files taken from the cache back in
+    /// the order that they would have been generated.
+    ///
+    /// [`GenerationCache`]: crate::GenerationCache
+    fn generate_parts(
+        &mut self,
+        module_request_set: &ModuleRequestSet,
+    ) -> std::result::Result<Vec<Vec<File>>, Error> {
+        Ok(vec![self.generate(module_request_set)?])
+    }
+
+    /// Whether the output for each module depends on every file in the
+    /// request, rather than only on the files that the module imports
+    ///
+    /// Used by [`GenerationCache`] to decide when cached output is stale.
+    ///
+    /// [`GenerationCache`]: crate::GenerationCache
+    fn depends_on_whole_request(&self) -> bool {
+        false
+    }
+
     /// Chain multiple generators together, returning their composite output
     fn chain<G>(self, next: G) -> ChainedGenerator<Self, G>
     where
@@ -80,6 +163,19 @@ where
         files.extend(self.generator2.generate(module_request_set)?);
         Ok(files)
     }
+
+    fn generate_parts(
+        &mut self,
+        module_request_set: &ModuleRequestSet,
+    ) -> std::result::Result<Vec<Vec<File>>, Error> {
+        let mut parts = self.generator1.generate_parts(module_request_set)?;
+        parts.extend(self.generator2.generate_parts(module_request_set)?);
+        Ok(parts)
+    }
+
+    fn depends_on_whole_request(&self) -> bool {
+        self.generator1.depends_on_whole_request() || self.generator2.depends_on_whole_request()
+    }
 }
 
 impl<G> Generator for Option<G>
@@ -93,4 +189,19 @@ where
             Ok(Vec::new())
         }
     }
+
+    fn generate_parts(
+        &mut self,
+        module_request_set: &ModuleRequestSet,
+    ) -> std::result::Result<Vec<Vec<File>>, Error> {
+        if let Some(slf) = self {
+            slf.generate_parts(module_request_set)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn depends_on_whole_request(&self) -> bool {
+        self.as_ref().is_some_and(G::depends_on_whole_request)
+    }
 }
@@ -1,58 +1,329 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    thread,
+};
 
 use prost_build::Module;
-use prost_types::compiler::code_generator_response::File;
+use prost_types::{compiler::code_generator_response::File, FileDescriptorProto};
 
-use crate::{Generator, ModuleRequestSet, Result};
+use super::{deprecations, insertion_points};
+use crate::{DocLinks, Error, Generator, ModuleRequest, ModuleRequestSet, Resolver, Result};
 
-pub struct CoreProstGenerator {
-    config: prost_build::Config,
+/// How the generated code for each module is split into files
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputLayout {
+    /// One file per protobuf package
+    #[default]
+    PerPackage,
+
+    /// One file per `.proto` file, included by a file for each protobuf package
+    PerFile,
+}
+
+/// Generates the _Prost!_ code for each module
+///
+/// Modules are independent of each other, so they are split between up to
+/// `jobs` threads. `prost_build::Config` cannot be shared between threads, so
+/// each thread builds its own using `config`.
+pub struct CoreProstGenerator<F> {
+    config: F,
+    layout: OutputLayout,
+    jobs: usize,
+    format: bool,
+    insertion_points: bool,
+    doc_links: Option<Resolver>,
+    strict: bool,
 }
 
-impl Generator for CoreProstGenerator {
+impl<F> Generator for CoreProstGenerator<F>
+where
+    F: Fn() -> prost_build::Config + Sync,
+{
     fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
-        let prost_requests: Vec<_> = module_request_set
-            .requests()
-            .flat_map(|(module, request)| {
-                request
-                    .files
+        let groups = self.partition(module_request_set);
+        let doc_links = self
+            .doc_links
+            .as_ref()
+            .map(|resolver| DocLinks::new(module_request_set, resolver));
+        let doc_links = doc_links.as_ref();
+        let mut file_contents = match groups.as_slice() {
+            [] => HashMap::new(),
+            [group] => self.generate_group(group, module_request_set, doc_links)?,
+            groups => thread::scope(|scope| {
+                let workers: Vec<_> = groups
                     .iter()
-                    .map(|proto| (module.clone(), proto.clone()))
+                    .map(|group| {
+                        scope.spawn(|| self.generate_group(group, module_request_set, doc_links))
+                    })
+                    .collect();
+
+                let mut file_contents = HashMap::new();
+                for worker in workers {
+                    let contents = worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+                    file_contents.extend(contents);
+                }
+                Ok::<_, Error>(file_contents)
+            })?,
+        };
+
+        let mut files = Vec::new();
+        for (module, request) in module_request_set.requests() {
+            if request.output_filename().is_none() {
+                continue;
+            }
+
+            match self.layout {
+                OutputLayout::PerPackage => {
+                    let content = file_contents.remove(module).unwrap_or_default();
+                    files.extend(Self::content_to_file(content, request));
+                }
+                OutputLayout::PerFile => {
+                    files.extend(Self::per_file_files(module, request, &mut file_contents));
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Comments are linked to types anywhere in the request, not only to
+    /// those that the module imports
+    fn depends_on_whole_request(&self) -> bool {
+        self.doc_links.is_some()
+    }
+}
+
+impl<F> CoreProstGenerator<F>
+where
+    F: Fn() -> prost_build::Config + Sync,
+{
+    pub(crate) fn new(config: F, layout: OutputLayout) -> Self {
+        Self {
+            config,
+            layout,
+            jobs: 1,
+            format: false,
+            insertion_points: false,
+            doc_links: None,
+            strict: false,
+        }
+    }
+
+    /// Sets whether the generated code is pretty-printed
+    pub(crate) fn format(mut self, format: bool) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the maximum number of threads used to generate modules
+    pub(crate) fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Sets whether insertion points are added for each message and enum
+    pub(crate) fn insertion_points(mut self, insertion_points: bool) -> Self {
+        self.insertion_points = insertion_points;
+        self
+    }
+
+    /// Sets the resolver used to link references to types in comments, if
+    /// they are linked
+    pub(crate) fn doc_links(mut self, resolver: Option<Resolver>) -> Self {
+        self.doc_links = resolver;
+        self
+    }
+
+    /// Sets whether only the requested files of each module are generated
+    ///
+    /// The types of the other files must have extern paths, as set up by
+    /// [`strict_extern_paths`](crate::strict_extern_paths).
+    pub(crate) fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The files of `request` whose code is generated
+    fn generated_files<'a>(
+        &self,
+        request: &'a ModuleRequest,
+    ) -> Box<dyn Iterator<Item = &'a FileDescriptorProto> + 'a> {
+        if self.strict {
+            Box::new(request.requested_files())
+        } else {
+            Box::new(request.files())
+        }
+    }
+
+    /// Splits the modules with output into at most `jobs` groups of roughly
+    /// equal size
+    fn partition<'a>(
+        &self,
+        module_request_set: &'a ModuleRequestSet,
+    ) -> Vec<Vec<(&'a Module, &'a ModuleRequest)>> {
+        let mut requests: Vec<_> = module_request_set
+            .requests()
+            .filter(|(_, request)| request.output_filename().is_some())
+            .map(|(module, request)| {
+                let size: usize = request.raw_files().map(<[u8]>::len).sum();
+                (size, module, request)
             })
             .collect();
+        requests.sort_by(|(a, ..), (b, ..)| b.cmp(a));
 
-        let modules: HashSet<_> = prost_requests
+        let jobs = self.jobs.min(requests.len());
+        let mut groups: Vec<(usize, Vec<_>)> = (0..jobs).map(|_| (0, Vec::new())).collect();
+        for (size, module, request) in requests {
+            // Each module goes to the group with the least to generate so far
+            if let Some((total, group)) = groups.iter_mut().min_by_key(|(total, _)| *total) {
+                *total += size;
+                group.push((module, request));
+            }
+        }
+
+        groups.into_iter().map(|(_, group)| group).collect()
+    }
+
+    /// Generates the code for a group of modules
+    ///
+    /// _Prost!_ requires the definition of every message referenced by the
+    /// generated files, so the files that they transitively import are passed
+    /// as well. As the code generated for these files is discarded, they are
+    /// passed without comments or services. In strict mode, this includes
+    /// the files of a module that were not requested, whose types are
+    /// skipped by _Prost!_ as they have extern paths.
+    fn generate_group(
+        &self,
+        group: &[(&Module, &ModuleRequest)],
+        module_request_set: &ModuleRequestSet,
+        doc_links: Option<&DocLinks<'_>>,
+    ) -> std::result::Result<HashMap<Module, String>, Error> {
+        let mut prost_requests = Vec::new();
+        let mut generated = HashSet::new();
+        let mut wanted = HashSet::new();
+        for (module, request) in group {
+            for proto in self.generated_files(request) {
+                let prost_module = self.prost_module(module, proto);
+                wanted.insert(prost_module.clone());
+                generated.insert(proto.name());
+                let mut proto = proto.clone();
+                if let Some(doc_links) = doc_links {
+                    doc_links.link_file(&mut proto);
+                }
+                prost_requests.push((prost_module, proto));
+            }
+        }
+
+        let all_files: HashMap<&str, (&Module, &FileDescriptorProto)> = module_request_set
+            .requests()
+            .flat_map(|(module, request)| request.files().map(move |file| (module, file)))
+            .map(|(module, file)| (file.name(), (module, file)))
+            .collect();
+        let mut visited = generated;
+        let mut pending: Vec<&str> = group
             .iter()
-            .map(|(module, _)| module.clone())
+            .flat_map(|(_, request)| self.generated_files(request))
+            .flat_map(|file| file.dependency.iter().map(String::as_str))
             .collect();
+        while let Some(name) = pending.pop() {
+            if !visited.insert(name) {
+                continue;
+            }
+            if let Some(&(module, file)) = all_files.get(name) {
+                pending.extend(file.dependency.iter().map(String::as_str));
+                prost_requests.push((self.prost_module(module, file), without_code(file)));
+            }
+        }
 
-        let mut file_contents = self.config.generate(prost_requests)?;
-        let files = modules
-            .into_iter()
-            .filter_map(|module| {
-                let content = file_contents.remove(&module).unwrap_or_default();
-                Self::content_to_file(module, content, module_request_set)
-            })
-            .collect();
+        let mut config = (self.config)();
+        // Deprecation notes are taken from the comments before they are linked
+        deprecations::register(
+            &mut config,
+            group
+                .iter()
+                .flat_map(|(_, request)| self.generated_files(request)),
+        );
+        if self.insertion_points {
+            let files = prost_requests
+                .iter()
+                .filter(|(module, _)| wanted.contains(module))
+                .map(|(_, file)| file);
+            insertion_points::register(&mut config, files);
+        }
 
-        Ok(files)
+        let mut file_contents = config.generate(prost_requests)?;
+        file_contents.retain(|module, _| wanted.contains(module));
+        for (module, content) in &mut file_contents {
+            *content = deprecations::expand(content);
+            if self.format {
+                *content = format_content(module, content)?;
+            }
+            if self.insertion_points {
+                *content = insertion_points::expand(content);
+            }
+        }
+
+        Ok(file_contents)
     }
-}
 
-impl CoreProstGenerator {
-    pub fn new(config: prost_build::Config) -> Self {
-        Self { config }
+    /// The module under which _Prost!_ generates the code for `proto`
+    fn prost_module(&self, module: &Module, proto: &FileDescriptorProto) -> Module {
+        match self.layout {
+            OutputLayout::PerPackage => module.clone(),
+            OutputLayout::PerFile => file_module(module, proto),
+        }
     }
 
-    fn content_to_file(
-        module: Module,
-        content: String,
-        module_requests: &ModuleRequestSet,
-    ) -> Option<File> {
-        let request = module_requests
-            .for_module(&module)
-            .expect("generated module that somehow wasn't in the original requests…");
+    /// Writes each `.proto` file separately, alongside a file for the
+    /// package that includes them
+    ///
+    /// All files are still passed to _Prost!_ together, each under its own
+    /// module, so that references between files resolve as they would if
+    /// the package were generated as a whole.
+    fn per_file_files(
+        module: &Module,
+        request: &ModuleRequest,
+        file_contents: &mut HashMap<Module, String>,
+    ) -> Vec<File> {
+        let mut files = Vec::new();
+        let mut includes = Vec::new();
+        for proto in request.files() {
+            let content = match file_contents.remove(&file_module(module, proto)) {
+                Some(content) => content,
+                None => continue,
+            };
+
+            let filename = per_file_filename(proto);
+            files.push(File {
+                name: Some(format!("{}{filename}", request.output_dir())),
+                content: Some(format!("// @generated\n{content}")),
+                ..Default::default()
+            });
+            includes.push(filename);
+        }
 
+        files.push(File {
+            name: request.output_filepath(),
+            content: Some(Self::package_file_content(&includes)),
+            ..Default::default()
+        });
+        files
+    }
+
+    fn package_file_content(includes: &[String]) -> String {
+        let mut buffer = String::from("// @generated\n");
+        for filename in includes {
+            buffer.push_str("include!(\"");
+            buffer.push_str(filename);
+            buffer.push_str("\");\n");
+        }
+        buffer.push_str("// @@protoc_insertion_point(module)\n");
+        buffer
+    }
+
+    fn content_to_file(content: String, request: &ModuleRequest) -> Option<File> {
         request.write_to_file(move |buffer| {
             buffer.push_str("// @generated\n");
             buffer.push_str(&content);
@@ -60,3 +331,41 @@ impl CoreProstGenerator {
         })
     }
 }
+
+/// Pretty-prints the code generated for a module
+fn format_content(module: &Module, content: &str) -> std::result::Result<String, Error> {
+    let file = syn::parse_file(content)
+        .map_err(|e| format!("unable to format the code generated for `{module}`: {e}"))?;
+    Ok(prettyplease::unparse(&file))
+}
+
+/// A copy of a file descriptor with only what _Prost!_ needs to resolve the
+/// messages referenced by other files
+fn without_code(file: &FileDescriptorProto) -> FileDescriptorProto {
+    FileDescriptorProto {
+        name: file.name.clone(),
+        package: file.package.clone(),
+        dependency: file.dependency.clone(),
+        message_type: file.message_type.clone(),
+        enum_type: file.enum_type.clone(),
+        syntax: file.syntax.clone(),
+        ..Default::default()
+    }
+}
+
+/// A module unique to a single `.proto` file, used to keep _Prost!_ from
+/// combining the output of files in the same package
+fn file_module(module: &Module, proto: &FileDescriptorProto) -> Module {
+    Module::from_parts(module.parts().chain([proto.name()]))
+}
+
+/// The name of the file generated for a single `.proto` file
+///
+/// The name is derived from the path of the `.proto` file, which is unique
+/// within a request, so files generated from different packages cannot
+/// collide when using `flat_output_dir`.
+pub(crate) fn per_file_filename(proto: &FileDescriptorProto) -> String {
+    let path = proto.name();
+    let path = path.strip_suffix(".proto").unwrap_or(path);
+    format!("{}.pb.rs", path.replace('/', "."))
+}
@@ -0,0 +1,287 @@
+//! `#[deprecated]` attributes for deprecated messages, enums and enum values
+//!
+//! _Prost!_ marks deprecated fields itself. Enums and enum values are marked
+//! with attributes registered for their own paths, but _Prost!_ applies the
+//! attributes of a message to the messages nested within it as well. Each
+//! message within a deprecated message is given a sentinel attribute naming
+//! it instead, and once the code has been generated, only the most specific
+//! sentinel on each message is kept.
+//!
+//! The code generated alongside a deprecated definition, such as the fields
+//! of other messages and the `as_str_name` of an enum, refers to it. Each
+//! item of a module with deprecated definitions is marked
+//! `#[allow(deprecated)]`, so that only uses outside of the generated code
+//! are reported.
+
+use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto};
+
+/// The attribute that allows the use of deprecated definitions
+const ALLOW: &str = "#[allow(deprecated)]";
+
+/// The attribute that marks a message within a deprecated message
+const SENTINEL: &str = "#[protoc_deprecated = \"";
+
+/// Adds `#[deprecated]` attributes for the deprecated definitions in `files`
+pub(crate) fn register<'a>(
+    config: &mut prost_build::Config,
+    files: impl IntoIterator<Item = &'a FileDescriptorProto>,
+) {
+    for file in files {
+        let scope = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+        for (index, message) in file.message_type.iter().enumerate() {
+            register_message(config, file, &scope, message, &[4, index as i32], false);
+        }
+        for (index, enumeration) in file.enum_type.iter().enumerate() {
+            register_enum(config, file, &scope, enumeration, &[5, index as i32]);
+        }
+    }
+}
+
+fn register_message(
+    config: &mut prost_build::Config,
+    file: &FileDescriptorProto,
+    scope: &str,
+    message: &DescriptorProto,
+    path: &[i32],
+    in_deprecated: bool,
+) {
+    let name = format!("{scope}.{}", message.name());
+    let deprecated = message
+        .options
+        .as_ref()
+        .is_some_and(|options| options.deprecated());
+    if deprecated || in_deprecated {
+        let attribute = if deprecated {
+            attribute(deprecation_note(file, path, [7, 3]))
+        } else {
+            String::new()
+        };
+        config.message_attribute(&name, format!("{SENTINEL}{name}\"]{attribute}"));
+    }
+
+    for (index, nested) in message.nested_type.iter().enumerate() {
+        let path = [path, &[3, index as i32]].concat();
+        register_message(
+            config,
+            file,
+            &name,
+            nested,
+            &path,
+            deprecated || in_deprecated,
+        );
+    }
+    for (index, enumeration) in message.enum_type.iter().enumerate() {
+        let path = [path, &[4, index as i32]].concat();
+        register_enum(config, file, &name, enumeration, &path);
+    }
+}
+
+fn register_enum(
+    config: &mut prost_build::Config,
+    file: &FileDescriptorProto,
+    scope: &str,
+    enumeration: &EnumDescriptorProto,
+    path: &[i32],
+) {
+    let name = format!("{scope}.{}", enumeration.name());
+    if enumeration
+        .options
+        .as_ref()
+        .is_some_and(|options| options.deprecated())
+    {
+        config.enum_attribute(&name, attribute(deprecation_note(file, path, [3, 3])));
+    }
+
+    for (index, value) in enumeration.value.iter().enumerate() {
+        if value
+            .options
+            .as_ref()
+            .is_some_and(|options| options.deprecated())
+        {
+            let path = [path, &[2, index as i32]].concat();
+            config.field_attribute(
+                format!("{name}.{}", value.name()),
+                attribute(deprecation_note(file, &path, [3, 1])),
+            );
+        }
+    }
+}
+
+/// The note explaining why the definition at `path` in `file` is deprecated
+///
+/// The note is taken from the trailing comment on the definition or, if it
+/// has none, on its `deprecated` option, found at `option_path` below the
+/// definition. The lines of the comment are joined into a single line.
+pub fn deprecation_note(
+    file: &FileDescriptorProto,
+    path: &[i32],
+    option_path: [i32; 2],
+) -> Option<String> {
+    let locations = &file.source_code_info.as_ref()?.location;
+    let trailing_comment = |path: &[i32]| {
+        locations
+            .iter()
+            .find(|location| location.path == path)
+            .and_then(|location| location.trailing_comments.as_deref())
+            .map(|comment| {
+                comment
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .filter(|note| !note.is_empty())
+    };
+
+    trailing_comment(path).or_else(|| {
+        let option_path: Vec<i32> = path.iter().copied().chain(option_path).collect();
+        trailing_comment(&option_path)
+    })
+}
+
+/// Whether `file` deprecates a message, enum or enum value at the top level
+/// of its package
+///
+/// The trait implementations derived for these definitions refer to them
+/// from the module that includes the generated code, which must allow the
+/// use of deprecated definitions itself.
+pub fn has_deprecated_top_level_types(file: &FileDescriptorProto) -> bool {
+    let message = file.message_type.iter().any(|message| {
+        message
+            .options
+            .as_ref()
+            .is_some_and(|options| options.deprecated())
+    });
+    let enumeration = file.enum_type.iter().any(|enumeration| {
+        enumeration
+            .options
+            .as_ref()
+            .is_some_and(|options| options.deprecated())
+            || enumeration.value.iter().any(|value| {
+                value
+                    .options
+                    .as_ref()
+                    .is_some_and(|options| options.deprecated())
+            })
+    });
+
+    message || enumeration
+}
+
+fn attribute(note: Option<String>) -> String {
+    match note {
+        Some(note) => format!("#[deprecated = {note:?}]"),
+        None => String::from("#[deprecated]"),
+    }
+}
+
+/// Replaces the sentinel attributes in `content` with the `#[deprecated]`
+/// attribute of the most specific one on each message, if any, and allows
+/// the use of deprecated definitions within each item if there are any
+pub(crate) fn expand(content: &str) -> String {
+    let expanded = if content.contains(SENTINEL) {
+        expand_sentinels(content)
+    } else {
+        content.to_owned()
+    };
+
+    if expanded.contains("#[deprecated") {
+        allow_deprecated(&expanded)
+    } else {
+        expanded
+    }
+}
+
+/// Marks each item at the top level of `content` `#[allow(deprecated)]`,
+/// after any other attributes
+fn allow_deprecated(content: &str) -> String {
+    let mut buffer = String::with_capacity(content.len());
+    for line in content.lines() {
+        let is_item = ["pub struct ", "pub enum ", "pub mod ", "impl "]
+            .iter()
+            .any(|item| line.starts_with(item));
+        if is_item {
+            buffer.push_str(ALLOW);
+            buffer.push('\n');
+        }
+        buffer.push_str(line);
+        buffer.push('\n');
+    }
+    buffer
+}
+
+fn expand_sentinels(content: &str) -> String {
+    let mut buffer = String::with_capacity(content.len());
+    let mut attributes: Vec<&str> = Vec::new();
+    let mut deprecation: Option<(&str, &str)> = None;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if let Some(sentinel) = trimmed.strip_prefix(SENTINEL) {
+            if let Some((name, attribute)) = sentinel.split_once("\"]") {
+                if deprecation.is_none_or(|(longest, _)| name.len() > longest.len()) {
+                    deprecation = Some((name, attribute));
+                }
+                continue;
+            }
+        }
+        if trimmed.starts_with("#[") {
+            attributes.push(line);
+            continue;
+        }
+
+        // The deprecation comes first, keeping any other sentinels together
+        if let Some((_, attribute)) = deprecation.take().filter(|(_, a)| !a.is_empty()) {
+            buffer.push_str(&line[..line.len() - trimmed.len()]);
+            buffer.push_str(attribute);
+            buffer.push('\n');
+        }
+        for attribute in attributes.drain(..) {
+            buffer.push_str(attribute);
+            buffer.push('\n');
+        }
+        buffer.push_str(line);
+        buffer.push('\n');
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_most_specific_sentinel_is_kept() {
+        let content = "\
+/// A message
+#[protoc_deprecated = \".x.M\"]#[deprecated = \"Use `N`\"]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct M {}
+/// Nested message and enum types in `M`.
+pub mod m {
+    #[protoc_deprecated = \".x.M.Inner\"]
+    #[protoc_deprecated = \".x.M\"]#[deprecated = \"Use `N`\"]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Inner {}
+}
+";
+        assert_eq!(
+            expand_sentinels(content),
+            "\
+/// A message
+#[deprecated = \"Use `N`\"]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct M {}
+/// Nested message and enum types in `M`.
+pub mod m {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Inner {}
+}
+"
+        );
+    }
+}
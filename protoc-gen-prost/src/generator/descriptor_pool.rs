@@ -0,0 +1,230 @@
+use std::fmt::Write;
+
+use heck::ToUpperCamelCase;
+use prost::Message;
+use prost_build::Module;
+use prost_types::{compiler::code_generator_response::File, DescriptorProto, EnumDescriptorProto};
+
+use super::{
+    file_descriptor_set::{append_bytes_const, RawProtosSet},
+    minimize::Minimize,
+};
+use crate::{Generator, ModuleRequest, ModuleRequestSet, Result};
+
+/// The name of the file holding the shared descriptor pool
+const DESCRIPTOR_POOL_FILENAME: &str = "descriptor_pool.rs";
+
+/// Generates a single `DescriptorPool` shared by every module, along with
+/// accessors for the descriptors of the enums and services in each module
+pub struct DescriptorPoolGenerator {
+    /// The Rust path at which the generated pool will be available, such as
+    /// `crate::DESCRIPTOR_POOL`
+    path: String,
+
+    /// Which information to remove from each FileDescriptorProto before embedding it
+    minimize: Minimize,
+
+    /// Whether accessors are only generated for the requested files
+    strict: bool,
+}
+
+impl DescriptorPoolGenerator {
+    pub(crate) fn new(path: String, minimize: Minimize) -> Self {
+        Self {
+            path,
+            minimize,
+            strict: false,
+        }
+    }
+
+    /// Sets whether accessors are only generated for the types of the
+    /// requested files, as the other files are generated elsewhere
+    pub(crate) fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The name of the static holding the pool, taken from the end of its path
+    fn static_name(&self) -> &str {
+        self.path.rsplit("::").next().unwrap_or(&self.path)
+    }
+
+    /// Generates the file defining the pool, built on first use from every
+    /// proto file in the request
+    fn generate_pool(&self, module_request_set: &ModuleRequestSet) -> Result {
+        let minimized = self.minimize.apply(module_request_set)?;
+        let file_descriptor_set = RawProtosSet {
+            file: module_request_set
+                .requests()
+                .flat_map(|(_, request)| minimized.raw_files(request))
+                .map(|b| b.to_owned())
+                .collect(),
+        };
+
+        let static_name = self.static_name();
+        let bytes_name = format!("{static_name}_FILE_DESCRIPTOR_SET");
+
+        let mut content = String::from("// @generated\n");
+        content
+            .push_str("/// Encoded file descriptor set for every package in the descriptor pool\n");
+        append_bytes_const(
+            &bytes_name,
+            &file_descriptor_set.encode_to_vec(),
+            &mut content,
+        );
+        writeln!(
+            content,
+            "/// Descriptors for every package, decoded on first use
+pub static {static_name}: ::once_cell::sync::Lazy<::prost_reflect::DescriptorPool> =
+    ::once_cell::sync::Lazy::new(|| {{
+        ::prost_reflect::DescriptorPool::decode({bytes_name})
+            .expect(\"invalid file descriptor set\")
+    }});"
+        )
+        .unwrap();
+
+        Ok(vec![File {
+            name: Some(DESCRIPTOR_POOL_FILENAME.to_owned()),
+            content: Some(content),
+            ..File::default()
+        }])
+    }
+
+    /// Appends accessors for the descriptors of the enums and services in the module
+    fn generate_accessors(&self, request: &ModuleRequest) -> Option<File> {
+        let files: Box<dyn Iterator<Item = _>> = if self.strict {
+            Box::new(request.requested_files())
+        } else {
+            Box::new(request.files())
+        };
+
+        let mut buffer = String::new();
+        for file in files {
+            let package = file.package();
+            for enum_type in &file.enum_type {
+                self.append_enum_accessor(package, &[], enum_type, &mut buffer);
+            }
+            for message in &file.message_type {
+                self.append_nested_enum_accessors(package, &[], message, &mut buffer);
+            }
+            for service in &file.service {
+                self.append_service_accessor(package, service.name(), &mut buffer);
+            }
+        }
+
+        if buffer.is_empty() {
+            None
+        } else {
+            request.append_to_file(|b| b.push_str(&buffer))
+        }
+    }
+
+    fn append_nested_enum_accessors(
+        &self,
+        proto_scope: &str,
+        rust_scope: &[String],
+        message: &DescriptorProto,
+        buffer: &mut String,
+    ) {
+        let proto_scope = qualify(proto_scope, message.name());
+        let mut rust_scope = rust_scope.to_vec();
+        rust_scope.push(to_snake(message.name()));
+
+        for enum_type in &message.enum_type {
+            self.append_enum_accessor(&proto_scope, &rust_scope, enum_type, buffer);
+        }
+        for nested in &message.nested_type {
+            self.append_nested_enum_accessors(&proto_scope, &rust_scope, nested, buffer);
+        }
+    }
+
+    fn append_enum_accessor(
+        &self,
+        proto_scope: &str,
+        rust_scope: &[String],
+        enum_type: &EnumDescriptorProto,
+        buffer: &mut String,
+    ) {
+        let full_name = qualify(proto_scope, enum_type.name());
+        let mut rust_path = rust_scope.join("::");
+        if !rust_path.is_empty() {
+            rust_path.push_str("::");
+        }
+        rust_path.push_str(&to_upper_camel(enum_type.name()));
+
+        writeln!(
+            buffer,
+            "impl {rust_path} {{
+    /// Returns the descriptor for the `{full_name}` enum
+    pub fn descriptor() -> ::prost_reflect::EnumDescriptor {{
+        {pool}
+            .get_enum_by_name(\"{full_name}\")
+            .expect(\"enum `{full_name}` not found in the descriptor pool\")
+    }}
+}}",
+            pool = self.path,
+        )
+        .unwrap();
+    }
+
+    fn append_service_accessor(&self, package: &str, name: &str, buffer: &mut String) {
+        let full_name = qualify(package, name);
+        writeln!(
+            buffer,
+            "/// Returns the descriptor for the `{full_name}` service
+pub fn {fn_name}_descriptor() -> ::prost_reflect::ServiceDescriptor {{
+    {pool}
+        .get_service_by_name(\"{full_name}\")
+        .expect(\"service `{full_name}` not found in the descriptor pool\")
+}}",
+            fn_name = to_snake(name),
+            pool = self.path,
+        )
+        .unwrap();
+    }
+}
+
+impl Generator for DescriptorPoolGenerator {
+    fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
+        let mut files = self.generate_pool(module_request_set)?;
+        files.extend(
+            module_request_set
+                .requests()
+                .filter_map(|(_, request)| self.generate_accessors(request)),
+        );
+
+        Ok(files)
+    }
+
+    /// The pool holds the descriptors of every file in the request
+    fn depends_on_whole_request(&self) -> bool {
+        true
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{scope}.{name}")
+    }
+}
+
+/// Converts a protobuf name into the `snake_case` name used by _Prost!_ for
+/// modules and functions
+fn to_snake(s: &str) -> String {
+    let as_module = Module::from_protobuf_package_name(s);
+    let mut parts = as_module.parts();
+    parts.next().unwrap_or_default().to_owned()
+}
+
+/// Converts a protobuf name into the `UpperCamel` name used by _Prost!_ for types
+fn to_upper_camel(s: &str) -> String {
+    let mut ident = s.to_upper_camel_case();
+
+    // Suffix an underscore for the `Self` Rust keyword as it is not allowed as raw identifier.
+    if ident == "Self" {
+        ident += "_";
+    }
+    ident
+}
@@ -1,14 +1,31 @@
-use std::fmt::Write;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
 
-use prost_types::compiler::code_generator_response::File;
+use prost_types::{compiler::code_generator_response::File, FileDescriptorProto};
 
+use super::minimize::{Minimize, Minimized};
 use crate::{Generator, ModuleRequest, ModuleRequestSet, Result};
 
 pub struct FileDescriptorSetGenerator {
-    /// Whether to include all dependent proto files in the FileDescriptorSet.
-    /// When true, the generated FileDescriptorSet will include all imported proto files
-    /// from all modules, which is required for prost-reflect to work correctly.
-    include_all_dependencies: bool,
+    /// Which dependent proto files to include in the FileDescriptorSet.
+    /// Including dependencies is required for prost-reflect to work correctly.
+    dependencies: Dependencies,
+
+    /// Whether to write the FileDescriptorSet to a separate `<module>.fds.rs` file,
+    /// which is then included into the module, rather than appending it directly
+    separate_file: bool,
+
+    /// Whether to also generate a constant holding the encoded FileDescriptorProto
+    /// for each proto file in the module, along with a function to look them up
+    per_file: bool,
+
+    /// Which information to remove from each FileDescriptorProto before embedding it
+    minimize: Minimize,
+
+    /// How to compress the embedded FileDescriptorSet, if at all
+    compression: Option<Compression>,
 }
 
 impl FileDescriptorSetGenerator {
@@ -16,7 +33,11 @@ impl FileDescriptorSetGenerator {
     /// in each module (not their dependencies)
     pub fn new() -> Self {
         Self {
-            include_all_dependencies: false,
+            dependencies: Dependencies::None,
+            separate_file: false,
+            per_file: false,
+            minimize: Minimize::default(),
+            compression: None,
         }
     }
 
@@ -24,40 +45,87 @@ impl FileDescriptorSetGenerator {
     /// from all modules. This is required when using prost-reflect.
     pub fn with_all_dependencies() -> Self {
         Self {
-            include_all_dependencies: true,
+            dependencies: Dependencies::All,
+            separate_file: false,
+            per_file: false,
+            minimize: Minimize::default(),
+            compression: None,
+        }
+    }
+
+    /// Creates a new FileDescriptorSetGenerator that includes only the proto files
+    /// that are transitively imported by the proto files in each module. This is
+    /// sufficient for prost-reflect, while embedding far less in large requests.
+    pub fn with_imported_dependencies() -> Self {
+        Self {
+            dependencies: Dependencies::Imported,
+            ..Self::new()
         }
     }
+
+    /// Writes the FileDescriptorSet for each module to a separate `<module>.fds.rs` file
+    /// that is included into the module, keeping the main file free of encoded bytes
+    pub fn separate_file(mut self, separate_file: bool) -> Self {
+        self.separate_file = separate_file;
+        self
+    }
+
+    /// Generates a constant for each proto file in the module holding its encoded
+    /// FileDescriptorProto, along with a `file_descriptor` function to look them up
+    pub fn per_file(mut self, per_file: bool) -> Self {
+        self.per_file = per_file;
+        self
+    }
+
+    /// Removes information from each FileDescriptorProto before embedding it
+    ///
+    /// By default, the raw file descriptors are embedded exactly as received.
+    pub(crate) fn minimize(mut self, minimize: Minimize) -> Self {
+        self.minimize = minimize;
+        self
+    }
+
+    /// Embeds the FileDescriptorSet compressed, along with a `file_descriptor_set()`
+    /// function that decompresses it on first use
+    pub(crate) fn compressed(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
 }
 
 impl Generator for FileDescriptorSetGenerator {
     fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
+        let minimized = self.minimize.apply(module_request_set)?;
         let files = module_request_set
             .requests()
-            .filter_map(|(_, request)| {
-                if self.include_all_dependencies {
-                    Self::generate_all_dependent(request, module_request_set)
-                } else {
-                    Self::generate_one(request)
+            .flat_map(|(_, request)| match self.dependencies {
+                Dependencies::None => self.generate_one(request, &minimized),
+                Dependencies::All => {
+                    self.generate_all_dependent(request, module_request_set, &minimized)
+                }
+                Dependencies::Imported => {
+                    self.generate_imported(request, module_request_set, &minimized)
                 }
             })
             .collect();
 
         Ok(files)
     }
+
+    fn depends_on_whole_request(&self) -> bool {
+        matches!(self.dependencies, Dependencies::All)
+    }
 }
 
 impl FileDescriptorSetGenerator {
     /// Generates a FileDescriptorSet containing only the proto files in the current module
-    fn generate_one(request: &ModuleRequest) -> Option<File> {
-        request.append_to_file(|buffer| {
-            // This cannot be done with another file and `include_bytes!` because the
-            // contract for a file's contents requires that they be valid UTF-8.
-            //
-            // So, we append them as an embedded array instead.
+    fn generate_one(&self, request: &ModuleRequest, minimized: &Minimized) -> Vec<File> {
+        self.write_to_module(request, minimized, |buffer| {
             append_file_descriptor_set_bytes(
                 request.proto_package_name(),
+                self.compression,
                 &RawProtosSet {
-                    file: request.raw_files().map(|b| b.to_owned()).collect(),
+                    file: minimized.raw_files(request).map(|b| b.to_owned()).collect(),
                 },
                 buffer,
             );
@@ -67,24 +135,22 @@ impl FileDescriptorSetGenerator {
     /// Generates a FileDescriptorSet containing all proto files from all modules.
     /// This ensures imported dependencies are included, which is required for prost-reflect.
     fn generate_all_dependent(
+        &self,
         request: &ModuleRequest,
         module_request_set: &ModuleRequestSet,
-    ) -> Option<File> {
-        request.append_to_file(|buffer| {
-            // This cannot be done with another file and `include_bytes!` because the
-            // contract for a file's contents requires that they be valid UTF-8.
-            //
-            // So, we append them as an embedded array instead.
-            //
+        minimized: &Minimized,
+    ) -> Vec<File> {
+        self.write_to_module(request, minimized, |buffer| {
             // Collect all raw proto files from all modules to ensure imported dependencies
             // are included in the FileDescriptorSet
             let all_raw_files: Vec<Vec<u8>> = module_request_set
                 .requests()
-                .flat_map(|(_, req)| req.raw_files().map(|b| b.to_owned()))
+                .flat_map(|(_, req)| minimized.raw_files(req).map(|b| b.to_owned()))
                 .collect();
 
             append_file_descriptor_set_bytes(
                 request.proto_package_name(),
+                self.compression,
                 &RawProtosSet {
                     file: all_raw_files,
                 },
@@ -92,27 +158,320 @@ impl FileDescriptorSetGenerator {
             );
         })
     }
+
+    /// Generates a FileDescriptorSet containing the proto files in the current module
+    /// along with the transitive closure of their imports, ordered such that every
+    /// file follows the files that it imports
+    fn generate_imported(
+        &self,
+        request: &ModuleRequest,
+        module_request_set: &ModuleRequestSet,
+        minimized: &Minimized,
+    ) -> Vec<File> {
+        self.write_to_module(request, minimized, |buffer| {
+            let all_files: HashMap<&str, (&FileDescriptorProto, &[u8])> = module_request_set
+                .requests()
+                .flat_map(|(_, req)| req.files().zip(req.raw_files()))
+                .map(|(file, raw)| (file.name(), (file, minimized.raw_file(file, raw))))
+                .collect();
+
+            let mut visited = HashSet::new();
+            let mut imported_files = Vec::new();
+            for file in request.files() {
+                visit_imports(file.name(), &all_files, &mut visited, &mut imported_files);
+            }
+
+            append_file_descriptor_set_bytes(
+                request.proto_package_name(),
+                self.compression,
+                &RawProtosSet {
+                    file: imported_files,
+                },
+                buffer,
+            );
+        })
+    }
+
+    /// Writes the output of `f` into the module, either appending it to the main file
+    /// or writing it to a separate file that is included by the main file
+    fn write_to_module<F: FnOnce(&mut String)>(
+        &self,
+        request: &ModuleRequest,
+        minimized: &Minimized,
+        f: F,
+    ) -> Vec<File> {
+        let f = |buffer: &mut String| {
+            f(buffer);
+            if self.per_file {
+                append_file_descriptors(request, minimized, buffer);
+            }
+        };
+
+        if !self.separate_file {
+            return request.append_to_file(f).into_iter().collect();
+        }
+
+        // Named after the module file, so the empty package gets `_.fds.rs`
+        let output_filename = match request.output_filename() {
+            Some(filename) => format!(
+                "{}.fds.rs",
+                filename.strip_suffix(".rs").unwrap_or(filename)
+            ),
+            None => return Vec::new(),
+        };
+        let include = match request.append_to_file(|buf| {
+            buf.push_str("include!(\"");
+            buf.push_str(&output_filename);
+            buf.push_str("\");\n");
+        }) {
+            Some(include) => include,
+            None => return Vec::new(),
+        };
+
+        let mut content = String::from("// @generated\n");
+        f(&mut content);
+
+        vec![
+            include,
+            File {
+                name: Some(request.output_dir() + &output_filename),
+                content: Some(content),
+                ..File::default()
+            },
+        ]
+    }
+}
+
+/// The compression applied to an embedded FileDescriptorSet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    /// Zstandard, decompressed in the generated code with `ruzstd`
+    Zstd,
+
+    /// Raw DEFLATE, decompressed in the generated code with `miniz_oxide`
+    Deflate,
+}
+
+impl Compression {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Deflate => "DEFLATE",
+        }
+    }
+
+    fn compress(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Zstd => ruzstd::encoding::compress_to_vec(
+                bytes,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            ),
+            Self::Deflate => miniz_oxide::deflate::compress_to_vec(
+                bytes,
+                miniz_oxide::deflate::CompressionLevel::BestCompression as u8,
+            ),
+        }
+    }
+
+    /// An expression decompressing `FILE_DESCRIPTOR_SET_COMPRESSED` into a `Vec<u8>`
+    fn decompress_expr(self) -> &'static str {
+        match self {
+            Self::Zstd => {
+                "{
+        let mut decoder =
+            ::ruzstd::decoding::StreamingDecoder::new(FILE_DESCRIPTOR_SET_COMPRESSED)
+                .expect(\"invalid compressed file descriptor set\");
+        let mut buf = ::std::vec::Vec::new();
+        ::std::io::Read::read_to_end(&mut decoder, &mut buf)
+            .expect(\"invalid compressed file descriptor set\");
+        buf
+    }"
+            }
+            Self::Deflate => {
+                "::miniz_oxide::inflate::decompress_to_vec(FILE_DESCRIPTOR_SET_COMPRESSED)
+        .expect(\"invalid compressed file descriptor set\")"
+            }
+        }
+    }
+}
+
+/// Which dependent proto files to include in a FileDescriptorSet
+enum Dependencies {
+    /// Only the proto files in the module
+    None,
+
+    /// Every proto file in the request
+    All,
+
+    /// The proto files transitively imported by the proto files in the module
+    Imported,
+}
+
+/// Visits the imports of a proto file depth-first, pushing each file after its imports
+///
+/// `public_dependency` and `weak_dependency` only index into `dependency`, so walking
+/// `dependency` covers every kind of import.
+fn visit_imports<'a>(
+    name: &'a str,
+    all_files: &HashMap<&'a str, (&'a FileDescriptorProto, &[u8])>,
+    visited: &mut HashSet<&'a str>,
+    out: &mut Vec<Vec<u8>>,
+) {
+    if !visited.insert(name) {
+        return;
+    }
+
+    // `protoc` always sends every imported file, but skip any that are missing
+    // rather than producing a partial import graph with a panic
+    if let Some(&(file, raw)) = all_files.get(name) {
+        for dependency in &file.dependency {
+            visit_imports(dependency, all_files, visited, out);
+        }
+        out.push(raw.to_owned());
+    }
 }
 
 /// Wire-compatible FileDescriptorSet that doesn't require fully-decoded file descriptors
 #[derive(Clone, PartialEq, ::prost::Message)]
-struct RawProtosSet {
+pub(super) struct RawProtosSet {
     #[prost(bytes = "vec", repeated, tag = "1")]
     pub file: Vec<Vec<u8>>,
 }
 
 fn append_file_descriptor_set_bytes(
     package: &str,
+    compression: Option<Compression>,
     file_descriptor_set: &impl prost::Message,
     buffer: &mut String,
 ) {
-    buffer.push_str("/// Encoded file descriptor set for the `");
-    buffer.push_str(package);
-    buffer.push_str("` package\n");
+    let compression = match compression {
+        Some(compression) => compression,
+        None => {
+            buffer.push_str("/// Encoded file descriptor set for the `");
+            buffer.push_str(package);
+            buffer.push_str("` package\n");
+
+            append_bytes_const(
+                "FILE_DESCRIPTOR_SET",
+                &file_descriptor_set.encode_to_vec(),
+                buffer,
+            );
+            return;
+        }
+    };
+
+    writeln!(
+        buffer,
+        "/// Encoded file descriptor set for the `{package}` package, compressed with {}",
+        compression.name(),
+    )
+    .unwrap();
+    append_bytes_const(
+        "FILE_DESCRIPTOR_SET_COMPRESSED",
+        &compression.compress(&file_descriptor_set.encode_to_vec()),
+        buffer,
+    );
+
+    writeln!(
+        buffer,
+        "/// Encoded file descriptor set for the `{package}` package
+///
+/// Decompressed from [`FILE_DESCRIPTOR_SET_COMPRESSED`] on first use.
+pub fn file_descriptor_set() -> &'static [u8] {{
+    static FILE_DESCRIPTOR_SET: ::once_cell::sync::OnceCell<::std::vec::Vec<u8>> =
+        ::once_cell::sync::OnceCell::new();
+    FILE_DESCRIPTOR_SET.get_or_init(|| {})
+}}",
+        compression.decompress_expr(),
+    )
+    .unwrap();
+}
+
+/// Appends a constant for each proto file in the module, followed by a function
+/// that looks up those constants by the name of the proto file
+fn append_file_descriptors(request: &ModuleRequest, minimized: &Minimized, buffer: &mut String) {
+    let const_names = file_descriptor_const_names(request.files().map(|file| file.name()));
+    let files: Vec<_> = request
+        .files()
+        .zip(const_names)
+        .zip(minimized.raw_files(request))
+        .map(|((file, const_name), raw)| (file.name(), const_name, raw))
+        .collect();
+
+    for (name, const_name, raw) in &files {
+        writeln!(buffer, "/// Encoded file descriptor for `{name}`").unwrap();
+        append_bytes_const(const_name, raw, buffer);
+    }
+
+    buffer.push_str("/// Looks up the encoded file descriptor for a proto file in the `");
+    buffer.push_str(request.proto_package_name());
+    buffer.push_str("` package by name\n");
+    buffer.push_str(
+        "pub fn file_descriptor(name: &str) -> ::core::option::Option<&'static [u8]> {\n",
+    );
+    buffer.push_str("    match name {\n");
+    for (name, const_name, _) in &files {
+        writeln!(
+            buffer,
+            "        {name:?} => ::core::option::Option::Some({const_name}),"
+        )
+        .unwrap();
+    }
+    buffer.push_str("        _ => ::core::option::Option::None,\n");
+    buffer.push_str("    }\n");
+    buffer.push_str("}\n");
+}
+
+/// The names of the constants holding the encoded file descriptors for
+/// each of `proto_names`
+///
+/// Distinct proto files can share a name, such as `a/b_c.proto` and
+/// `a_b/c.proto`, in which case the later files are given the first free
+/// name with a numeric suffix, such as `FILE_DESCRIPTOR_A_B_C_PROTO_2`.
+fn file_descriptor_const_names<'a>(proto_names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let const_names: Vec<String> = proto_names.map(file_descriptor_const_name).collect();
+    let mut taken: HashSet<String> = const_names.iter().cloned().collect();
+    let mut seen = HashSet::new();
+
+    const_names
+        .into_iter()
+        .map(|const_name| {
+            if seen.insert(const_name.clone()) {
+                return const_name;
+            }
+            let unique = (2..)
+                .map(|index| format!("{const_name}_{index}"))
+                .find(|candidate| !taken.contains(candidate))
+                .unwrap();
+            taken.insert(unique.clone());
+            unique
+        })
+        .collect()
+}
+
+/// The name of the constant holding the encoded file descriptor for a proto file
+///
+/// For example, `greeter/v1/greeter.proto` is held in
+/// `FILE_DESCRIPTOR_GREETER_V1_GREETER_PROTO`.
+fn file_descriptor_const_name(proto_name: &str) -> String {
+    let mut const_name = String::from("FILE_DESCRIPTOR_");
+    const_name.extend(proto_name.chars().map(|c| {
+        if c.is_ascii_alphanumeric() {
+            c.to_ascii_uppercase()
+        } else {
+            '_'
+        }
+    }));
+    const_name
+}
 
-    buffer.push_str("pub const FILE_DESCRIPTOR_SET: &[u8] = &[\n");
+/// Appends a constant named `name` holding `encoded` as an embedded array
+///
+/// This cannot be done with another file and `include_bytes!` because the
+/// contract for a file's contents requires that they be valid UTF-8.
+pub(super) fn append_bytes_const(name: &str, encoded: &[u8], buffer: &mut String) {
+    writeln!(buffer, "pub const {name}: &[u8] = &[").unwrap();
 
-    let encoded = file_descriptor_set.encode_to_vec();
     let mut chunks = encoded.chunks_exact(16);
     for chunck in chunks.by_ref() {
         writeln!(
@@ -0,0 +1,221 @@
+//! Insertion points scoped to each message and enum
+//!
+//! For each type, the generated code holds an `attribute:<type>` insertion
+//! point among its attributes, and a `message_scope:<type>` or
+//! `enum_scope:<type>` insertion point after its definition and any nested
+//! module or `impl` blocks, where other plugins can add their own items.
+//!
+//! _Prost!_ has no hook for arbitrary comments, so a sentinel attribute
+//! naming the type is added to each type and replaced once the code has been
+//! generated and formatted. _Prost!_ applies attributes to nested types as
+//! well, so the most specific sentinel on a type is the one naming it.
+
+use prost_types::{DescriptorProto, FileDescriptorProto};
+
+/// The attribute that marks where an insertion point belongs
+const SENTINEL: &str = "#[protoc_insertion_point = \"";
+
+/// Adds a sentinel attribute to each message and enum in `files`
+pub(crate) fn register<'a>(
+    config: &mut prost_build::Config,
+    files: impl IntoIterator<Item = &'a FileDescriptorProto>,
+) {
+    for file in files {
+        let scope = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+        for message in &file.message_type {
+            register_message(config, &scope, message);
+        }
+        for enumeration in &file.enum_type {
+            let name = format!("{scope}.{}", enumeration.name());
+            config.enum_attribute(&name, sentinel("enum_scope", &name));
+        }
+    }
+}
+
+fn register_message(config: &mut prost_build::Config, scope: &str, message: &DescriptorProto) {
+    let name = format!("{scope}.{}", message.name());
+    config.message_attribute(&name, sentinel("message_scope", &name));
+    for nested in &message.nested_type {
+        register_message(config, &name, nested);
+    }
+    for enumeration in &message.enum_type {
+        let name = format!("{name}.{}", enumeration.name());
+        config.enum_attribute(&name, sentinel("enum_scope", &name));
+    }
+}
+
+fn sentinel(kind: &str, name: &str) -> String {
+    format!("{SENTINEL}{kind}:{}\"]", &name[1..])
+}
+
+/// Replaces the sentinel attributes in `content` with insertion points
+pub(crate) fn expand(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut scopes: Vec<(usize, String)> = Vec::new();
+    let mut buffer = String::with_capacity(content.len());
+
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let mut point: Option<&str> = None;
+        while let Some(sentinel) = lines.get(index).and_then(|line| parse_sentinel(line)) {
+            if point.is_none_or(|point| sentinel.len() > point.len()) {
+                point = Some(sentinel);
+            }
+            index += 1;
+        }
+
+        match point {
+            Some(point) => {
+                let name = &point[point.find(':').map_or(0, |i| i + 1)..];
+                push_line(&mut buffer, indent, &format!("attribute:{name}"));
+                scopes.push((end_of_type(&lines, index, indent), point.to_owned()));
+            }
+            None => {
+                buffer.push_str(line);
+                buffer.push('\n');
+                index += 1;
+            }
+        }
+
+        // Types nested within this one close first, so the innermost scope
+        // is written first
+        while let Some(position) = scopes.iter().rposition(|(end, _)| *end < index) {
+            let (end, point) = scopes.remove(position);
+            let line = lines[end];
+            push_line(
+                &mut buffer,
+                &line[..line.len() - line.trim_start().len()],
+                &point,
+            );
+        }
+    }
+
+    buffer
+}
+
+fn parse_sentinel(line: &str) -> Option<&str> {
+    line.trim_start()
+        .strip_prefix(SENTINEL)
+        .and_then(|rest| rest.strip_suffix("\"]"))
+}
+
+fn push_line(buffer: &mut String, indent: &str, point: &str) {
+    buffer.push_str(indent);
+    buffer.push_str("// @@protoc_insertion_point(");
+    buffer.push_str(point);
+    buffer.push_str(")\n");
+}
+
+/// The index of the last line of the type whose definition follows `start`,
+/// including the nested module and `impl` blocks that follow it
+fn end_of_type(lines: &[&str], start: usize, indent: &str) -> usize {
+    let mut end = match (start..lines.len()).find(|&i| is_definition(lines[i], indent)) {
+        Some(definition) => closing_line(lines, definition, indent),
+        None => return lines.len().saturating_sub(1),
+    };
+
+    while let Some(next) = lines.get(end + 1) {
+        let next = next.strip_prefix(indent).unwrap_or_default();
+        let item = if next.starts_with("/// Nested message and enum types in") {
+            (end + 1..lines.len()).find(|&i| {
+                lines[i]
+                    .strip_prefix(indent)
+                    .is_some_and(|l| l.starts_with("pub mod "))
+            })
+        } else if next.starts_with("impl ") {
+            Some(end + 1)
+        } else {
+            None
+        };
+        match item {
+            Some(item) => end = closing_line(lines, item, indent),
+            None => break,
+        }
+    }
+
+    end
+}
+
+fn is_definition(line: &str, indent: &str) -> bool {
+    line.strip_prefix(indent)
+        .is_some_and(|line| line.starts_with("pub struct ") || line.starts_with("pub enum "))
+}
+
+/// The index of the line that closes the item opened at `start`
+fn closing_line(lines: &[&str], start: usize, indent: &str) -> usize {
+    if lines[start].ends_with('}') || lines[start].ends_with(';') {
+        return start;
+    }
+    (start + 1..lines.len())
+        .find(|&i| lines[i].strip_prefix(indent) == Some("}"))
+        .unwrap_or(lines.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentinels_are_replaced_with_scoped_insertion_points() {
+        let content = "\
+#[protoc_insertion_point = \"message_scope:x.M\"]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct M {
+    #[prost(int64, tag = \"1\")]
+    pub id: i64,
+}
+/// Nested message and enum types in `M`.
+pub mod m {
+    #[protoc_insertion_point = \"message_scope:x.M\"]
+    #[protoc_insertion_point = \"message_scope:x.M.Inner\"]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Inner {}
+}
+#[protoc_insertion_point = \"enum_scope:x.E\"]
+#[repr(i32)]
+pub enum E {
+    Unspecified = 0,
+}
+impl E {
+    pub fn as_str_name(&self) -> &'static str {
+        \"E_UNSPECIFIED\"
+    }
+}
+";
+        assert_eq!(
+            expand(content),
+            "\
+// @@protoc_insertion_point(attribute:x.M)
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct M {
+    #[prost(int64, tag = \"1\")]
+    pub id: i64,
+}
+/// Nested message and enum types in `M`.
+pub mod m {
+    // @@protoc_insertion_point(attribute:x.M.Inner)
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Inner {}
+    // @@protoc_insertion_point(message_scope:x.M.Inner)
+}
+// @@protoc_insertion_point(message_scope:x.M)
+// @@protoc_insertion_point(attribute:x.E)
+#[repr(i32)]
+pub enum E {
+    Unspecified = 0,
+}
+impl E {
+    pub fn as_str_name(&self) -> &'static str {
+        \"E_UNSPECIFIED\"
+    }
+}
+// @@protoc_insertion_point(enum_scope:x.E)
+"
+        );
+    }
+}
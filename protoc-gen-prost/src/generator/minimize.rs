@@ -0,0 +1,486 @@
+//! Minimization of encoded file descriptors before they are embedded
+//!
+//! Descriptors are rewritten at the wire level rather than decoded with
+//! `prost-types`, so that custom options and any fields unknown to
+//! `prost-types` are preserved in the embedded bytes.
+
+use std::collections::{HashMap, HashSet};
+
+use prost::{
+    bytes::Buf,
+    encoding::{decode_key, decode_varint, encode_key, encode_varint, WireType},
+};
+use prost_types::FileDescriptorProto;
+
+use crate::{Error, ModuleRequest, ModuleRequestSet};
+
+/// `FieldOptions.retention`
+const RETENTION_FIELD: u32 = 17;
+
+/// `FieldOptions.OptionRetention.RETENTION_SOURCE`
+const RETENTION_SOURCE: u64 = 2;
+
+const FILE_OPTIONS: &str = ".google.protobuf.FileOptions";
+const MESSAGE_OPTIONS: &str = ".google.protobuf.MessageOptions";
+const FIELD_OPTIONS: &str = ".google.protobuf.FieldOptions";
+const ONEOF_OPTIONS: &str = ".google.protobuf.OneofOptions";
+const EXTENSION_RANGE_OPTIONS: &str = ".google.protobuf.ExtensionRangeOptions";
+const ENUM_OPTIONS: &str = ".google.protobuf.EnumOptions";
+const ENUM_VALUE_OPTIONS: &str = ".google.protobuf.EnumValueOptions";
+const SERVICE_OPTIONS: &str = ".google.protobuf.ServiceOptions";
+const METHOD_OPTIONS: &str = ".google.protobuf.MethodOptions";
+
+/// Which information to remove from file descriptors before embedding them
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Minimize {
+    /// Remove `source_code_info`, which holds the locations and comments of
+    /// every declaration
+    pub(crate) strip_source_code_info: bool,
+
+    /// Remove custom options declared with `retention = RETENTION_SOURCE`
+    pub(crate) strip_source_retention: bool,
+
+    /// Remove `json_name` values that match the name `protoc` derives by default
+    pub(crate) strip_default_json_name: bool,
+}
+
+impl Minimize {
+    fn is_enabled(&self) -> bool {
+        self.strip_source_code_info || self.strip_source_retention || self.strip_default_json_name
+    }
+
+    /// Minimizes every file descriptor in the request
+    ///
+    /// When no minimization is requested, the raw file descriptors are
+    /// embedded exactly as they were received.
+    pub(crate) fn apply<'a>(
+        &self,
+        module_request_set: &'a ModuleRequestSet,
+    ) -> Result<Minimized<'a>, Error> {
+        let mut files = HashMap::new();
+        if !self.is_enabled() {
+            return Ok(Minimized { files });
+        }
+
+        let all_files = || {
+            module_request_set
+                .requests()
+                .flat_map(|(_, request)| request.files().zip(request.raw_files()))
+        };
+
+        let mut source_retention = SourceRetention::default();
+        if self.strip_source_retention {
+            for (_, raw) in all_files() {
+                source_retention.collect_file(raw)?;
+            }
+        }
+
+        let rewriter = Rewriter {
+            minimize: *self,
+            source_retention,
+        };
+        for (file, raw) in all_files() {
+            files.insert(file.name(), rewriter.file(raw)?);
+        }
+
+        Ok(Minimized { files })
+    }
+}
+
+/// The minimized file descriptors for a request
+pub(crate) struct Minimized<'a> {
+    files: HashMap<&'a str, Vec<u8>>,
+}
+
+impl Minimized<'_> {
+    /// The encoded file descriptor to embed for `file`
+    pub(crate) fn raw_file<'b>(&'b self, file: &FileDescriptorProto, raw: &'b [u8]) -> &'b [u8] {
+        self.files.get(file.name()).map_or(raw, Vec::as_slice)
+    }
+
+    /// The encoded file descriptors to embed for the files in `request`
+    pub(crate) fn raw_files<'b>(
+        &'b self,
+        request: &'b ModuleRequest,
+    ) -> impl Iterator<Item = &'b [u8]> {
+        request
+            .files()
+            .zip(request.raw_files())
+            .map(move |(file, raw)| self.raw_file(file, raw))
+    }
+}
+
+/// The custom options declared with `retention = RETENTION_SOURCE`, as field
+/// numbers keyed by the options message that they extend
+#[derive(Default)]
+struct SourceRetention {
+    options: HashMap<String, HashSet<u32>>,
+}
+
+impl SourceRetention {
+    fn collect_file(&mut self, raw: &[u8]) -> Result<(), Error> {
+        for field in fields(raw)? {
+            match (field.number, field.value) {
+                (4, Value::Bytes(message)) => self.collect_message(message)?,
+                (7, Value::Bytes(extension)) => self.collect_extension(extension)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_message(&mut self, raw: &[u8]) -> Result<(), Error> {
+        for field in fields(raw)? {
+            match (field.number, field.value) {
+                (3, Value::Bytes(nested)) => self.collect_message(nested)?,
+                (6, Value::Bytes(extension)) => self.collect_extension(extension)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_extension(&mut self, raw: &[u8]) -> Result<(), Error> {
+        let mut extendee = None;
+        let mut number = None;
+        let mut source_retention = false;
+        for field in fields(raw)? {
+            match (field.number, field.value) {
+                (2, Value::Bytes(name)) => extendee = Some(String::from_utf8_lossy(name)),
+                (3, Value::Varint(value)) => number = Some(value as u32),
+                (8, Value::Bytes(options)) => {
+                    source_retention = fields(options)?.iter().any(|option| {
+                        option.number == RETENTION_FIELD
+                            && option.value == Value::Varint(RETENTION_SOURCE)
+                    })
+                }
+                _ => (),
+            }
+        }
+
+        if let (Some(extendee), Some(number), true) = (extendee, number, source_retention) {
+            self.options
+                .entry(extendee.into_owned())
+                .or_default()
+                .insert(number);
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites encoded descriptors, following the structure of `descriptor.proto`
+struct Rewriter {
+    minimize: Minimize,
+    source_retention: SourceRetention,
+}
+
+impl Rewriter {
+    /// `FileDescriptorProto`
+    fn file(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        rewrite(raw, |number, value| match number {
+            4 => self.message(value).map(Some),
+            5 => self.enumeration(value).map(Some),
+            6 => self.service(value).map(Some),
+            7 => self.field(value).map(Some),
+            8 => self.options(FILE_OPTIONS, value).map(Some),
+            9 if self.minimize.strip_source_code_info => Ok(None),
+            _ => Ok(Some(value.to_vec())),
+        })
+    }
+
+    /// `DescriptorProto`
+    fn message(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        rewrite(raw, |number, value| match number {
+            2 | 6 => self.field(value).map(Some),
+            3 => self.message(value).map(Some),
+            4 => self.enumeration(value).map(Some),
+            5 => self.nested_options(3, EXTENSION_RANGE_OPTIONS, value),
+            7 => self.options(MESSAGE_OPTIONS, value).map(Some),
+            8 => self.nested_options(2, ONEOF_OPTIONS, value),
+            _ => Ok(Some(value.to_vec())),
+        })
+    }
+
+    /// `FieldDescriptorProto`
+    fn field(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        let name = fields(raw)?
+            .into_iter()
+            .find(|field| field.number == 1)
+            .and_then(|field| match field.value {
+                Value::Bytes(name) => Some(String::from_utf8_lossy(name)),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let default_json_name = to_json_name(&name);
+
+        rewrite(raw, |number, value| match number {
+            8 => self.options(FIELD_OPTIONS, value).map(Some),
+            10 if self.minimize.strip_default_json_name
+                && value == default_json_name.as_bytes() =>
+            {
+                Ok(None)
+            }
+            _ => Ok(Some(value.to_vec())),
+        })
+    }
+
+    /// `EnumDescriptorProto`
+    fn enumeration(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        rewrite(raw, |number, value| match number {
+            2 => self.nested_options(3, ENUM_VALUE_OPTIONS, value),
+            3 => self.options(ENUM_OPTIONS, value).map(Some),
+            _ => Ok(Some(value.to_vec())),
+        })
+    }
+
+    /// `ServiceDescriptorProto`
+    fn service(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        rewrite(raw, |number, value| match number {
+            2 => self.nested_options(4, METHOD_OPTIONS, value),
+            3 => self.options(SERVICE_OPTIONS, value).map(Some),
+            _ => Ok(Some(value.to_vec())),
+        })
+    }
+
+    /// A descriptor whose only rewritten field is its options, held in `options_field`
+    fn nested_options(
+        &self,
+        options_field: u32,
+        options_type: &str,
+        raw: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        rewrite(raw, |number, value| {
+            if number == options_field {
+                self.options(options_type, value).map(Some)
+            } else {
+                Ok(Some(value.to_vec()))
+            }
+        })
+        .map(Some)
+    }
+
+    /// An options message of the given type, such as `.google.protobuf.FileOptions`
+    fn options(&self, options_type: &str, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        let source_retention = match self.source_retention.options.get(options_type) {
+            Some(numbers) if self.minimize.strip_source_retention => numbers,
+            _ => return Ok(raw.to_vec()),
+        };
+
+        let mut out = Vec::with_capacity(raw.len());
+        for field in fields(raw)? {
+            if !source_retention.contains(&field.number) {
+                out.extend_from_slice(field.encoded);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Rewrites each length-delimited field of an encoded message with `f`,
+/// dropping the field if `f` returns `None`
+///
+/// All other fields are copied as they are.
+fn rewrite<F>(raw: &[u8], mut f: F) -> Result<Vec<u8>, Error>
+where
+    F: FnMut(u32, &[u8]) -> Result<Option<Vec<u8>>, Error>,
+{
+    let mut out = Vec::with_capacity(raw.len());
+    for field in fields(raw)? {
+        match field.value {
+            Value::Bytes(value) => {
+                if let Some(value) = f(field.number, value)? {
+                    encode_key(field.number, WireType::LengthDelimited, &mut out);
+                    encode_varint(value.len() as u64, &mut out);
+                    out.extend_from_slice(&value);
+                }
+            }
+            _ => out.extend_from_slice(field.encoded),
+        }
+    }
+    Ok(out)
+}
+
+/// A field of an encoded message
+struct Field<'a> {
+    number: u32,
+    value: Value<'a>,
+
+    /// The complete encoding of the field, including its key
+    encoded: &'a [u8],
+}
+
+#[derive(PartialEq)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Other,
+}
+
+/// Splits an encoded message into its fields
+fn fields(raw: &[u8]) -> Result<Vec<Field<'_>>, Error> {
+    let mut fields = Vec::new();
+    let mut buf = raw;
+    while buf.has_remaining() {
+        let start = raw.len() - buf.len();
+        let (number, wire_type) = decode_key(&mut buf)?;
+        let value = match wire_type {
+            WireType::Varint => Value::Varint(decode_varint(&mut buf)?),
+            WireType::LengthDelimited => {
+                let len = decode_varint(&mut buf)? as usize;
+                if len > buf.len() {
+                    return Err(Error::from("buffer underflow"));
+                }
+                let (value, rest) = buf.split_at(len);
+                buf = rest;
+                Value::Bytes(value)
+            }
+            WireType::SixtyFourBit => {
+                skip(&mut buf, 8)?;
+                Value::Other
+            }
+            WireType::ThirtyTwoBit => {
+                skip(&mut buf, 4)?;
+                Value::Other
+            }
+            WireType::StartGroup | WireType::EndGroup => {
+                return Err(Error::from("unexpected group in file descriptor"));
+            }
+        };
+
+        fields.push(Field {
+            number,
+            value,
+            encoded: &raw[start..raw.len() - buf.len()],
+        });
+    }
+    Ok(fields)
+}
+
+fn skip(buf: &mut &[u8], len: usize) -> Result<(), Error> {
+    if len > buf.len() {
+        return Err(Error::from("buffer underflow"));
+    }
+    buf.advance(len);
+    Ok(())
+}
+
+/// The JSON name that `protoc` derives for a field by default
+fn to_json_name(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut capitalize_next = false;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize_next = true;
+        } else if capitalize_next {
+            json_name.push(c.to_ascii_uppercase());
+            capitalize_next = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, SourceCodeInfo,
+    };
+
+    use super::*;
+
+    /// Appends a length-delimited field to an encoded message
+    fn append_bytes(buf: &mut Vec<u8>, number: u32, value: &[u8]) {
+        encode_key(number, WireType::LengthDelimited, buf);
+        encode_varint(value.len() as u64, buf);
+        buf.extend_from_slice(value);
+    }
+
+    fn field(name: &str, number: i32, json_name: &str) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(Type::String as i32),
+            json_name: Some(json_name.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn minimized_descriptors_keep_runtime_information() {
+        // extend google.protobuf.FieldOptions {
+        //   string internal = 50000 [retention = RETENTION_SOURCE];
+        //   string visible = 50001;
+        // }
+        let mut internal = FieldDescriptorProto {
+            extendee: Some(FIELD_OPTIONS.to_owned()),
+            ..field("internal", 50000, "internal")
+        }
+        .encode_to_vec();
+        let mut retention = Vec::new();
+        encode_key(RETENTION_FIELD, WireType::Varint, &mut retention);
+        encode_varint(RETENTION_SOURCE, &mut retention);
+        append_bytes(&mut internal, 8, &retention);
+        let visible = FieldDescriptorProto {
+            extendee: Some(FIELD_OPTIONS.to_owned()),
+            ..field("visible", 50001, "visible")
+        }
+        .encode_to_vec();
+
+        // string user_id = 1 [(internal) = "secret", (visible) = "kept"];
+        let mut user_id = field("user_id", 1, "userId").encode_to_vec();
+        let mut options = Vec::new();
+        append_bytes(&mut options, 50000, b"secret");
+        append_bytes(&mut options, 50001, b"kept");
+        append_bytes(&mut user_id, 8, &options);
+
+        let mut message = DescriptorProto {
+            name: Some("Example".to_owned()),
+            field: vec![field("name", 2, "fullName")],
+            ..Default::default()
+        }
+        .encode_to_vec();
+        append_bytes(&mut message, 2, &user_id);
+
+        let mut file = FileDescriptorProto {
+            name: Some("example.proto".to_owned()),
+            package: Some("example".to_owned()),
+            source_code_info: Some(SourceCodeInfo::default()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        append_bytes(&mut file, 4, &message);
+        append_bytes(&mut file, 7, &internal);
+        append_bytes(&mut file, 7, &visible);
+
+        let mut source_retention = SourceRetention::default();
+        source_retention.collect_file(&file).unwrap();
+        let rewriter = Rewriter {
+            minimize: Minimize {
+                strip_source_code_info: true,
+                strip_source_retention: true,
+                strip_default_json_name: true,
+            },
+            source_retention,
+        };
+        let minimized = rewriter.file(&file).unwrap();
+
+        let contains = |needle: &[u8]| minimized.windows(needle.len()).any(|w| w == needle);
+        assert!(!contains(b"secret"));
+        assert!(contains(b"kept"));
+
+        let decoded = FileDescriptorProto::decode(minimized.as_slice()).unwrap();
+        assert_eq!(decoded.source_code_info, None);
+        let json_names: Vec<_> = decoded.message_type[0]
+            .field
+            .iter()
+            .map(|field| (field.name(), field.json_name.as_deref()))
+            .collect();
+        assert_eq!(json_names, [("name", Some("fullName")), ("user_id", None)]);
+        assert_eq!(decoded.extension[0].json_name, None);
+        assert!(decoded.message_type[0].field[1].options.is_some());
+    }
+}
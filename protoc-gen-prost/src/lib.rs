@@ -4,80 +4,92 @@ use std::{
     borrow::Cow,
     collections::{BTreeMap, HashSet},
     fmt,
+    num::NonZeroUsize,
     str::{self},
+    thread,
 };
 
 use once_cell::sync::Lazy;
-use prost::Message;
-use prost_build::Module;
-use prost_types::{
-    compiler::{code_generator_response::File, CodeGeneratorRequest},
-    DescriptorProto, FileDescriptorProto,
+use prost::{bytes::Bytes, Message};
+use prost_build::{Comments, Module};
+use prost_types::{compiler::code_generator_response::File, DescriptorProto, FileDescriptorProto};
+
+use self::{
+    generator::{
+        Compression, CoreProstGenerator, DescriptorPoolGenerator, FileDescriptorSetGenerator,
+        Minimize, OutputLayout,
+    },
+    path_pattern::{Candidates, Names, PathPatterns},
 };
 
-use self::generator::{CoreProstGenerator, FileDescriptorSetGenerator};
-
+mod cache;
+mod cli;
+mod config;
+mod diagnostics;
+mod doc_links;
+mod editions;
 mod generator;
-
-pub use self::generator::{Error, Generator, GeneratorResultExt, Result};
+mod path_pattern;
+mod replay;
+mod resolver;
+mod rust_options;
+mod strict;
+pub mod testing;
+
+pub use self::{
+    cache::GenerationCache,
+    cli::{plugin_main, run_generate_command, write_files},
+    config::PluginOpts,
+    diagnostics::{InvalidParameter, ParamSpec, ParameterError, ParameterErrorKind, PluginSpec},
+    doc_links::DocLinks,
+    editions::{MAXIMUM_EDITION, MINIMUM_EDITION},
+    generator::{
+        deprecation_note, has_deprecated_top_level_types, CodeGeneratorResponse, Error, Generator,
+        GeneratorResultExt, Result,
+    },
+    replay::{dump_request, replay_request},
+    resolver::Resolver,
+    strict::strict_extern_paths,
+};
 
 /// Execute the core _Prost!_ generator from an encoded [`CodeGeneratorRequest`]
 pub fn execute(raw_request: &[u8]) -> generator::Result {
-    let request = CodeGeneratorRequest::decode(raw_request)?;
-    let params = request.parameter().parse::<Parameters>()?;
-
-    let module_request_set = ModuleRequestSet::new(
-        request.file_to_generate,
-        request.proto_file,
-        raw_request,
-        params.prost.default_package_filename(),
-        params.prost.flat_output_dir,
-    )?;
-
-    let file_descriptor_set_generator = if params.file_descriptor_set {
-        Some(if params.prost_reflect {
-            // When using prost-reflect, we need to include all dependencies
-            FileDescriptorSetGenerator::with_all_dependencies()
-        } else {
-            // Normal mode: only include files from each module
-            FileDescriptorSetGenerator::new()
-        })
-    } else {
-        None
-    };
-
-    let mut config = params.prost.to_prost_config();
-
-    if params.file_descriptor_set && params.prost_reflect {
-        let mut messages = Vec::new();
-        for (_, request) in module_request_set.requests() {
-            for file in request.files() {
-                collect_message_names(file.package(), &file.message_type, &mut messages);
-            }
-        }
-
-        for full_name in &messages {
-            config
-                .type_attribute(full_name, "#[derive(::prost_reflect::ReflectMessage)]")
-                .type_attribute(
-                    full_name,
-                    // This relies on the fact that file_descriptor_set_generator will create a
-                    // variable named FILE_DESCRIPTOR_SET which contains the
-                    // raw bytes of the file descriptor set.
-                    r#"#[prost_reflect(file_descriptor_set_bytes = "FILE_DESCRIPTOR_SET")]"#,
-                )
-                .type_attribute(
-                    full_name,
-                    format!("#[prost_reflect(message_name = \"{}\")]", full_name),
-                );
-        }
+    let request = PluginRequest::decode(raw_request)?;
+    let parameter = request.parameter().to_owned();
+    let opts = PluginOpts::load(&parameter, &PLUGIN)?;
+    dump_request(raw_request, env!("CARGO_PKG_VERSION"), &[&opts])?;
+    let params = Parameters::from_opts(&opts)?;
+
+    let mut module_request_set = params.module_request_set(request)?;
+    let mut generator = params.into_generator(&module_request_set)?;
+
+    match GenerationCache::from_opts(&opts, env!("CARGO_PKG_VERSION")) {
+        Some(cache) => cache.generate(&mut module_request_set, &mut generator),
+        None => generator.generate(&module_request_set),
     }
+}
 
-    let files = CoreProstGenerator::new(config)
-        .chain(file_descriptor_set_generator)
-        .generate(&module_request_set)?;
+/// Builds the modules for a request as configured by the options of
+/// `protoc-gen-prost`
+///
+/// Used by combined plugins, so that every generator shares the same modules.
+pub fn module_request_set(
+    request: PluginRequest,
+    opts: &PluginOpts<'_>,
+) -> std::result::Result<ModuleRequestSet, Error> {
+    Parameters::from_opts(opts)?.module_request_set(request)
+}
 
-    Ok(files)
+/// Creates the generators of `protoc-gen-prost` as configured by `opts`, for
+/// running as part of a combined plugin
+///
+/// Attributes declared in the proto files of `module_request_set`, or with
+/// patterns in `opts`, are resolved against its modules.
+pub fn generator(
+    opts: &PluginOpts<'_>,
+    module_request_set: &ModuleRequestSet,
+) -> std::result::Result<impl Generator, Error> {
+    Parameters::from_opts(opts)?.into_generator(module_request_set)
 }
 
 /// Recursively collect the fully-qualified names of all messages, including
@@ -90,6 +102,57 @@ fn collect_message_names(package_name: &str, messages: &[DescriptorProto], out:
     }
 }
 
+/// A [`CodeGeneratorRequest`] decoded in a single pass
+///
+/// Each proto file is decoded once, while its encoded bytes are kept as a view
+/// into a single copy of the request, so that they can be embedded into the
+/// generated code as they were received.
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+pub struct PluginRequest {
+    file_to_generate: Vec<String>,
+    parameter: Option<String>,
+    proto_file: Vec<FileDescriptorProto>,
+    raw_proto_file: Vec<Bytes>,
+}
+
+impl PluginRequest {
+    /// Decodes an encoded [`CodeGeneratorRequest`]
+    ///
+    /// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+    pub fn decode(raw_request: &[u8]) -> std::result::Result<Self, Error> {
+        let raw_protos = RawProtos::decode(Bytes::copy_from_slice(raw_request))?;
+        let proto_file = raw_protos
+            .proto_file
+            .iter()
+            .map(|raw| FileDescriptorProto::decode(raw.as_ref()))
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(Self {
+            file_to_generate: raw_protos.file_to_generate,
+            parameter: raw_protos.parameter,
+            proto_file,
+            raw_proto_file: raw_protos.proto_file,
+        })
+    }
+
+    /// The parameter passed to the plugin
+    pub fn parameter(&self) -> &str {
+        self.parameter.as_deref().unwrap_or_default()
+    }
+
+    /// The names of the proto files for which code is requested
+    pub fn file_to_generate(&self) -> &[String] {
+        &self.file_to_generate
+    }
+
+    /// The descriptors of the proto files for which code is requested, along
+    /// with those of every file that they import
+    pub fn proto_files(&self) -> &[FileDescriptorProto] {
+        &self.proto_file
+    }
+}
+
 /// A set of requests to generate code for a series of modules
 pub struct ModuleRequestSet {
     requests: BTreeMap<Module, ModuleRequest>,
@@ -98,6 +161,12 @@ pub struct ModuleRequestSet {
 impl ModuleRequestSet {
     /// Construct a new module request set from an encoded [`CodeGeneratorRequest`]
     ///
+    /// Files declaring an edition are resolved into equivalent `proto3`
+    /// descriptors, while the raw file descriptors are left untouched.
+    ///
+    /// Prefer [`from_request()`][Self::from_request()], which does not decode
+    /// the request a second time.
+    ///
     /// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
     pub fn new<I>(
         input_protos: I,
@@ -105,56 +174,74 @@ impl ModuleRequestSet {
         raw_request: &[u8],
         default_package_filename: Option<&str>,
         flat_output_dir: bool,
-    ) -> std::result::Result<Self, prost::DecodeError>
+    ) -> std::result::Result<Self, Error>
     where
         I: IntoIterator<Item = String>,
     {
         let raw_protos = RawProtos::decode(raw_request)?;
 
-        Ok(Self::new_decoded(
+        Self::new_decoded(
             input_protos,
             proto_file,
-            raw_protos,
+            raw_protos.proto_file,
             default_package_filename.unwrap_or("_"),
             flat_output_dir,
-        ))
+        )
+    }
+
+    /// Construct a new module request set from a decoded [`PluginRequest`]
+    ///
+    /// Files declaring an edition are resolved into equivalent `proto3`
+    /// descriptors, while the raw file descriptors are left untouched.
+    pub fn from_request(
+        request: PluginRequest,
+        default_package_filename: Option<&str>,
+        flat_output_dir: bool,
+    ) -> std::result::Result<Self, Error> {
+        Self::new_decoded(
+            request.file_to_generate,
+            request.proto_file,
+            request.raw_proto_file,
+            default_package_filename.unwrap_or("_"),
+            flat_output_dir,
+        )
     }
 
     fn new_decoded<I>(
         input_protos: I,
         proto_file: Vec<FileDescriptorProto>,
-        raw_protos: RawProtos,
+        raw_proto_file: Vec<Bytes>,
         default_package_filename: &str,
         flat_output_dir: bool,
-    ) -> Self
+    ) -> std::result::Result<Self, Error>
     where
         I: IntoIterator<Item = String>,
     {
         let input_protos: HashSet<_> = input_protos.into_iter().collect();
 
-        let requests = proto_file.into_iter().zip(raw_protos.proto_file).fold(
-            BTreeMap::new(),
-            |mut acc, (proto, raw)| {
-                let module = Module::from_protobuf_package_name(proto.package());
-                let proto_filename = proto.name();
-                let entry = acc.entry(module.clone()).or_insert_with(|| {
-                    ModuleRequest::new(proto.package().to_owned(), module, flat_output_dir)
-                });
-
-                if entry.output_filename().is_none() && input_protos.contains(proto_filename) {
-                    let filename = match proto.package() {
-                        "" => default_package_filename.to_owned(),
-                        package => format!("{package}.rs"),
-                    };
-                    entry.with_output_filename(filename);
-                }
+        let mut requests = BTreeMap::new();
+        for (mut proto, raw) in proto_file.into_iter().zip(raw_proto_file) {
+            editions::resolve(&mut proto, &raw)?;
+
+            let module = Module::from_protobuf_package_name(proto.package());
+            let proto_filename = proto.name();
+            let entry = requests.entry(module.clone()).or_insert_with(|| {
+                ModuleRequest::new(proto.package().to_owned(), module, flat_output_dir)
+            });
+
+            let requested = input_protos.contains(proto_filename);
+            if entry.output_filename().is_none() && requested {
+                let filename = match proto.package() {
+                    "" => default_package_filename.to_owned(),
+                    package => format!("{package}.rs"),
+                };
+                entry.with_output_filename(filename);
+            }
 
-                entry.push_file_descriptor_proto(proto, raw);
-                acc
-            },
-        );
+            entry.push_file_descriptor_proto(proto, raw, requested);
+        }
 
-        Self { requests }
+        Ok(Self { requests })
     }
 
     /// An ordered iterator of all requests
@@ -179,9 +266,13 @@ pub struct ModuleRequest {
     flat_output_dir: bool,
     output_filename: Option<String>,
     files: Vec<FileDescriptorProto>,
-    raw: Vec<Vec<u8>>,
+    raw: Vec<Bytes>,
+    requested: Vec<bool>,
 }
 
+/// The field number of `package` in a `FileDescriptorProto`
+const PACKAGE_PATH: i32 = 2;
+
 impl ModuleRequest {
     fn new(proto_package_name: String, module: Module, flat_output_dir: bool) -> Self {
         Self {
@@ -191,6 +282,7 @@ impl ModuleRequest {
             output_filename: None,
             files: Vec::new(),
             raw: Vec::new(),
+            requested: Vec::new(),
         }
     }
 
@@ -198,9 +290,15 @@ impl ModuleRequest {
         self.output_filename = Some(filename);
     }
 
-    fn push_file_descriptor_proto(&mut self, encoded: FileDescriptorProto, raw: Vec<u8>) {
+    fn push_file_descriptor_proto(
+        &mut self,
+        encoded: FileDescriptorProto,
+        raw: Bytes,
+        requested: bool,
+    ) {
         self.files.push(encoded);
         self.raw.push(raw);
+        self.requested.push(requested);
     }
 
     /// The protobuf package name for this module
@@ -236,9 +334,53 @@ impl ModuleRequest {
         self.files.iter()
     }
 
+    /// An iterator of the file descriptors of the files for which code was
+    /// requested
+    pub fn requested_files(&self) -> impl Iterator<Item = &FileDescriptorProto> {
+        self.files
+            .iter()
+            .zip(&self.requested)
+            .filter_map(|(file, &requested)| requested.then_some(file))
+    }
+
     /// An iterator of the encoded [`FileDescriptorProto`]s from [`files()`][Self::files()]
     pub fn raw_files(&self) -> impl Iterator<Item = &[u8]> {
-        self.raw.iter().map(|b| b.as_slice())
+        self.raw.iter().map(|b| b.as_ref())
+    }
+
+    /// Documentation for the module, from the leading comments on the
+    /// `package` statement of each file, followed by a list of the files
+    ///
+    /// Files in the same package often repeat the same comment, so each
+    /// distinct comment is included once, in the order of the files.
+    pub fn package_docs(&self) -> Comments {
+        let mut comments: Vec<&str> = Vec::new();
+        for file in &self.files {
+            let comment = file
+                .source_code_info
+                .as_ref()
+                .and_then(|info| info.location.iter().find(|l| l.path == [PACKAGE_PATH]))
+                .and_then(|location| location.leading_comments.as_deref());
+            if let Some(comment) = comment {
+                if !comments.contains(&comment) {
+                    comments.push(comment);
+                }
+            }
+        }
+
+        let mut leading = Vec::new();
+        for comment in comments {
+            leading.extend(comment.lines().map(str::to_owned));
+            leading.push(String::new());
+        }
+        leading.push("Generated from:".to_owned());
+        leading.push(String::new());
+        leading.extend(self.files.iter().map(|file| format!("* `{}`", file.name())));
+
+        Comments {
+            leading,
+            ..Default::default()
+        }
     }
 
     /// Creates a code generation file from the output
@@ -285,25 +427,63 @@ struct Parameters {
     /// Whether a file descriptor set has been requested in each module
     file_descriptor_set: bool,
 
+    /// Whether the file descriptor set is written to a separate file for each module
+    separate_file_descriptor_set: bool,
+
+    /// Whether the encoded file descriptor of each proto file is also generated
+    per_file_descriptors: bool,
+
+    /// Whether the file descriptor set includes the transitive imports of each module
+    imported_file_descriptors: bool,
+
+    /// Which information is removed from file descriptors before they are embedded
+    minimize_file_descriptors: Minimize,
+
+    /// How the file descriptor set is compressed before it is embedded, if at all
+    file_descriptor_set_compression: Option<Compression>,
+
     /// Whether to generate prost-reflect trait implementations for the generated
     /// rust types using prost-reflect-build
     prost_reflect: bool,
+
+    /// How the generated code for each package is split into files
+    output_layout: OutputLayout,
+
+    /// The Rust path of a descriptor pool shared by every package, used to implement
+    /// prost-reflect traits for the generated types
+    descriptor_pool: Option<String>,
+
+    /// The maximum number of threads used to generate modules, which defaults
+    /// to the available parallelism
+    jobs: Option<usize>,
+
+    /// Whether the generated code is pretty-printed
+    format: bool,
+
+    /// Whether insertion points are added for each message and enum
+    insertion_points: bool,
+
+    /// Whether references to types in comments are turned into intra-doc links
+    doc_links: bool,
+
+    /// Whether only the types of the requested files are generated
+    strict: bool,
 }
 
 /// Parameters used to configure the underlying Prost generator
 #[derive(Debug, Default)]
 struct ProstParameters {
-    btree_map: Vec<String>,
-    bytes: Vec<String>,
-    boxed: Vec<String>,
+    btree_map: PathPatterns<()>,
+    bytes: PathPatterns<()>,
+    boxed: PathPatterns<()>,
     disable_comments: Vec<String>,
     skip_debug: Vec<String>,
     default_package_filename: Option<String>,
     extern_path: Vec<(String, String)>,
-    type_attribute: Vec<(String, String)>,
-    field_attribute: Vec<(String, String)>,
-    enum_attribute: Vec<(String, String)>,
-    message_attribute: Vec<(String, String)>,
+    type_attribute: PathPatterns<String>,
+    field_attribute: PathPatterns<String>,
+    enum_attribute: PathPatterns<String>,
+    message_attribute: PathPatterns<String>,
     compile_well_known_types: bool,
     retain_enum_prefix: bool,
     enable_type_names: bool,
@@ -311,13 +491,33 @@ struct ProstParameters {
 }
 
 impl ProstParameters {
+    /// Resolves proto path patterns against the types and fields in the request
+    fn resolve_path_patterns(
+        &mut self,
+        module_request_set: &ModuleRequestSet,
+    ) -> std::result::Result<(), Error> {
+        let names = Names::new(module_request_set);
+        self.btree_map.resolve(&names, Candidates::Fields)?;
+        self.bytes.resolve(&names, Candidates::Fields)?;
+        self.boxed.resolve(&names, Candidates::BoxedFields)?;
+        self.type_attribute.resolve(&names, Candidates::Types)?;
+        self.field_attribute
+            .resolve(&names, Candidates::FieldAttributes)?;
+        self.enum_attribute.resolve(&names, Candidates::Enums)?;
+        self.message_attribute
+            .resolve(&names, Candidates::Messages)?;
+        Ok(())
+    }
+
     /// Builds a [`prost_build::Config`] from the parameters
+    ///
+    /// Proto path patterns must have been resolved first.
     fn to_prost_config(&self) -> prost_build::Config {
         let mut config = prost_build::Config::new();
-        config.btree_map(self.btree_map.iter());
-        config.bytes(self.bytes.iter());
-        for b in self.boxed.iter() {
-            config.boxed(b);
+        config.btree_map(self.btree_map.paths().map(|(proto_path, ())| proto_path));
+        config.bytes(self.bytes.paths().map(|(proto_path, ())| proto_path));
+        for (proto_path, ()) in self.boxed.paths() {
+            config.boxed(proto_path);
         }
         config.disable_comments(self.disable_comments.iter());
         config.skip_debug(self.skip_debug.iter());
@@ -329,16 +529,16 @@ impl ProstParameters {
         for (proto_path, rust_path) in &self.extern_path {
             config.extern_path(proto_path, rust_path);
         }
-        for (proto_path, attribute) in &self.type_attribute {
+        for (proto_path, attribute) in self.type_attribute.paths() {
             config.type_attribute(proto_path, attribute);
         }
-        for (proto_path, attribute) in &self.field_attribute {
+        for (proto_path, attribute) in self.field_attribute.paths() {
             config.field_attribute(proto_path, attribute);
         }
-        for (proto_path, attribute) in &self.enum_attribute {
+        for (proto_path, attribute) in self.enum_attribute.paths() {
             config.enum_attribute(proto_path, attribute);
         }
-        for (proto_path, attribute) in &self.message_attribute {
+        for (proto_path, attribute) in self.message_attribute.paths() {
             config.message_attribute(proto_path, attribute);
         }
 
@@ -364,15 +564,15 @@ impl ProstParameters {
             Param::Value {
                 param: "btree_map",
                 value,
-            } => self.btree_map.push(value.to_string()),
+            } => self.btree_map.push(value, ()),
             Param::Value {
                 param: "bytes",
                 value,
-            } => self.bytes.push(value.to_string()),
+            } => self.bytes.push(value, ()),
             Param::Value {
                 param: "boxed",
                 value,
-            } => self.boxed.push(value.to_string()),
+            } => self.boxed.push(value, ()),
             Param::Parameter {
                 param: "default_package_filename",
             }
@@ -419,34 +619,46 @@ impl ProstParameters {
                 param: "type_attribute",
                 key: prefix,
                 value: module,
-            } => self.type_attribute.push((
-                prefix.to_string(),
-                module.replace(r"\,", ",").replace(r"\\", r"\"),
-            )),
+            } => self
+                .type_attribute
+                .push(prefix, module.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::Value {
+                param: "type_attribute",
+                value,
+            } if path_pattern::is_exclusion(value) => self.type_attribute.exclude(value),
             Param::KeyValue {
                 param: "field_attribute",
                 key: prefix,
                 value: module,
-            } => self.field_attribute.push((
-                prefix.to_string(),
-                module.replace(r"\,", ",").replace(r"\\", r"\"),
-            )),
+            } => self
+                .field_attribute
+                .push(prefix, module.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::Value {
+                param: "field_attribute",
+                value,
+            } if path_pattern::is_exclusion(value) => self.field_attribute.exclude(value),
             Param::KeyValue {
                 param: "enum_attribute",
                 key: prefix,
                 value: module,
-            } => self.enum_attribute.push((
-                prefix.to_string(),
-                module.replace(r"\,", ",").replace(r"\\", r"\"),
-            )),
+            } => self
+                .enum_attribute
+                .push(prefix, module.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::Value {
+                param: "enum_attribute",
+                value,
+            } if path_pattern::is_exclusion(value) => self.enum_attribute.exclude(value),
             Param::KeyValue {
                 param: "message_attribute",
                 key: prefix,
                 value: module,
-            } => self.message_attribute.push((
-                prefix.to_string(),
-                module.replace(r"\,", ",").replace(r"\\", r"\"),
-            )),
+            } => self
+                .message_attribute
+                .push(prefix, module.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::Value {
+                param: "message_attribute",
+                value,
+            } if path_pattern::is_exclusion(value) => self.message_attribute.exclude(value),
             Param::Parameter {
                 param: "enable_type_names",
             }
@@ -492,7 +704,7 @@ impl ProstParameters {
 ///   if that `,` is prefixed by `\` but not `\\`, then it will not terminate.
 static PARAMETER: Lazy<regex::Regex> = Lazy::new(|| {
     regex::Regex::new(
-        r"(?:(?P<param>[^,=]+)(?:=(?P<key>[^,=]+)(?:=(?P<value>(?:[^,\\]|\\,|\\\\)+))?)?)",
+        r"^(?P<param>[^,=]+)(?:=(?P<key>[^,=]+)(?:=(?P<value>(?:[^,\\]|\\,|\\\\)+))?)?$",
     )
     .unwrap()
 });
@@ -510,7 +722,7 @@ impl<'a> IntoIterator for Params<'a> {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Param<'a> {
     Parameter {
         param: &'a str,
@@ -527,6 +739,28 @@ pub enum Param<'a> {
 }
 
 impl<'a> Param<'a> {
+    /// The name of the parameter
+    pub fn name(&self) -> &'a str {
+        match *self {
+            Self::Parameter { param }
+            | Self::Value { param, .. }
+            | Self::KeyValue { param, .. } => param,
+        }
+    }
+
+    /// The same parameter under another name
+    pub fn with_name(self, name: &'a str) -> Self {
+        match self {
+            Self::Parameter { .. } => Self::Parameter { param: name },
+            Self::Value { value, .. } => Self::Value { param: name, value },
+            Self::KeyValue { key, value, .. } => Self::KeyValue {
+                param: name,
+                key,
+                value,
+            },
+        }
+    }
+
     pub fn value(self) -> Option<Cow<'a, str>> {
         match self {
             Self::Parameter { .. } => None,
@@ -536,127 +770,470 @@ impl<'a> Param<'a> {
     }
 }
 
-impl From<Param<'_>> for InvalidParameter {
-    fn from(param: Param<'_>) -> Self {
-        let message = match param {
-            Param::Parameter { param } => param.to_owned(),
-            Param::Value { param, value } => format!("{param}={value}"),
-            Param::KeyValue { param, key, value } => {
+impl fmt::Display for Param<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parameter { param } => f.write_str(param),
+            Self::Value { param, value } => write!(f, "{param}={value}"),
+            Self::KeyValue { param, key, value } => {
                 let value = value.replace('\\', r"\\").replace(',', r"\,");
-                format!("{param}={key}={value}")
+                write!(f, "{param}={key}={value}")
             }
-        };
-        InvalidParameter(message)
+        }
     }
 }
 
 impl<'a> Params<'a> {
-    pub fn from_protoc_plugin_opts(s: &'a str) -> std::result::Result<Self, InvalidParameter> {
-        let params = PARAMETER
-            .captures_iter(s)
-            .map(|capture| {
-                let param = capture
-                    .get(1)
-                    .expect("any captured group will at least have the param name")
-                    .as_str()
-                    .trim();
-
-                let key = capture.get(2).map(|m| m.as_str());
-                let value = capture.get(3).map(|m| m.as_str());
-
-                match (key, value) {
-                    (None, None) => Ok(Param::Parameter { param }),
-                    (Some(value), None) => Ok(Param::Value { param, value }),
-                    (Some(key), Some(value)) => Ok(Param::KeyValue {
+    /// Parses the comma-separated parameters passed to a plugin, reporting
+    /// every malformed parameter
+    pub fn from_protoc_plugin_opts(s: &'a str) -> std::result::Result<Self, Vec<ParameterError>> {
+        let (params, errors) = Self::parse(s);
+        if errors.is_empty() {
+            Ok(params)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses the comma-separated parameters passed to a plugin, along with
+    /// an error for each malformed parameter
+    pub(crate) fn parse(s: &'a str) -> (Self, Vec<ParameterError>) {
+        let mut params = Vec::new();
+        let mut errors = Vec::new();
+        for segment in split_params(s) {
+            let capture = match PARAMETER.captures(segment) {
+                Some(capture) => capture,
+                None => {
+                    errors.push(ParameterError::malformed(segment));
+                    continue;
+                }
+            };
+
+            let param = capture
+                .get(1)
+                .expect("any captured group will at least have the param name")
+                .as_str()
+                .trim();
+
+            let key = capture.get(2).map(|m| m.as_str());
+            let value = capture.get(3).map(|m| m.as_str());
+
+            params.push(match (key, value) {
+                (None, _) => Param::Parameter { param },
+                (Some(value), None) => Param::Value { param, value },
+                (Some(key), Some(value)) => Param::KeyValue {
+                    param,
+                    key,
+                    value: value.replace(r"\,", ",").replace(r"\\", r"\"),
+                },
+            });
+        }
+
+        (Self { params }, errors)
+    }
+}
+
+/// Splits parameters at each comma that is not escaped with `\`, skipping
+/// empty parameters
+fn split_params(s: &str) -> impl Iterator<Item = &str> {
+    let mut start = 0;
+    let mut escaped = false;
+    let mut segments = Vec::new();
+    for (index, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                segments.push(&s[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&s[start..]);
+
+    segments.into_iter().filter(|segment| !segment.is_empty())
+}
+
+/// The parameters accepted by `protoc-gen-prost`
+pub const PLUGIN: PluginSpec = PluginSpec {
+    name: "protoc-gen-prost",
+    section: "prost",
+    params: &[
+        ParamSpec::new("boxed", "boxed=<proto_path>"),
+        ParamSpec::new("btree_map", "btree_map=<proto_path>"),
+        ParamSpec::new("bytes", "bytes=<proto_path>"),
+        ParamSpec::new(
+            "default_package_filename",
+            "default_package_filename=<value>",
+        ),
+        ParamSpec::new("skip_debug", "skip_debug=<proto_path>"),
+        ParamSpec::new("disable_comments", "disable_comments=<proto_path>"),
+        ParamSpec::new("extern_path", "extern_path=<proto_path>=<rust_path>"),
+        ParamSpec::new(
+            "compile_well_known_types",
+            "compile_well_known_types(=<boolean>)",
+        ),
+        ParamSpec::new("retain_enum_prefix", "retain_enum_prefix(=<boolean>)"),
+        ParamSpec::new(
+            "field_attribute",
+            "field_attribute=<proto_path>=<attribute>",
+        ),
+        ParamSpec::new("type_attribute", "type_attribute=<proto_path>=<attribute>"),
+        ParamSpec::new("enum_attribute", "enum_attribute=<proto_path>=<attribute>"),
+        ParamSpec::new(
+            "message_attribute",
+            "message_attribute=<proto_path>=<attribute>",
+        ),
+        ParamSpec::new("enable_type_names", "enable_type_names(=<boolean>)"),
+        ParamSpec::new(
+            "file_descriptor_set",
+            "file_descriptor_set(=<boolean|separate|per_file|imports|strip_source_code_info|\
+             strip_source_retention|strip_json_name|compressed(=<zstd|deflate>)>)",
+        ),
+        ParamSpec::new("flat_output_dir", "flat_output_dir(=<boolean>)"),
+        ParamSpec::new("prost_reflect", "prost_reflect(=<boolean>)"),
+        ParamSpec::new("descriptor_pool", "descriptor_pool=<rust_path>"),
+        ParamSpec::new("output_layout", "output_layout=<per_package|per_file>"),
+        ParamSpec::new("jobs", "jobs=<number>"),
+        ParamSpec::new("format", "format(=<boolean>)"),
+        ParamSpec::new("insertion_points", "insertion_points(=<boolean>)"),
+        ParamSpec::new("doc_links", "doc_links(=<boolean>)"),
+        ParamSpec::new("strict", "strict(=<boolean>)"),
+    ],
+};
+
+impl Parameters {
+    fn handle_parameter(&mut self, param: Param<'_>) -> std::result::Result<(), ParameterError> {
+        if let Some(reason) = path_pattern::check(&param) {
+            return Err(ParameterError::invalid_value(param, reason));
+        }
+
+        match self.prost.try_handle_parameter(param) {
+            Err(Param::Parameter {
+                param: "file_descriptor_set",
+            })
+            | Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "true",
+            }) => self.file_descriptor_set = true,
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "false",
+            }) => (),
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "separate",
+            }) => {
+                self.file_descriptor_set = true;
+                self.separate_file_descriptor_set = true;
+            }
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "per_file",
+            }) => {
+                self.file_descriptor_set = true;
+                self.per_file_descriptors = true;
+            }
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "imports",
+            }) => {
+                self.file_descriptor_set = true;
+                self.imported_file_descriptors = true;
+            }
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "strip_source_code_info",
+            }) => {
+                self.file_descriptor_set = true;
+                self.minimize_file_descriptors.strip_source_code_info = true;
+            }
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "strip_source_retention",
+            }) => {
+                self.file_descriptor_set = true;
+                self.minimize_file_descriptors.strip_source_retention = true;
+            }
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "strip_json_name",
+            }) => {
+                self.file_descriptor_set = true;
+                self.minimize_file_descriptors.strip_default_json_name = true;
+            }
+            Err(Param::Value {
+                param: "file_descriptor_set",
+                value: "compressed",
+            }) => {
+                self.file_descriptor_set = true;
+                self.file_descriptor_set_compression = Some(Compression::Zstd);
+            }
+            Err(Param::KeyValue {
+                param: "file_descriptor_set",
+                key: "compressed",
+                ref value,
+            }) if value == "zstd" => {
+                self.file_descriptor_set = true;
+                self.file_descriptor_set_compression = Some(Compression::Zstd);
+            }
+            Err(Param::KeyValue {
+                param: "file_descriptor_set",
+                key: "compressed",
+                ref value,
+            }) if value == "deflate" => {
+                self.file_descriptor_set = true;
+                self.file_descriptor_set_compression = Some(Compression::Deflate);
+            }
+            Err(Param::Parameter {
+                param: "prost_reflect",
+            })
+            | Err(Param::Value {
+                param: "prost_reflect",
+                value: "true",
+            }) => self.prost_reflect = true,
+            Err(Param::Value {
+                param: "prost_reflect",
+                value: "false",
+            }) => (),
+            Err(Param::Value {
+                param: "descriptor_pool",
+                value,
+            }) => self.descriptor_pool = Some(value.to_owned()),
+            Err(
+                param @ Param::Value {
+                    param: "jobs",
+                    value,
+                },
+            ) => match value.parse() {
+                Ok(jobs) if jobs > 0 => self.jobs = Some(jobs),
+                _ => {
+                    return Err(ParameterError::invalid_value(
                         param,
-                        key,
-                        value: value.replace(r"\,", ",").replace(r"\\", r"\"),
-                    }),
-                    _ => Err(InvalidParameter(
-                        capture.get(0).unwrap().as_str().to_string(),
-                    )),
+                        "expected a positive number of threads",
+                    ))
                 }
+            },
+            Err(Param::Value {
+                param: "output_layout",
+                value: "per_package",
+            }) => self.output_layout = OutputLayout::PerPackage,
+            Err(Param::Value {
+                param: "output_layout",
+                value: "per_file",
+            }) => self.output_layout = OutputLayout::PerFile,
+            Err(Param::Parameter { param: "format" })
+            | Err(Param::Value {
+                param: "format",
+                value: "true",
+            }) => self.format = true,
+            Err(Param::Value {
+                param: "format",
+                value: "false",
+            }) => self.format = false,
+            Err(Param::Parameter {
+                param: "insertion_points",
             })
-            .collect::<std::result::Result<_, _>>()?;
-        Ok(Self { params })
+            | Err(Param::Value {
+                param: "insertion_points",
+                value: "true",
+            }) => self.insertion_points = true,
+            Err(Param::Value {
+                param: "insertion_points",
+                value: "false",
+            }) => self.insertion_points = false,
+            Err(Param::Parameter { param: "doc_links" })
+            | Err(Param::Value {
+                param: "doc_links",
+                value: "true",
+            }) => self.doc_links = true,
+            Err(Param::Value {
+                param: "doc_links",
+                value: "false",
+            }) => self.doc_links = false,
+            Err(Param::Parameter { param: "strict" })
+            | Err(Param::Value {
+                param: "strict",
+                value: "true",
+            }) => self.strict = true,
+            Err(Param::Value {
+                param: "strict",
+                value: "false",
+            }) => self.strict = false,
+            Err(param) => return Err(PLUGIN.reject(param)),
+            Ok(()) => (),
+        }
+
+        Ok(())
     }
 }
 
-impl str::FromStr for Parameters {
-    type Err = InvalidParameter;
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+impl Parameters {
+    fn from_opts(opts: &PluginOpts<'_>) -> std::result::Result<Self, InvalidParameter> {
         let mut ret_val = Self::default();
-        for param in Params::from_protoc_plugin_opts(s)? {
-            if let Err(param) = ret_val.prost.try_handle_parameter(param) {
-                match param {
-                    Param::Parameter {
-                        param: "file_descriptor_set",
-                    }
-                    | Param::Value {
-                        param: "file_descriptor_set",
-                        value: "true",
-                    } => ret_val.file_descriptor_set = true,
-                    Param::Value {
-                        param: "file_descriptor_set",
-                        value: "false",
-                    } => (),
-                    Param::Parameter {
-                        param: "prost_reflect",
-                    }
-                    | Param::Value {
-                        param: "prost_reflect",
-                        value: "true",
-                    } => ret_val.prost_reflect = true,
-                    Param::Value {
-                        param: "prost_reflect",
-                        value: "false",
-                    } => (),
-                    _ => return Err(InvalidParameter::from(param)),
+        opts.apply(&mut ret_val, Self::handle_parameter)?;
+        Ok(ret_val)
+    }
+
+    fn module_request_set(
+        &self,
+        request: PluginRequest,
+    ) -> std::result::Result<ModuleRequestSet, Error> {
+        ModuleRequestSet::from_request(
+            request,
+            self.prost.default_package_filename(),
+            self.prost.flat_output_dir,
+        )
+    }
+
+    /// Creates the generators configured by these parameters for the modules
+    /// in `module_request_set`
+    fn into_generator(
+        mut self,
+        module_request_set: &ModuleRequestSet,
+    ) -> std::result::Result<impl Generator, Error> {
+        let file_descriptor_set_generator = if self.file_descriptor_set {
+            Some(
+                if self.imported_file_descriptors {
+                    // Only include the files that each module actually imports
+                    FileDescriptorSetGenerator::with_imported_dependencies()
+                } else if self.prost_reflect {
+                    // When using prost-reflect, we need to include all dependencies
+                    FileDescriptorSetGenerator::with_all_dependencies()
+                } else {
+                    // Normal mode: only include files from each module
+                    FileDescriptorSetGenerator::new()
                 }
-            }
+                .separate_file(self.separate_file_descriptor_set)
+                .per_file(self.per_file_descriptors)
+                .minimize(self.minimize_file_descriptors)
+                .compressed(self.file_descriptor_set_compression),
+            )
+        } else {
+            None
+        };
+
+        self.prost.resolve_path_patterns(module_request_set)?;
+        rust_options::apply(module_request_set, &mut self.prost)?;
+        if self.strict {
+            self.prost.extern_path =
+                strict_extern_paths(module_request_set, &self.prost.extern_path)?;
         }
 
-        Ok(ret_val)
-    }
-}
+        let reflect_message_source = if let Some(path) = &self.descriptor_pool {
+            // This relies on the fact that descriptor_pool_generator will create a
+            // static at this path containing the descriptors of every package.
+            Some(format!("#[prost_reflect(descriptor_pool = \"{path}\")]"))
+        } else if self.file_descriptor_set && self.prost_reflect {
+            // This relies on the fact that file_descriptor_set_generator will create a
+            // variable named FILE_DESCRIPTOR_SET which contains the
+            // raw bytes of the file descriptor set, or a function named
+            // file_descriptor_set which decompresses them.
+            Some(if self.file_descriptor_set_compression.is_some() {
+                r#"#[prost_reflect(file_descriptor_set_bytes = "file_descriptor_set()")]"#
+                    .to_owned()
+            } else {
+                r#"#[prost_reflect(file_descriptor_set_bytes = "FILE_DESCRIPTOR_SET")]"#.to_owned()
+            })
+        } else {
+            None
+        };
 
-/// An invalid parameter
-#[derive(Debug)]
-pub struct InvalidParameter(String);
+        if let Some(reflect_message_source) = reflect_message_source {
+            let mut messages = Vec::new();
+            for (_, request) in module_request_set.requests() {
+                for file in request.files() {
+                    collect_message_names(file.package(), &file.message_type, &mut messages);
+                }
+            }
 
-impl InvalidParameter {
-    pub fn new(message: String) -> Self {
-        Self(message)
+            for full_name in &messages {
+                let type_attribute = &mut self.prost.type_attribute;
+                type_attribute.push(
+                    full_name,
+                    "#[derive(::prost_reflect::ReflectMessage)]".to_owned(),
+                );
+                type_attribute.push(full_name, reflect_message_source.clone());
+                type_attribute.push(
+                    full_name,
+                    format!("#[prost_reflect(message_name = \"{}\")]", full_name),
+                );
+            }
+        }
+
+        let descriptor_pool_generator = self.descriptor_pool.map(|path| {
+            DescriptorPoolGenerator::new(path, self.minimize_file_descriptors).strict(self.strict)
+        });
+        let jobs = self
+            .jobs
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+
+        let doc_links = self.doc_links.then(|| {
+            Resolver::new(
+                self.prost.extern_path.clone(),
+                self.prost.compile_well_known_types,
+            )
+        });
+        let prost = self.prost;
+        Ok(
+            CoreProstGenerator::new(move || prost.to_prost_config(), self.output_layout)
+                .jobs(jobs)
+                .format(self.format)
+                .insertion_points(self.insertion_points)
+                .doc_links(doc_links)
+                .strict(self.strict)
+                .chain(file_descriptor_set_generator)
+                .chain(descriptor_pool_generator),
+        )
     }
 }
 
-impl fmt::Display for InvalidParameter {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("invalid parameter: ")?;
-        f.write_str(&self.0)
+impl str::FromStr for Parameters {
+    type Err = InvalidParameter;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::from_opts(&PluginOpts::load(s, &PLUGIN)?)
     }
 }
 
-impl std::error::Error for InvalidParameter {}
-
 /// A wire-compatible reader of a [`CodeGeneratorRequest`]
 ///
 /// This type treats the proto files contained in the request as raw byte
 /// arrays so that we can round-trip those bytes into the generated files
-/// as an encoded [`FileDescriptorSet`].
+/// as an encoded [`FileDescriptorSet`]. When decoded from [`Bytes`], these
+/// share the buffer of the request rather than being copied.
 ///
 /// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
 /// [`FileDescriptorSet`]: prost_types::FileDescriptorSet
 #[derive(Clone, PartialEq, ::prost::Message)]
 struct RawProtos {
-    #[prost(bytes = "vec", repeated, tag = "15")]
-    proto_file: Vec<Vec<u8>>,
+    #[prost(string, repeated, tag = "1")]
+    file_to_generate: Vec<String>,
+    #[prost(string, optional, tag = "2")]
+    parameter: Option<String>,
+    #[prost(bytes = "bytes", repeated, tag = "15")]
+    proto_file: Vec<Bytes>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Parses the bytes embedded in a generated `pub const {name}: &[u8]`
+    fn embedded_bytes(content: &str, name: &str) -> Vec<u8> {
+        let start = content
+            .find(&format!("pub const {name}: &[u8] = &[\n"))
+            .unwrap_or_else(|| panic!("{name} not generated"));
+        let bytes = &content[start..];
+        let bytes = &bytes[bytes.find("= &[").unwrap() + 4..bytes.find("];").unwrap()];
+        bytes
+            .split(',')
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+            .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16).unwrap())
+            .collect()
+    }
+
     #[test]
     fn compiler_option_string_with_three_plus_equals_parses_correctly() {
         const INPUT: &str = r#"flat_output_dir,enable_type_names,compile_well_known_types,disable_comments=.,skip_debug=.,extern_path=.google.protobuf=::pbjson_types,type_attribute=.=#[cfg(all(feature = "test"\, feature = "orange"))]"#;
@@ -695,6 +1272,20 @@ mod tests {
         assert_eq!(actual.params, expected);
     }
 
+    #[test]
+    fn malformed_parameters_are_reported_with_the_other_errors() {
+        let error = "=x,flat_output_dri,,a==b,bytes=.=x\\y,flat_output_dir"
+            .parse::<Parameters>()
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "protoc-gen-prost: 4 invalid parameters\n  * `=x`: malformed parameter\n  * `a==b`: \
+             malformed parameter\n  * `bytes=.=x\\y`: malformed parameter\n  * `flat_output_dri`: \
+             unknown parameter, did you mean `flat_output_dir`?"
+        );
+    }
+
     #[test]
     fn prost_reflect_applies_to_nested_messages() {
         use prost::Message;
@@ -752,4 +1343,754 @@ mod tests {
             example_rs
         );
     }
+
+    #[test]
+    fn prost_reflect_applies_to_documented_nested_messages() {
+        use crate::testing::{file, request, FieldType};
+
+        let encoded = request()
+            .parameter("file_descriptor_set,prost_reflect")
+            .file(
+                file("example.proto")
+                    .package("example")
+                    .message("TestMessage", |m| {
+                        m.message("MyInnerMessage", |m| {
+                            m.comment("An inner message.")
+                                .field("value", FieldType::String, 1)
+                        })
+                    }),
+            )
+            .encode();
+        let files = execute(&encoded).expect("code generation failed");
+
+        let example_rs = files
+            .iter()
+            .find(|f| f.name.as_deref() == Some("example/example.rs"))
+            .map(|f| f.content.as_deref().unwrap_or_default())
+            .expect("example/example.rs not generated");
+
+        assert!(
+            example_rs.contains(
+                "    /// An inner message.\n    #[derive(::prost_reflect::ReflectMessage)]\n"
+            ),
+            "nested message should keep its comment:\n{}",
+            example_rs
+        );
+        assert!(
+            example_rs.contains(
+                r#"#[prost_reflect(message_name = "example.TestMessage.MyInnerMessage")]"#
+            ),
+            "nested message should derive ReflectMessage:\n{}",
+            example_rs
+        );
+    }
+
+    #[test]
+    fn doc_links_link_references_in_comments() {
+        use crate::testing::{file, request, FieldType};
+
+        let encoded = request()
+            .parameter("doc_links")
+            .file(
+                file("example.proto")
+                    .package("example")
+                    .message("Outer", |m| {
+                        m.comment("Holds an [example.Inner].").field_with(
+                            "inner",
+                            FieldType::Message(".example.Inner"),
+                            1,
+                            |f| f.comment("See `Inner`:\n\n    inner.value = 1;"),
+                        )
+                    })
+                    .message("Inner", |m| {
+                        m.message("Nested", |m| m.comment("Within [`Outer`](x) and `Outer`."))
+                    }),
+            )
+            .encode();
+        let files = execute(&encoded).expect("code generation failed");
+        let content = files[0].content();
+
+        assert!(
+            content.contains("/// Holds an [example.Inner](Inner).\n"),
+            "{content}"
+        );
+        assert!(
+            content.contains(
+                "    /// See [`Inner`](Inner):\n    ///\n    /// ```text\n    /// inner.value = \
+                 1;\n    /// ```\n"
+            ),
+            "{content}"
+        );
+        assert!(
+            content.contains("    /// Within [`Outer`](x) and [`Outer`](super::Outer).\n"),
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn deprecated_definitions_are_marked_deprecated() {
+        use prost_types::{EnumValueOptions, MessageOptions};
+
+        use crate::testing::{file, request, FieldType};
+
+        let deprecated = MessageOptions {
+            deprecated: Some(true),
+            ..Default::default()
+        };
+        let encoded = request()
+            .file(
+                file("example.proto")
+                    .package("example")
+                    .message("Old", |m| {
+                        m.options(deprecated.clone())
+                            .trailing_comment(" Use `New` instead.\n")
+                            .message("Inner", |m| m.field("id", FieldType::Int64, 1))
+                            .message("Gone", |m| m.options(deprecated))
+                    })
+                    .enumeration("Kind", |e| {
+                        e.value("KIND_UNSPECIFIED", 0).value_with(
+                            "KIND_LEGACY",
+                            1,
+                            None,
+                            Some(EnumValueOptions {
+                                deprecated: Some(true),
+                                ..Default::default()
+                            }),
+                        )
+                    }),
+            )
+            .encode();
+        let files = execute(&encoded).expect("code generation failed");
+        let content = files[0].content();
+
+        assert!(
+            content.contains("#[deprecated = \"Use `New` instead.\"]\n#[derive("),
+            "{content}"
+        );
+        assert!(
+            content.contains(
+                "    #[deprecated]\n    #[derive(Clone, Copy, PartialEq, Eq, Hash, \
+                 ::prost::Message)]\n    pub struct Gone {"
+            ),
+            "{content}"
+        );
+        assert!(
+            content.contains("    #[deprecated]\n    Legacy = 1,\n"),
+            "{content}"
+        );
+        // `Inner` is not deprecated along with `Old`
+        assert_eq!(content.matches("#[deprecated").count(), 3, "{content}");
+        assert!(!content.contains("protoc_deprecated"), "{content}");
+        // Code that refers to deprecated definitions is allowed to
+        assert!(
+            content.contains("#[allow(deprecated)]\npub mod old {"),
+            "{content}"
+        );
+        assert!(
+            content.contains("#[allow(deprecated)]\nimpl Kind {"),
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn strict_mode_generates_only_the_requested_files() {
+        use crate::testing::{file, request, FieldType};
+
+        let split_package = |parameter: &str| {
+            request()
+                .parameter(parameter)
+                .file(
+                    file("foo/v1/local.proto")
+                        .package("foo.v1")
+                        .import("foo/v1/remote.proto")
+                        .message("Local", |m| {
+                            m.field("remote", FieldType::Message(".foo.v1.Remote"), 1)
+                        }),
+                )
+                .import(
+                    file("foo/v1/remote.proto")
+                        .package("foo.v1")
+                        .message("Remote", |m| m.field("id", FieldType::Int64, 1)),
+                )
+                .encode()
+        };
+
+        let files = execute(&split_package("")).expect("code generation failed");
+        assert!(files[0].content().contains("pub struct Remote"));
+
+        let files = execute(&split_package(
+            "strict,extern_path=.foo.v1=::remote::foo::v1",
+        ))
+        .expect("code generation failed");
+        let content = files[0].content();
+        assert!(content.contains("pub struct Local"), "{content}");
+        assert!(!content.contains("pub struct Remote"), "{content}");
+        assert!(
+            content.contains("pub remote: ::core::option::Option<::remote::foo::v1::Remote>"),
+            "{content}"
+        );
+
+        let error = execute(&split_package("strict")).unwrap_err();
+        assert!(error.to_string().contains("`.foo.v1.Remote`"), "{error}");
+    }
+
+    #[test]
+    fn strict_mode_generates_descriptor_accessors_only_for_requested_files() {
+        use crate::testing::{file, request};
+
+        let request = request()
+            .parameter("strict,descriptor_pool=crate::POOL,extern_path=.foo.v1=::remote::foo::v1")
+            .file(
+                file("foo/v1/local.proto")
+                    .package("foo.v1")
+                    .enumeration("LocalKind", |e| e.value("LOCAL_KIND_UNSPECIFIED", 0)),
+            )
+            .import(
+                file("foo/v1/remote.proto")
+                    .package("foo.v1")
+                    .enumeration("RemoteKind", |e| e.value("REMOTE_KIND_UNSPECIFIED", 0)),
+            )
+            .encode();
+
+        let files = execute(&request).expect("code generation failed");
+        let accessors = files
+            .iter()
+            .find(|f| f.insertion_point() == "module")
+            .expect("accessors not generated")
+            .content();
+        assert!(accessors.contains("impl LocalKind {"), "{accessors}");
+        assert!(!accessors.contains("RemoteKind"), "{accessors}");
+    }
+
+    #[test]
+    fn per_file_layout_includes_each_file_from_the_package_file() {
+        use prost::Message;
+        use prost_types::{
+            compiler::CodeGeneratorRequest,
+            field_descriptor_proto::{Label, Type},
+            FieldDescriptorProto,
+        };
+
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec![
+                "greeter/v1/types.proto".to_owned(),
+                "greeter/v1/greeter.proto".to_owned(),
+            ],
+            parameter: Some("output_layout=per_file".to_owned()),
+            proto_file: vec![
+                FileDescriptorProto {
+                    name: Some("greeter/v1/types.proto".to_owned()),
+                    package: Some("greeter.v1".to_owned()),
+                    message_type: vec![DescriptorProto {
+                        name: Some("Name".to_owned()),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                FileDescriptorProto {
+                    name: Some("greeter/v1/greeter.proto".to_owned()),
+                    package: Some("greeter.v1".to_owned()),
+                    dependency: vec!["greeter/v1/types.proto".to_owned()],
+                    message_type: vec![DescriptorProto {
+                        name: Some("HelloRequest".to_owned()),
+                        field: vec![FieldDescriptorProto {
+                            name: Some("name".to_owned()),
+                            number: Some(1),
+                            r#type: Some(Type::Message as i32),
+                            type_name: Some(".greeter.v1.Name".to_owned()),
+                            label: Some(Label::Optional as i32),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let files = execute(&request.encode_to_vec()).expect("code generation failed");
+        let content = |name: &str| {
+            files
+                .iter()
+                .find(|f| f.name.as_deref() == Some(name))
+                .and_then(|f| f.content.as_deref())
+                .unwrap_or_else(|| panic!("{name} not generated"))
+        };
+
+        assert_eq!(
+            content("greeter/v1/greeter.v1.rs"),
+            "// @generated\ninclude!(\"greeter.v1.types.pb.rs\");\ninclude!(\"greeter.v1.greeter.\
+             pb.rs\");\n// @@protoc_insertion_point(module)\n"
+        );
+        assert!(content("greeter/v1/greeter.v1.types.pb.rs").contains("pub struct Name {"));
+        let greeter_rs = content("greeter/v1/greeter.v1.greeter.pb.rs");
+        assert!(greeter_rs.contains("pub name: ::core::option::Option<Name>,"));
+        assert!(!greeter_rs.contains("pub struct Name {"));
+    }
+
+    #[test]
+    fn separate_file_descriptor_set_is_included_into_the_module() {
+        use prost::Message;
+        use prost_types::compiler::CodeGeneratorRequest;
+
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec!["example.proto".to_owned()],
+            parameter: Some("file_descriptor_set=separate,prost_reflect".to_owned()),
+            proto_file: vec![FileDescriptorProto {
+                name: Some("example.proto".to_owned()),
+                package: Some("example".to_owned()),
+                message_type: vec![DescriptorProto {
+                    name: Some("TestMessage".to_owned()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let files = execute(&request.encode_to_vec()).expect("code generation failed");
+
+        let include = files
+            .iter()
+            .find(|f| f.insertion_point.as_deref() == Some("module"))
+            .expect("include not generated");
+        assert_eq!(include.name.as_deref(), Some("example/example.rs"));
+        assert_eq!(
+            include.content.as_deref(),
+            Some("include!(\"example.fds.rs\");\n")
+        );
+
+        let fds_rs = files
+            .iter()
+            .find(|f| f.name.as_deref() == Some("example/example.fds.rs"))
+            .and_then(|f| f.content.as_deref())
+            .expect("example/example.fds.rs not generated");
+        assert!(fds_rs.starts_with(
+            "// @generated\n/// Encoded file descriptor set for the `example` package\npub const \
+             FILE_DESCRIPTOR_SET: &[u8] = &[\n"
+        ));
+
+        let example_rs = files
+            .iter()
+            .find(|f| {
+                f.name.as_deref() == Some("example/example.rs") && f.insertion_point.is_none()
+            })
+            .and_then(|f| f.content.as_deref())
+            .expect("example/example.rs not generated");
+        assert!(
+            example_rs
+                .contains(r#"#[prost_reflect(file_descriptor_set_bytes = "FILE_DESCRIPTOR_SET")]"#),
+            "message should refer to the included FILE_DESCRIPTOR_SET:\n{}",
+            example_rs
+        );
+    }
+
+    #[test]
+    fn separate_file_descriptor_set_of_the_empty_package_is_named_after_its_module() {
+        use crate::testing::{file, request};
+
+        let request = request()
+            .parameter("file_descriptor_set=separate")
+            .file(file("example.proto").message("TestMessage", |m| m))
+            .encode();
+
+        let files = execute(&request).expect("code generation failed");
+
+        let include = files
+            .iter()
+            .find(|f| f.insertion_point() == "module")
+            .expect("include not generated");
+        assert_eq!(include.name(), "_");
+        assert_eq!(include.content(), "include!(\"_.fds.rs\");\n");
+        assert!(
+            files.iter().any(|f| f.name() == "_.fds.rs"),
+            "_.fds.rs not generated"
+        );
+    }
+
+    #[test]
+    fn per_file_descriptors_can_be_looked_up_by_name() {
+        use prost::Message;
+        use prost_types::compiler::CodeGeneratorRequest;
+
+        let file = |name: &str| FileDescriptorProto {
+            name: Some(name.to_owned()),
+            package: Some("greeter.v1".to_owned()),
+            ..Default::default()
+        };
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec!["greeter/v1/greeter.proto".to_owned()],
+            parameter: Some("file_descriptor_set=per_file".to_owned()),
+            proto_file: vec![
+                file("greeter/v1/types.proto"),
+                file("greeter/v1/greeter.proto"),
+            ],
+            ..Default::default()
+        };
+
+        let files = execute(&request.encode_to_vec()).expect("code generation failed");
+        let appended = files
+            .iter()
+            .find(|f| f.insertion_point.as_deref() == Some("module"))
+            .and_then(|f| f.content.as_deref())
+            .expect("file descriptors not generated");
+
+        for expected in [
+            "pub const FILE_DESCRIPTOR_SET: &[u8] = &[\n",
+            "/// Encoded file descriptor for `greeter/v1/types.proto`\npub const \
+             FILE_DESCRIPTOR_GREETER_V1_TYPES_PROTO: &[u8] = &[\n",
+            "/// Encoded file descriptor for `greeter/v1/greeter.proto`\npub const \
+             FILE_DESCRIPTOR_GREETER_V1_GREETER_PROTO: &[u8] = &[\n",
+            "pub fn file_descriptor(name: &str) -> ::core::option::Option<&'static [u8]> {\n    \
+             match name {\n        \"greeter/v1/types.proto\" => \
+             ::core::option::Option::Some(FILE_DESCRIPTOR_GREETER_V1_TYPES_PROTO),\n        \
+             \"greeter/v1/greeter.proto\" => \
+             ::core::option::Option::Some(FILE_DESCRIPTOR_GREETER_V1_GREETER_PROTO),\n        _ \
+             => ::core::option::Option::None,\n    }\n}\n",
+        ] {
+            assert!(
+                appended.contains(expected),
+                "expected `{expected}` in:\n{appended}"
+            );
+        }
+    }
+
+    #[test]
+    fn colliding_file_descriptor_names_are_disambiguated() {
+        use crate::testing::{file, request};
+
+        let request = request()
+            .parameter("file_descriptor_set=per_file")
+            .file(file("a/b_c.proto").package("x"))
+            .file(file("a_b/c.proto").package("x"))
+            .file(file("a/b/c.proto").package("x"))
+            .encode();
+
+        let files = execute(&request).expect("code generation failed");
+        let appended = files
+            .iter()
+            .find(|f| f.insertion_point.as_deref() == Some("module"))
+            .and_then(|f| f.content.as_deref())
+            .expect("file descriptors not generated");
+
+        for expected in [
+            "\"a/b_c.proto\" => ::core::option::Option::Some(FILE_DESCRIPTOR_A_B_C_PROTO),\n",
+            "\"a_b/c.proto\" => ::core::option::Option::Some(FILE_DESCRIPTOR_A_B_C_PROTO_2),\n",
+            "\"a/b/c.proto\" => ::core::option::Option::Some(FILE_DESCRIPTOR_A_B_C_PROTO_3),\n",
+        ] {
+            assert!(
+                appended.contains(expected),
+                "expected `{expected}` in:\n{appended}"
+            );
+        }
+        assert_eq!(
+            appended
+                .matches("pub const FILE_DESCRIPTOR_A_B_C_PROTO: ")
+                .count(),
+            1,
+            "{appended}"
+        );
+    }
+
+    #[test]
+    fn imported_file_descriptor_sets_are_limited_to_the_import_closure() {
+        use prost::Message;
+        use prost_types::{compiler::CodeGeneratorRequest, FileDescriptorSet};
+
+        let file = |name: &str, package: &str, dependency: &[&str]| FileDescriptorProto {
+            name: Some(name.to_owned()),
+            package: Some(package.to_owned()),
+            dependency: dependency.iter().map(|&d| d.to_owned()).collect(),
+            ..Default::default()
+        };
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec!["a/a.proto".to_owned(), "d/d.proto".to_owned()],
+            parameter: Some("file_descriptor_set=imports".to_owned()),
+            proto_file: vec![
+                file("c/c.proto", "c", &[]),
+                file("d/d.proto", "d", &[]),
+                file("b/b.proto", "b", &["c/c.proto"]),
+                file("a/a.proto", "a", &["b/b.proto", "c/c.proto"]),
+            ],
+            ..Default::default()
+        };
+
+        let files = execute(&request.encode_to_vec()).expect("code generation failed");
+        let embedded_names = |module: &str| {
+            let content = files
+                .iter()
+                .find(|f| {
+                    f.name.as_deref() == Some(module)
+                        && f.insertion_point.as_deref() == Some("module")
+                })
+                .and_then(|f| f.content.as_deref())
+                .unwrap_or_else(|| panic!("no file descriptor set appended to {module}"));
+            let bytes = embedded_bytes(content, "FILE_DESCRIPTOR_SET");
+
+            FileDescriptorSet::decode(bytes.as_slice())
+                .unwrap()
+                .file
+                .into_iter()
+                .map(|f| f.name.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            embedded_names("a/a.rs"),
+            ["c/c.proto", "b/b.proto", "a/a.proto"]
+        );
+        assert_eq!(embedded_names("d/d.rs"), ["d/d.proto"]);
+    }
+
+    #[test]
+    fn compressed_file_descriptor_sets_decompress_to_the_raw_set() {
+        use std::io::Read;
+
+        use prost::Message;
+        use prost_types::{compiler::CodeGeneratorRequest, FileDescriptorSet};
+
+        let proto_file = FileDescriptorProto {
+            name: Some("example.proto".to_owned()),
+            package: Some("example".to_owned()),
+            message_type: vec![DescriptorProto {
+                name: Some("TestMessage".to_owned()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let generate = |parameter: &str| {
+            let request = CodeGeneratorRequest {
+                file_to_generate: vec!["example.proto".to_owned()],
+                parameter: Some(parameter.to_owned()),
+                proto_file: vec![proto_file.clone()],
+                ..Default::default()
+            };
+            execute(&request.encode_to_vec())
+                .expect("code generation failed")
+                .into_iter()
+                .filter_map(|f| f.content)
+                .collect::<String>()
+        };
+        let expected = FileDescriptorSet {
+            file: vec![proto_file.clone()],
+        };
+
+        let zstd = generate("file_descriptor_set=compressed,prost_reflect");
+        let mut decompressed = Vec::new();
+        ruzstd::decoding::StreamingDecoder::new(
+            embedded_bytes(&zstd, "FILE_DESCRIPTOR_SET_COMPRESSED").as_slice(),
+        )
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+        assert_eq!(
+            FileDescriptorSet::decode(decompressed.as_slice()).unwrap(),
+            expected
+        );
+        assert!(zstd.contains("::ruzstd::decoding::StreamingDecoder::new"));
+        assert!(zstd
+            .contains(r#"#[prost_reflect(file_descriptor_set_bytes = "file_descriptor_set()")]"#));
+        assert!(!zstd.contains("pub const FILE_DESCRIPTOR_SET: &[u8]"));
+
+        let deflate = generate("file_descriptor_set=compressed=deflate");
+        let decompressed = miniz_oxide::inflate::decompress_to_vec(&embedded_bytes(
+            &deflate,
+            "FILE_DESCRIPTOR_SET_COMPRESSED",
+        ))
+        .unwrap();
+        assert_eq!(
+            FileDescriptorSet::decode(decompressed.as_slice()).unwrap(),
+            expected
+        );
+        assert!(deflate.contains("::miniz_oxide::inflate::decompress_to_vec"));
+    }
+
+    #[test]
+    fn descriptor_pool_is_shared_by_every_package() {
+        use prost::Message;
+        use prost_types::{
+            compiler::CodeGeneratorRequest, EnumDescriptorProto, ServiceDescriptorProto,
+        };
+
+        let status = || EnumDescriptorProto {
+            name: Some("Status".to_owned()),
+            ..Default::default()
+        };
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec!["greeter/v1/greeter.proto".to_owned()],
+            parameter: Some("descriptor_pool=crate::DESCRIPTOR_POOL".to_owned()),
+            proto_file: vec![FileDescriptorProto {
+                name: Some("greeter/v1/greeter.proto".to_owned()),
+                package: Some("greeter.v1".to_owned()),
+                message_type: vec![DescriptorProto {
+                    name: Some("HelloReply".to_owned()),
+                    enum_type: vec![status()],
+                    ..Default::default()
+                }],
+                enum_type: vec![status()],
+                service: vec![ServiceDescriptorProto {
+                    name: Some("Greeter".to_owned()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let files = execute(&request.encode_to_vec()).expect("code generation failed");
+        let content = |name: &str, insertion_point: Option<&str>| {
+            files
+                .iter()
+                .find(|f| {
+                    f.name.as_deref() == Some(name)
+                        && f.insertion_point.as_deref() == insertion_point
+                })
+                .and_then(|f| f.content.as_deref())
+                .unwrap_or_else(|| panic!("{name} not generated"))
+        };
+
+        let pool_rs = content("descriptor_pool.rs", None);
+        assert!(pool_rs.contains("pub const DESCRIPTOR_POOL_FILE_DESCRIPTOR_SET: &[u8] = &[\n"));
+        assert!(pool_rs.contains(
+            "pub static DESCRIPTOR_POOL: ::once_cell::sync::Lazy<::prost_reflect::DescriptorPool> \
+             ="
+        ));
+
+        let greeter_rs = content("greeter/v1/greeter.v1.rs", None);
+        assert!(greeter_rs.contains(
+            "#[prost_reflect(descriptor_pool = \
+             \"crate::DESCRIPTOR_POOL\")]\n#[prost_reflect(message_name = \
+             \"greeter.v1.HelloReply\")]"
+        ));
+
+        let accessors = content("greeter/v1/greeter.v1.rs", Some("module"));
+        for expected in [
+            "impl Status {\n    /// Returns the descriptor for the `greeter.v1.Status` enum\n",
+            "impl hello_reply::Status {\n    /// Returns the descriptor for the \
+             `greeter.v1.HelloReply.Status` enum\n",
+            "pub fn greeter_descriptor() -> ::prost_reflect::ServiceDescriptor {\n    \
+             crate::DESCRIPTOR_POOL\n        .get_service_by_name(\"greeter.v1.Greeter\")\n",
+        ] {
+            assert!(
+                accessors.contains(expected),
+                "expected `{expected}` in:\n{accessors}"
+            );
+        }
+    }
+
+    #[test]
+    fn parallel_generation_matches_sequential_generation() {
+        use prost::Message;
+        use prost_types::{
+            compiler::CodeGeneratorRequest,
+            field_descriptor_proto::{Label, Type},
+            FieldDescriptorProto,
+        };
+
+        // Each package references a message from the previous one, so that
+        // modules generated on different threads depend on each other
+        let file = |index: usize| FileDescriptorProto {
+            name: Some(format!("pkg{index}/types.proto")),
+            package: Some(format!("pkg{index}")),
+            dependency: (index > 0)
+                .then(|| format!("pkg{}/types.proto", index - 1))
+                .into_iter()
+                .collect(),
+            message_type: vec![DescriptorProto {
+                name: Some("Message".to_owned()),
+                field: (index > 0)
+                    .then(|| FieldDescriptorProto {
+                        name: Some("previous".to_owned()),
+                        number: Some(1),
+                        r#type: Some(Type::Message as i32),
+                        type_name: Some(format!(".pkg{}.Message", index - 1)),
+                        label: Some(Label::Optional as i32),
+                        ..Default::default()
+                    })
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let generate = |parameter: &str| {
+            let request = CodeGeneratorRequest {
+                file_to_generate: (1..8).map(|i| format!("pkg{i}/types.proto")).collect(),
+                parameter: Some(parameter.to_owned()),
+                proto_file: (0..8).map(file).collect(),
+                ..Default::default()
+            };
+            execute(&request.encode_to_vec()).expect("code generation failed")
+        };
+
+        for layout in ["per_package", "per_file"] {
+            let sequential = generate(&format!("jobs=1,output_layout={layout}"));
+            let parallel = generate(&format!("jobs=4,output_layout={layout}"));
+            assert_eq!(sequential, parallel, "output differs with {layout}");
+        }
+        let sequential = generate("jobs=1");
+        assert_eq!(sequential.len(), 7);
+        assert!(sequential.iter().any(|f| {
+            f.content.as_deref().is_some_and(|c| {
+                c.contains("pub previous: ::core::option::Option<super::pkg3::Message>,")
+            })
+        }));
+    }
+
+    #[test]
+    fn jobs_must_be_a_positive_number() {
+        for jobs in ["0", "many"] {
+            let error = format!("jobs={jobs}")
+                .parse::<Parameters>()
+                .expect_err("invalid jobs should be rejected");
+            assert!(
+                error.to_string().contains("positive number"),
+                "unexpected error: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn cached_output_matches_generated_output() {
+        use prost::Message;
+        use prost_types::compiler::CodeGeneratorRequest;
+
+        let dir = std::env::temp_dir().join(format!(
+            "protoc-gen-prost-cached-output-{}",
+            std::process::id()
+        ));
+        let file = |name: &str, dependency: &[&str]| FileDescriptorProto {
+            name: Some(format!("{name}/{name}.proto")),
+            package: Some(name.to_owned()),
+            dependency: dependency
+                .iter()
+                .map(|d| format!("{d}/{d}.proto"))
+                .collect(),
+            message_type: vec![DescriptorProto {
+                name: Some("Message".to_owned()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let generate = |parameter: &str| {
+            let request = CodeGeneratorRequest {
+                file_to_generate: vec!["a/a.proto".to_owned(), "b/b.proto".to_owned()],
+                parameter: Some(parameter.to_owned()),
+                proto_file: vec![file("a", &[]), file("b", &["a"])],
+                ..Default::default()
+            };
+            execute(&request.encode_to_vec()).expect("code generation failed")
+        };
+
+        let parameter = "file_descriptor_set=separate,descriptor_pool=crate::POOL";
+        let generated = generate(parameter);
+        let cached = format!("{parameter},cache_dir={}", dir.display());
+        assert_eq!(generate(&cached), generated);
+        assert_eq!(generate(&cached), generated);
+        // One entry for each module, and one for the shared descriptor pool
+        assert_eq!(std::fs::read_dir(dir.join(PLUGIN.name)).unwrap().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,563 @@
+//! Glob and regular expression patterns for proto path parameters
+//!
+//! `prost-build` only matches proto paths by prefix or by suffix. Proto paths
+//! beginning with `re:` or `glob:` are instead matched against the
+//! fully-qualified names of the types and fields in the request, and each
+//! name that matches is passed to `prost-build` as an exact path. A proto path
+//! beginning with `!` excludes the names that it matches from the patterns
+//! given for the same parameter.
+
+use std::collections::{HashMap, HashSet};
+
+use prost_types::{DescriptorProto, EnumDescriptorProto};
+use regex::Regex;
+
+use crate::{Error, ModuleRequestSet, Param};
+
+const REGEX_PREFIX: &str = "re:";
+const GLOB_PREFIX: &str = "glob:";
+const EXCLUSION_PREFIX: char = '!';
+
+/// The proto paths given for a parameter, along with the value given for
+/// each path
+#[derive(Debug)]
+pub(crate) struct PathPatterns<T> {
+    paths: Vec<(String, T)>,
+    exclusions: Vec<(String, Option<T>)>,
+}
+
+impl<T> Default for PathPatterns<T> {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            exclusions: Vec::new(),
+        }
+    }
+}
+
+impl<T: PartialEq> PathPatterns<T> {
+    /// Adds a proto path, or an exclusion if it begins with `!`
+    ///
+    /// An exclusion without a value applies to every pattern of the
+    /// parameter, while an exclusion with a value only applies to the
+    /// patterns given with that same value.
+    pub(crate) fn push(&mut self, path: &str, value: T) {
+        match path.strip_prefix(EXCLUSION_PREFIX) {
+            Some(exclusion) => self.exclusions.push((exclusion.to_owned(), Some(value))),
+            None => self.paths.push((path.to_owned(), value)),
+        }
+    }
+
+    /// Adds an exclusion that applies to every pattern of the parameter
+    pub(crate) fn exclude(&mut self, path: &str) {
+        let path = path.strip_prefix(EXCLUSION_PREFIX).unwrap_or(path);
+        self.exclusions.push((path.to_owned(), None));
+    }
+
+    /// Resolves the patterns into the exact paths to pass to `prost-build`
+    ///
+    /// Plain proto paths are kept as they were given, while each pattern is
+    /// replaced by the exact path of each of the `candidates` that it matches.
+    /// A pattern matching a name that has no exact path is rejected.
+    pub(crate) fn resolve(&mut self, names: &Names, candidates: Candidates) -> Result<(), Error>
+    where
+        T: Clone,
+    {
+        let exclusions = self
+            .exclusions
+            .iter()
+            .map(|(path, value)| Ok((Matcher::parse(path)?, value.as_ref())))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut exact_paths = None;
+        let mut resolved = Vec::new();
+        for (path, value) in &self.paths {
+            let matcher = match Matcher::parse(path)? {
+                Matcher::Path(path) => {
+                    resolved.push((path.to_owned(), value.clone()));
+                    continue;
+                }
+                matcher => matcher,
+            };
+
+            let exact_paths = exact_paths.get_or_insert_with(|| names.exact_paths(candidates));
+            let matches = names
+                .candidates(candidates)
+                .filter(|name| matcher.is_match(name))
+                .filter(|name| {
+                    !exclusions.iter().any(|(exclusion, excluded_value)| {
+                        excluded_value.is_none_or(|v| v == value) && exclusion.is_match(name)
+                    })
+                });
+            for name in matches {
+                match exact_paths[name.as_str()] {
+                    Some(exact_path) => resolved.push((exact_path.to_owned(), value.clone())),
+                    None => {
+                        return Err(format!(
+                            "`{path}` matches `{name}`, which `prost-build` cannot match without \
+                             also matching the names nested within it or ending with it"
+                        )
+                        .into())
+                    }
+                }
+            }
+        }
+
+        self.paths = resolved;
+        self.exclusions.clear();
+        Ok(())
+    }
+
+    /// The proto paths and their values, which are exact paths once resolved
+    pub(crate) fn paths(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.paths
+            .iter()
+            .map(|(path, value)| (path.as_str(), value))
+    }
+}
+
+/// Checks that the proto path given to a path parameter is valid
+///
+/// Returns the reason that the proto path was rejected, if it was.
+pub(crate) fn check(param: &Param<'_>) -> Option<String> {
+    let path = match *param {
+        Param::KeyValue {
+            param: "type_attribute" | "field_attribute" | "message_attribute" | "enum_attribute",
+            key,
+            ..
+        }
+        | Param::Value {
+            param:
+                "type_attribute" | "field_attribute" | "message_attribute" | "enum_attribute" | "bytes"
+                | "btree_map" | "boxed",
+            value: key,
+        } => key,
+        _ => return None,
+    };
+    let path = path.strip_prefix(EXCLUSION_PREFIX).unwrap_or(path);
+
+    Matcher::parse(path).err().map(|err| err.to_string())
+}
+
+/// Whether an attribute parameter given without an attribute is an exclusion
+pub(crate) fn is_exclusion(path: &str) -> bool {
+    path.starts_with(EXCLUSION_PREFIX)
+}
+
+enum Matcher<'a> {
+    /// A proto path matched by prefix or by suffix, as by `prost-build`
+    Path(&'a str),
+
+    /// A pattern matched against the whole fully-qualified name
+    Regex(Regex),
+}
+
+impl<'a> Matcher<'a> {
+    fn parse(path: &'a str) -> Result<Self, Error> {
+        if let Some(pattern) = path.strip_prefix(REGEX_PREFIX) {
+            Regex::new(pattern)
+                .map(Matcher::Regex)
+                .map_err(|err| format!("invalid regular expression `{pattern}`: {err}").into())
+        } else if let Some(pattern) = path.strip_prefix(GLOB_PREFIX) {
+            Regex::new(&glob_to_regex(pattern))
+                .map(Matcher::Regex)
+                .map_err(|err| format!("invalid glob `{pattern}`: {err}").into())
+        } else {
+            Ok(Matcher::Path(path))
+        }
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        match self {
+            Matcher::Path(path) if path.starts_with('.') => {
+                *path == "." || name == *path || name.starts_with(&format!("{path}."))
+            }
+            Matcher::Path(path) => name.ends_with(&format!(".{path}")),
+            Matcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Translates a glob into an anchored regular expression
+///
+/// `*` matches within a single component of a name, `**` matches across
+/// components, and `?` matches a single character other than `.`.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^.]*"),
+            '?' => regex.push_str("[^.]"),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// The names that the proto paths of a parameter are matched against
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Candidates {
+    /// Messages, enums and oneofs, for `type_attribute`
+    Types,
+
+    /// Messages, for `message_attribute`
+    Messages,
+
+    /// Enums and oneofs, for `enum_attribute`
+    Enums,
+
+    /// Fields, oneofs, and enum values, for `field_attribute`
+    FieldAttributes,
+
+    /// Fields by the name of their message, for `bytes` and `btree_map`
+    Fields,
+
+    /// Fields by the name of their message or oneof, for `boxed`
+    BoxedFields,
+}
+
+/// The fully-qualified names of the types and fields in a request, as
+/// `prost-build` sees them when matching proto paths
+#[derive(Debug, Default)]
+pub(crate) struct Names {
+    /// Messages, such as `.pkg.Message`
+    messages: Vec<String>,
+
+    /// Enums, such as `.pkg.Enum`
+    enums: Vec<String>,
+
+    /// Oneofs, such as `.pkg.Message.oneof`
+    oneofs: Vec<String>,
+
+    /// Fields of messages, including those in oneofs, such as `.pkg.Message.field`
+    fields: Vec<String>,
+
+    /// Fields outside of any oneof, such as `.pkg.Message.field`
+    plain_fields: Vec<String>,
+
+    /// Fields in a oneof, such as `.pkg.Message.oneof.field`
+    oneof_fields: Vec<String>,
+
+    /// Enum values, such as `.pkg.Enum.VALUE`
+    enum_values: Vec<String>,
+}
+
+impl Names {
+    /// Collects the names of every type and field in the request
+    pub(crate) fn new(module_request_set: &ModuleRequestSet) -> Self {
+        let mut names = Self::default();
+        for (_, request) in module_request_set.requests() {
+            for file in request.files() {
+                let scope = match file.package() {
+                    "" => String::new(),
+                    package => format!(".{package}"),
+                };
+                for message in &file.message_type {
+                    names.collect_message(&scope, message);
+                }
+                for enum_type in &file.enum_type {
+                    names.collect_enum(&scope, enum_type);
+                }
+            }
+        }
+        names
+    }
+
+    fn candidates(&self, candidates: Candidates) -> impl Iterator<Item = &String> {
+        let sets: Vec<&[String]> = match candidates {
+            Candidates::Types => vec![&self.messages, &self.enums, &self.oneofs],
+            Candidates::Messages => vec![&self.messages],
+            Candidates::Enums => vec![&self.enums, &self.oneofs],
+            Candidates::FieldAttributes => vec![
+                &self.plain_fields,
+                &self.oneofs,
+                &self.oneof_fields,
+                &self.enum_values,
+            ],
+            Candidates::Fields => vec![&self.fields],
+            Candidates::BoxedFields => vec![&self.plain_fields, &self.oneof_fields],
+        };
+        sets.into_iter().flatten()
+    }
+
+    fn collect_message(&mut self, scope: &str, message: &DescriptorProto) {
+        let full_name = format!("{}.{}", scope, message.name());
+        self.messages.push(full_name.clone());
+
+        for field in &message.field {
+            let oneof = field
+                .oneof_index
+                .filter(|_| !field.proto3_optional())
+                .and_then(|index| message.oneof_decl.get(usize::try_from(index).ok()?));
+            match oneof {
+                Some(oneof) => self.oneof_fields.push(format!(
+                    "{}.{}.{}",
+                    full_name,
+                    oneof.name(),
+                    field.name()
+                )),
+                None => self
+                    .plain_fields
+                    .push(format!("{}.{}", full_name, field.name())),
+            }
+            self.fields.push(format!("{}.{}", full_name, field.name()));
+        }
+        for (index, oneof) in message.oneof_decl.iter().enumerate() {
+            // Synthetic oneofs for proto3 optional fields are not generated
+            let synthetic = message.field.iter().any(|field| {
+                field.proto3_optional() && field.oneof_index == i32::try_from(index).ok()
+            });
+            if !synthetic {
+                self.oneofs.push(format!("{}.{}", full_name, oneof.name()));
+            }
+        }
+        for nested in &message.nested_type {
+            if nested.options.as_ref().is_some_and(|o| o.map_entry()) {
+                continue;
+            }
+            self.collect_message(&full_name, nested);
+        }
+        for enum_type in &message.enum_type {
+            self.collect_enum(&full_name, enum_type);
+        }
+    }
+
+    fn collect_enum(&mut self, scope: &str, enum_type: &EnumDescriptorProto) {
+        let full_name = format!("{}.{}", scope, enum_type.name());
+        for value in &enum_type.value {
+            self.enum_values
+                .push(format!("{}.{}", full_name, value.name()));
+        }
+        self.enums.push(full_name);
+    }
+
+    /// The path matching only each of the `candidates` in `prost-build`, by
+    /// the name of the candidate
+    ///
+    /// A path without a leading `.` is matched by suffix, which avoids
+    /// matching the names nested within a name as a prefix match would. The
+    /// fully-qualified name is used when another candidate ends with the
+    /// name, as long as no candidate is nested within it. Otherwise, no path
+    /// matches the name alone.
+    fn exact_paths(&self, candidates: Candidates) -> HashMap<&str, Option<&str>> {
+        let mut suffixes = HashSet::new();
+        let mut parents = HashSet::new();
+        for name in self.candidates(candidates) {
+            for (i, _) in name.match_indices('.').skip(1) {
+                suffixes.insert(&name[i..]);
+                parents.insert(&name[..i]);
+            }
+        }
+
+        self.candidates(candidates)
+            .map(|name| {
+                let exact_path = if !suffixes.contains(name.as_str()) {
+                    Some(&name[1..])
+                } else if !parents.contains(name.as_str()) {
+                    Some(name.as_str())
+                } else {
+                    None
+                };
+                (name.as_str(), exact_path)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_types::{
+        compiler::CodeGeneratorRequest,
+        field_descriptor_proto::{Label, Type},
+        FieldDescriptorProto, FileDescriptorProto,
+    };
+
+    use super::*;
+    use crate::{ParameterErrorKind, Parameters};
+
+    fn file(package: &str, messages: Vec<DescriptorProto>) -> FileDescriptorProto {
+        FileDescriptorProto {
+            name: Some(format!("{}.proto", package.replace('.', "/"))),
+            package: Some(package.to_owned()),
+            syntax: Some("proto3".to_owned()),
+            message_type: messages,
+            ..Default::default()
+        }
+    }
+
+    fn message(name: &str, nested_type: Vec<DescriptorProto>) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(name.to_owned()),
+            field: vec![FieldDescriptorProto {
+                name: Some("payload".to_owned()),
+                number: Some(1),
+                label: Some(Label::Optional as i32),
+                r#type: Some(Type::Bytes as i32),
+                ..Default::default()
+            }],
+            nested_type,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob_components_do_not_cross_dots() {
+        let glob = Regex::new(&glob_to_regex(".acme.*.v1.*Event")).unwrap();
+        assert!(glob.is_match(".acme.users.v1.UserEvent"));
+        assert!(!glob.is_match(".acme.users.v1.UserEvent.Detail"));
+        assert!(!glob.is_match(".acme.users.internal.v1.UserEvent"));
+
+        let glob = Regex::new(&glob_to_regex(".acme.**.payload")).unwrap();
+        assert!(glob.is_match(".acme.users.v1.UserEvent.payload"));
+        assert!(!glob.is_match(".other.v1.UserEvent.payload"));
+    }
+
+    #[test]
+    fn patterns_are_resolved_to_exact_paths() {
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec![
+                "acme/users/v1.proto".to_owned(),
+                "acme/users/v2.proto".to_owned(),
+                "acme/internal/v1.proto".to_owned(),
+            ],
+            parameter: Some(
+                [
+                    r"type_attribute=re:^\.acme\.[^.]+\.v1\..*Event$=#[must_use]",
+                    "type_attribute=!glob:.acme.internal.**",
+                    "bytes=glob:.acme.**.payload",
+                    "bytes=!.acme.users.v2",
+                ]
+                .join(","),
+            ),
+            proto_file: vec![
+                file(
+                    "acme.users.v1",
+                    vec![message("UserEvent", vec![message("Detail", vec![])])],
+                ),
+                file("acme.users.v2", vec![message("UserEvent", vec![])]),
+                file("acme.internal.v1", vec![message("AuditEvent", vec![])]),
+            ],
+            ..Default::default()
+        };
+
+        let files = crate::execute(&request.encode_to_vec()).expect("code generation failed");
+        let content = |name: &str| {
+            files
+                .iter()
+                .find(|f| f.name.as_deref() == Some(name))
+                .and_then(|f| f.content.as_deref())
+                .unwrap_or_else(|| panic!("{name} not generated"))
+        };
+
+        let users_v1 = content("acme/users/v1/acme.users.v1.rs");
+        assert!(
+            users_v1.contains(
+                "#[must_use]\n#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]\npub struct \
+                 UserEvent {"
+            ),
+            "matching message should have the attribute:\n{users_v1}"
+        );
+        assert!(
+            !users_v1.contains("#[must_use]\n    #[derive"),
+            "nested message should not have the attribute:\n{users_v1}"
+        );
+        assert_eq!(
+            users_v1
+                .matches("pub payload: ::prost::bytes::Bytes,")
+                .count(),
+            2,
+            "matching fields should use Bytes:\n{users_v1}"
+        );
+
+        let users_v2 = content("acme/users/v2/acme.users.v2.rs");
+        assert!(!users_v2.contains("#[must_use]"), "{users_v2}");
+        assert!(
+            users_v2.contains("pub payload: ::prost::alloc::vec::Vec<u8>,"),
+            "excluded fields should not use Bytes:\n{users_v2}"
+        );
+
+        let internal_v1 = content("acme/internal/v1/acme.internal.v1.rs");
+        assert!(
+            !internal_v1.contains("#[must_use]"),
+            "excluded message should not have the attribute:\n{internal_v1}"
+        );
+    }
+
+    #[test]
+    fn names_ending_other_names_are_matched_without_their_nested_types() {
+        let request = |parameter: &str| CodeGeneratorRequest {
+            file_to_generate: vec![
+                "acme/v1.proto".to_owned(),
+                "mirror/acme/v1.proto".to_owned(),
+            ],
+            parameter: Some(parameter.to_owned()),
+            proto_file: vec![
+                file(
+                    "acme.v1",
+                    vec![message(
+                        "Event",
+                        vec![message("Detail", vec![message("Extra", vec![])])],
+                    )],
+                ),
+                file(
+                    "mirror.acme.v1",
+                    vec![message(
+                        "Event",
+                        vec![message("Detail", vec![message("Extra", vec![])])],
+                    )],
+                ),
+            ],
+            ..Default::default()
+        };
+
+        let files = crate::execute(
+            &request("type_attribute=glob:.acme.v1.Event.Detail.Extra=#[must_use]").encode_to_vec(),
+        )
+        .expect("code generation failed");
+        let acme_v1 = files
+            .iter()
+            .find(|f| f.name.as_deref() == Some("acme/v1/acme.v1.rs"))
+            .and_then(|f| f.content.as_deref())
+            .expect("acme/v1/acme.v1.rs not generated");
+        assert_eq!(acme_v1.matches("#[must_use]").count(), 1, "{acme_v1}");
+        assert!(
+            acme_v1.contains("#[must_use]\n        #[derive"),
+            "only the innermost message should have the attribute:\n{acme_v1}"
+        );
+
+        // `.acme.v1.Event.Detail` as a path would also match `Extra`, and
+        // `acme.v1.Event.Detail` would also match the mirrored message
+        let error = crate::execute(
+            &request("type_attribute=glob:.acme.v1.Event.Detail=#[must_use]").encode_to_vec(),
+        )
+        .expect_err("a name without an exact path should be rejected");
+        assert!(
+            error.to_string().contains(
+                "`glob:.acme.v1.Event.Detail` matches `.acme.v1.Event.Detail`, which \
+                 `prost-build` cannot match"
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let err = "type_attribute=re:[=#[must_use]"
+            .parse::<Parameters>()
+            .expect_err("invalid regular expression should be rejected");
+        match err.errors()[0].kind() {
+            ParameterErrorKind::InvalidValue { reason } => assert!(
+                reason.starts_with("invalid regular expression `[`"),
+                "unexpected reason: {reason}"
+            ),
+            kind => panic!("unexpected error: {kind:?}"),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Recording requests with `dump_request=<path>`, so that they can be
+//! replayed with `--replay <path>` outside of `protoc` or _buf_
+
+use std::{fmt::Write, fs, path::Path};
+
+use prost::{
+    bytes::Buf,
+    encoding::{self, DecodeContext},
+    Message,
+};
+use prost_types::compiler::code_generator_response::File;
+
+use crate::{CodeGeneratorResponse, Error, GeneratorResultExt, PluginOpts, RawProtos, Result};
+
+/// The field number of `parameter` in a `CodeGeneratorRequest`
+const PARAMETER_TAG: u32 = 2;
+
+/// Writes the request to the path given by `dump_request=<path>`, if any,
+/// along with a description of the parameters at `<path>.txt`
+///
+/// The recorded request holds the parameters that `opts` resolved to rather
+/// than the original parameter, so that it can be replayed without any
+/// options file that it referenced. Parameters for plugins run by a combined
+/// plugin are prefixed with their section.
+pub fn dump_request(
+    raw_request: &[u8],
+    version: &str,
+    opts: &[&PluginOpts<'_>],
+) -> std::result::Result<(), Error> {
+    let path = match opts.iter().find_map(|opts| opts.dump_request()) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let plugin = opts.first().map_or("", |opts| opts.plugin().name);
+    let request = RawProtos::decode(raw_request)?;
+
+    let mut resolved = Vec::new();
+    for plugin_opts in opts {
+        let spec = plugin_opts.plugin();
+        let prefix = if opts.len() > 1 {
+            format!("{}.", spec.section)
+        } else {
+            String::new()
+        };
+        let params = plugin_opts
+            .shared_params()
+            .filter(|param| spec.accepts(param.name()))
+            .chain(plugin_opts.params());
+        resolved.extend(params.map(|param| format!("{prefix}{param}")));
+    }
+
+    let mut description = format!("{plugin} {version}\n\nparameter: {}\n", request.parameter());
+    description.push_str("\nfiles to generate:\n");
+    for file in &request.file_to_generate {
+        let _ = writeln!(description, "  {file}");
+    }
+    description.push_str("\nresolved parameters:\n");
+    for param in &resolved {
+        let _ = writeln!(description, "  {param}");
+    }
+    let _ = writeln!(
+        description,
+        "\nreplay with `{plugin} --replay {}`",
+        path.display()
+    );
+
+    let dumped = with_parameter(raw_request, &resolved.join(","))?;
+    let write = |path: &Path, contents: &[u8]| {
+        fs::write(path, contents)
+            .map_err(|e| format!("unable to write to dump_request `{}`: {e}", path.display()))
+    };
+    write(path, &dumped)?;
+    let mut description_path = path.as_os_str().to_owned();
+    description_path.push(".txt");
+    write(Path::new(&description_path), description.as_bytes())?;
+
+    Ok(())
+}
+
+/// Runs `execute` on a request recorded with `dump_request=<path>`,
+/// describing the response as text
+///
+/// Errors from `execute` are reported within the response, as they would
+/// be to `protoc`.
+pub fn replay_request<F>(path: &Path, execute: F) -> std::result::Result<String, Error>
+where
+    F: FnOnce(&[u8]) -> Result,
+{
+    let raw_request =
+        fs::read(path).map_err(|e| format!("unable to read `{}`: {e}", path.display()))?;
+    let response = execute(&raw_request).unwrap_codegen_response();
+    Ok(describe_response(&response))
+}
+
+/// Describes a response as text, including its error and any files
+fn describe_response(response: &CodeGeneratorResponse) -> String {
+    let mut description = String::new();
+    if let Some(error) = &response.error {
+        let _ = writeln!(description, "error: {error}");
+    }
+    let _ = writeln!(
+        description,
+        "supported_features: {}",
+        response.supported_features()
+    );
+    if let (Some(minimum), Some(maximum)) = (response.minimum_edition, response.maximum_edition) {
+        let _ = writeln!(description, "supported editions: {minimum}..={maximum}");
+    }
+    if !response.file.is_empty() {
+        description.push('\n');
+        description.push_str(&describe_files(&response.file));
+    }
+
+    description
+}
+
+/// Describes generated files as text, with the content of each file
+/// following its name and insertion point
+pub(crate) fn describe_files(files: &[File]) -> String {
+    let mut description = String::new();
+    for (index, file) in files.iter().enumerate() {
+        if index > 0 {
+            description.push('\n');
+        }
+        let _ = write!(description, "=== {}", file.name());
+        if let Some(insertion_point) = &file.insertion_point {
+            let _ = write!(description, " @@protoc_insertion_point({insertion_point})");
+        }
+        let _ = writeln!(description, " ===");
+        description.push_str(file.content());
+        if !file.content().ends_with('\n') {
+            description.push('\n');
+        }
+    }
+
+    description
+}
+
+/// Replaces the parameter of an encoded request, leaving every other field
+/// untouched
+fn with_parameter(raw_request: &[u8], parameter: &str) -> std::result::Result<Vec<u8>, Error> {
+    let mut dumped = Vec::with_capacity(raw_request.len());
+    let mut buf = raw_request;
+    while buf.has_remaining() {
+        let field = buf;
+        let (tag, wire_type) = encoding::decode_key(&mut buf)?;
+        encoding::skip_field(wire_type, tag, &mut buf, DecodeContext::default())?;
+        if tag != PARAMETER_TAG {
+            dumped.extend_from_slice(&field[..field.len() - buf.len()]);
+        }
+    }
+
+    if !parameter.is_empty() {
+        encoding::string::encode(PARAMETER_TAG, &parameter.to_owned(), &mut dumped);
+    }
+    Ok(dumped)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{
+        compiler::{CodeGeneratorRequest, Version},
+        FileDescriptorProto,
+    };
+
+    use super::*;
+    use crate::PluginSpec;
+
+    const PLUGIN: PluginSpec = PluginSpec {
+        name: "protoc-gen-test",
+        section: "prost",
+        params: &[crate::ParamSpec::new("no_server", "no_server(=<boolean>)")],
+    };
+
+    #[test]
+    fn dumped_requests_hold_the_resolved_parameters() {
+        let dir =
+            std::env::temp_dir().join(format!("protoc-gen-prost-replay-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("options.toml");
+        fs::write(
+            &config,
+            "[default]\nunknown = true\n\n[prost]\nno_server = true\n",
+        )
+        .unwrap();
+        let path = dir.join("request.bin");
+
+        let parameter = format!(
+            "config={},dump_request={},cache_dir=cache",
+            config.display(),
+            path.display()
+        );
+        let request = CodeGeneratorRequest {
+            file_to_generate: vec!["a.proto".to_owned()],
+            parameter: Some(parameter.clone()),
+            proto_file: vec![FileDescriptorProto {
+                name: Some("a.proto".to_owned()),
+                ..Default::default()
+            }],
+            compiler_version: Some(Version {
+                major: Some(29),
+                ..Default::default()
+            }),
+        };
+        let opts = PluginOpts::load(&parameter, &PLUGIN).unwrap();
+        dump_request(&request.encode_to_vec(), "1.0.0", &[&opts]).unwrap();
+
+        let dumped = CodeGeneratorRequest::decode(fs::read(&path).unwrap().as_slice()).unwrap();
+        assert_eq!(
+            dumped,
+            CodeGeneratorRequest {
+                parameter: Some("no_server".to_owned()),
+                ..request
+            }
+        );
+
+        let description = fs::read_to_string(dir.join("request.bin.txt")).unwrap();
+        assert!(description.starts_with("protoc-gen-test 1.0.0\n"));
+        assert!(description.contains("\nfiles to generate:\n  a.proto\n"));
+        assert!(description.contains("\nresolved parameters:\n  no_server\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replayed_responses_are_described() {
+        let response = CodeGeneratorResponse {
+            supported_features: Some(3),
+            minimum_edition: Some(998),
+            maximum_edition: Some(1000),
+            file: vec![
+                File {
+                    name: Some("a.rs".to_owned()),
+                    content: Some("// @@protoc_insertion_point(module)\n".to_owned()),
+                    ..Default::default()
+                },
+                File {
+                    name: Some("a.rs".to_owned()),
+                    insertion_point: Some("module".to_owned()),
+                    content: Some("include!(\"a.tonic.rs\");".to_owned()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            describe_response(&response),
+            "supported_features: 3\nsupported editions: 998..=1000\n\n=== a.rs ===\n// \
+             @@protoc_insertion_point(module)\n\n=== a.rs @@protoc_insertion_point(module) \
+             ===\ninclude!(\"a.tonic.rs\");\n"
+        );
+
+        let error = Err::<Vec<File>, Error>("unknown parameter".into()).unwrap_codegen_response();
+        assert!(describe_response(&error).starts_with("error: unknown parameter\n"));
+    }
+}
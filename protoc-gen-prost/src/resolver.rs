@@ -1,10 +1,14 @@
+//! Resolving the Rust paths of protobuf types
+
 use std::{collections::HashMap, iter};
 
+use heck::ToUpperCamelCase;
 use prost_build::Module;
 
-use crate::util;
-
-pub(crate) struct Resolver {
+/// Resolves the Rust path of a protobuf type as _Prost!_ generates it,
+/// taking `extern_path` parameters into account
+#[derive(Debug)]
+pub struct Resolver {
     extern_root: Node,
     compile_well_known_types: bool,
 }
@@ -44,7 +48,7 @@ impl Node {
                 let module = Module::from_protobuf_package_name(package);
                 let ret_val = module
                     .parts()
-                    .chain(iter::once(util::to_upper_camel(type_name).as_str()))
+                    .chain(iter::once(to_upper_camel(type_name).as_str()))
                     .fold(prefix.to_owned(), |mut acc, next| {
                         acc.push_str("::");
                         acc.push_str(next);
@@ -60,7 +64,9 @@ impl Node {
 }
 
 impl Resolver {
-    pub(crate) fn new(extern_path: Vec<(String, String)>, compile_well_known_types: bool) -> Self {
+    /// Creates a resolver for the given `extern_path` parameters, which maps
+    /// the well-known types to `prost-types` unless they are compiled
+    pub fn new(extern_path: Vec<(String, String)>, compile_well_known_types: bool) -> Self {
         let mut extern_root = Node::default();
 
         if !compile_well_known_types {
@@ -93,11 +99,15 @@ impl Resolver {
         }
     }
 
-    pub(crate) fn compile_well_known_types(&self) -> bool {
+    /// Whether the well-known types are compiled rather than taken from
+    /// `prost-types`
+    pub fn compile_well_known_types(&self) -> bool {
         self.compile_well_known_types
     }
 
-    pub(crate) fn resolve_ident(&self, from: &Module, to_fq: &str) -> String {
+    /// The path of the fully-qualified protobuf type `to_fq`, relative to
+    /// the module `from`
+    pub fn resolve_ident(&self, from: &Module, to_fq: &str) -> String {
         if let Some(proto_ident) = self.extern_root.resolve_extern(to_fq) {
             return proto_ident;
         }
@@ -107,9 +117,9 @@ impl Resolver {
 
         let (down, prefix) = difference(from, &to);
 
-        iter::repeat_n("super".to_owned(), down)
+        std::iter::repeat_n("super".to_owned(), down)
             .chain(to.parts().skip(prefix).map(|s| s.to_owned()))
-            .chain(iter::once(util::to_upper_camel(type_name)))
+            .chain(iter::once(to_upper_camel(type_name)))
             .reduce(|mut l, r| {
                 l.push_str("::");
                 l.push_str(&r);
@@ -119,6 +129,17 @@ impl Resolver {
     }
 }
 
+/// Converts a `snake_case` identifier to an `UpperCamel` case Rust type identifier.
+fn to_upper_camel(s: &str) -> String {
+    let mut ident = s.to_upper_camel_case();
+
+    // Suffix an underscore for the `Self` Rust keyword as it is not allowed as raw identifier.
+    if ident == "Self" {
+        ident += "_";
+    }
+    ident
+}
+
 fn difference(left: &Module, right: &Module) -> (usize, usize) {
     let mut left_parts = left.parts();
     let mut right_parts = right.parts();
@@ -0,0 +1,366 @@
+//! Rust attributes declared with custom options in the proto files
+//!
+//! The options understood here are declared in `proto/prost/rust.proto`:
+//!
+//! * `(prost.rust)` on messages
+//! * `(prost.rust_field)` on fields
+//! * `(prost.rust_enum)` on enums
+//! * `(prost.rust_oneof)` on oneofs
+//!
+//! Each holds a `RustOptions` message listing the `attribute`s to add to the
+//! generated item and the traits to `derive` for it. These are translated
+//! into the equivalent `prost-build` attribute parameters.
+//!
+//! The decoded descriptors from `prost-types` drop extensions set on options,
+//! so these are read from the raw file descriptors using the wire-compatible
+//! types below.
+
+use prost::Message;
+
+use crate::{Error, ModuleRequestSet, ProstParameters};
+
+/// Adds the attributes declared with custom options in every file of the
+/// request to the parameters for `prost-build`
+pub(crate) fn apply(
+    module_request_set: &ModuleRequestSet,
+    prost: &mut ProstParameters,
+) -> Result<(), Error> {
+    for (_, request) in module_request_set.requests() {
+        for raw in request.raw_files() {
+            let file = RustFile::decode(raw)?;
+            for message in &file.message_type {
+                apply_message(file.package(), message, prost)?;
+            }
+            for enum_type in &file.enum_type {
+                apply_enum(file.package(), enum_type, prost);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_message(
+    scope: &str,
+    message: &RustMessage,
+    prost: &mut ProstParameters,
+) -> Result<(), Error> {
+    let full_name = format!("{}.{}", scope, message.name());
+
+    for attribute in rust_attributes(message.options.as_ref()) {
+        prost.message_attribute.push(&full_name, attribute);
+    }
+    for field in &message.field {
+        // Fields in a oneof are generated as variants of the oneof enum
+        let path = match field.oneof_index {
+            Some(index) if !field.proto3_optional() => {
+                let oneof = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| message.oneof_decl.get(index))
+                    .ok_or_else(|| format!("{full_name}.{}: invalid oneof", field.name()))?;
+                format!("{}.{}.{}", full_name, oneof.name(), field.name())
+            }
+            _ => format!("{}.{}", full_name, field.name()),
+        };
+        if let Some(options) = field.options.as_ref().and_then(|o| o.rust.as_ref()) {
+            if !options.derive.is_empty() {
+                return Err(format!("{path}: `derive` is not supported on fields").into());
+            }
+            for attribute in &options.attribute {
+                prost.field_attribute.push(&path, attribute.clone());
+            }
+        }
+    }
+    for oneof in &message.oneof_decl {
+        let path = format!("{}.{}", full_name, oneof.name());
+        for attribute in rust_attributes(oneof.options.as_ref()) {
+            prost.type_attribute.push(&path, attribute);
+        }
+    }
+    for nested in &message.nested_type {
+        apply_message(&full_name, nested, prost)?;
+    }
+    for enum_type in &message.enum_type {
+        apply_enum(&full_name, enum_type, prost);
+    }
+
+    Ok(())
+}
+
+fn apply_enum(scope: &str, enum_type: &RustEnum, prost: &mut ProstParameters) {
+    let full_name = format!("{}.{}", scope, enum_type.name());
+    for attribute in rust_attributes(enum_type.options.as_ref()) {
+        prost.enum_attribute.push(&full_name, attribute);
+    }
+}
+
+/// The attributes to add to a generated type, with the derived traits first
+fn rust_attributes(options: Option<&Options>) -> Vec<String> {
+    let options = match options.and_then(|o| o.rust.as_ref()) {
+        Some(options) => options,
+        None => return Vec::new(),
+    };
+
+    let mut attributes = Vec::with_capacity(options.attribute.len() + 1);
+    if !options.derive.is_empty() {
+        attributes.push(format!("#[derive({})]", options.derive.join(", ")));
+    }
+    attributes.extend(options.attribute.iter().cloned());
+    attributes
+}
+
+/// Wire-compatible `FileDescriptorProto` retaining only Rust options
+#[derive(Clone, PartialEq, Message)]
+struct RustFile {
+    #[prost(string, optional, tag = "2")]
+    package: Option<String>,
+    #[prost(message, repeated, tag = "4")]
+    message_type: Vec<RustMessage>,
+    #[prost(message, repeated, tag = "5")]
+    enum_type: Vec<RustEnum>,
+}
+
+/// Wire-compatible `DescriptorProto` retaining only Rust options
+#[derive(Clone, PartialEq, Message)]
+struct RustMessage {
+    #[prost(string, optional, tag = "1")]
+    name: Option<String>,
+    #[prost(message, repeated, tag = "2")]
+    field: Vec<RustField>,
+    #[prost(message, repeated, tag = "3")]
+    nested_type: Vec<RustMessage>,
+    #[prost(message, repeated, tag = "4")]
+    enum_type: Vec<RustEnum>,
+    #[prost(message, optional, tag = "7")]
+    options: Option<Options>,
+    #[prost(message, repeated, tag = "8")]
+    oneof_decl: Vec<RustOneof>,
+}
+
+/// Wire-compatible `FieldDescriptorProto` retaining only Rust options
+#[derive(Clone, PartialEq, Message)]
+struct RustField {
+    #[prost(string, optional, tag = "1")]
+    name: Option<String>,
+    #[prost(message, optional, tag = "8")]
+    options: Option<Options>,
+    #[prost(int32, optional, tag = "9")]
+    oneof_index: Option<i32>,
+    #[prost(bool, optional, tag = "17")]
+    proto3_optional: Option<bool>,
+}
+
+/// Wire-compatible `EnumDescriptorProto` retaining only Rust options
+#[derive(Clone, PartialEq, Message)]
+struct RustEnum {
+    #[prost(string, optional, tag = "1")]
+    name: Option<String>,
+    #[prost(message, optional, tag = "3")]
+    options: Option<Options>,
+}
+
+/// Wire-compatible `OneofDescriptorProto` retaining only Rust options
+#[derive(Clone, PartialEq, Message)]
+struct RustOneof {
+    #[prost(string, optional, tag = "1")]
+    name: Option<String>,
+    #[prost(message, optional, tag = "2")]
+    options: Option<Options>,
+}
+
+/// Wire-compatible `MessageOptions`, `FieldOptions`, `EnumOptions`, or
+/// `OneofOptions`, which all carry the Rust options at field number 51712
+#[derive(Clone, PartialEq, Message)]
+struct Options {
+    #[prost(message, optional, tag = "51712")]
+    rust: Option<RustOptions>,
+}
+
+/// Wire-compatible `prost.RustOptions`
+#[derive(Clone, PartialEq, Message)]
+struct RustOptions {
+    #[prost(string, repeated, tag = "1")]
+    attribute: Vec<String>,
+    #[prost(string, repeated, tag = "2")]
+    derive: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::encoding;
+    use prost_types::{
+        compiler::CodeGeneratorRequest,
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, OneofDescriptorProto,
+    };
+
+    use super::*;
+    use crate::RawProtos;
+
+    /// Encodes `descriptor` with Rust options set in its options at `tag`
+    fn with_rust_options(
+        descriptor: impl Message,
+        tag: u32,
+        attribute: &[&str],
+        derive: &[&str],
+    ) -> Vec<u8> {
+        let options = Options {
+            rust: Some(RustOptions {
+                attribute: attribute.iter().map(|&a| a.to_owned()).collect(),
+                derive: derive.iter().map(|&d| d.to_owned()).collect(),
+            }),
+        };
+
+        let mut encoded = descriptor.encode_to_vec();
+        encoding::message::encode(tag, &options, &mut encoded);
+        encoded
+    }
+
+    /// Appends pre-encoded descriptors to the repeated field at `tag`
+    fn with_descriptors(mut encoded: Vec<u8>, tag: u32, descriptors: &[Vec<u8>]) -> Vec<u8> {
+        for descriptor in descriptors {
+            encoding::bytes::encode(tag, descriptor, &mut encoded);
+        }
+        encoded
+    }
+
+    fn field(name: &str, number: i32, oneof_index: Option<i32>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(Type::String as i32),
+            oneof_index,
+            ..Default::default()
+        }
+    }
+
+    fn file(message: Vec<u8>, enum_type: Option<Vec<u8>>) -> Vec<u8> {
+        let file = FileDescriptorProto {
+            name: Some("example.proto".to_owned()),
+            package: Some("example".to_owned()),
+            syntax: Some("proto3".to_owned()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let file = with_descriptors(file, 4, &[message]);
+        with_descriptors(file, 5, enum_type.as_slice())
+    }
+
+    fn generate(file: Vec<u8>) -> crate::Result {
+        let mut request = CodeGeneratorRequest {
+            file_to_generate: vec!["example.proto".to_owned()],
+            ..Default::default()
+        }
+        .encode_to_vec();
+        RawProtos {
+            proto_file: vec![file.into()],
+            ..Default::default()
+        }
+        .encode(&mut request)
+        .unwrap();
+
+        crate::execute(&request)
+    }
+
+    #[test]
+    fn rust_options_are_added_as_attributes() {
+        let message = DescriptorProto {
+            name: Some("Example".to_owned()),
+            nested_type: vec![DescriptorProto {
+                name: Some("Inner".to_owned()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let message = with_rust_options(message, 7, &["#[must_use]"], &["PartialOrd", "Ord"]);
+        let message = with_descriptors(
+            message,
+            2,
+            &[
+                with_rust_options(
+                    field("id", 1, None),
+                    8,
+                    &["#[doc(alias = \"identifier\")]"],
+                    &[],
+                ),
+                with_rust_options(
+                    field("name", 2, Some(0)),
+                    8,
+                    &["#[doc(alias = \"label\")]"],
+                    &[],
+                ),
+            ],
+        );
+        let oneof = OneofDescriptorProto {
+            name: Some("kind".to_owned()),
+            ..Default::default()
+        };
+        let message = with_descriptors(
+            message,
+            8,
+            &[with_rust_options(oneof, 2, &[], &["PartialOrd"])],
+        );
+
+        let enum_type = EnumDescriptorProto {
+            name: Some("Color".to_owned()),
+            value: vec![EnumValueDescriptorProto {
+                name: Some("COLOR_UNSPECIFIED".to_owned()),
+                number: Some(0),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let enum_type = with_rust_options(enum_type, 3, &["#[non_exhaustive]"], &[]);
+
+        let files = generate(file(message, Some(enum_type))).expect("code generation failed");
+        let example_rs = files
+            .iter()
+            .find(|f| f.name.as_deref() == Some("example/example.rs"))
+            .and_then(|f| f.content.as_deref())
+            .expect("example/example.rs not generated");
+
+        for expected in
+            [
+                "#[derive(PartialOrd, Ord)]\n#[must_use]\n#[derive(Clone, PartialEq, Eq, Hash, \
+                 ::prost::Message)]\npub struct Example {",
+                "#[doc(alias = \"identifier\")]\n    pub id: ::prost::alloc::string::String,",
+                "#[derive(PartialOrd)]\n    #[derive(Clone, PartialEq, Eq, Hash, \
+                 ::prost::Oneof)]\n    pub enum Kind {",
+                "#[doc(alias = \"label\")]\n        Name(",
+                "#[non_exhaustive]\n#[derive(Clone, Copy, Debug,",
+            ]
+        {
+            assert!(
+                example_rs.contains(expected),
+                "expected `{expected}` in:\n{example_rs}"
+            );
+        }
+        assert!(
+            !example_rs.contains("#[must_use]\n    #[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]\n    pub struct Inner"),
+            "nested messages should not inherit attributes:\n{example_rs}"
+        );
+    }
+
+    #[test]
+    fn derive_is_rejected_on_fields() {
+        let message = DescriptorProto {
+            name: Some("Example".to_owned()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let message = with_descriptors(
+            message,
+            2,
+            &[with_rust_options(field("id", 1, None), 8, &[], &["Eq"])],
+        );
+
+        let error =
+            generate(file(message, None)).expect_err("derive on a field should be rejected");
+        assert!(
+            error.to_string().contains("example.Example.id"),
+            "unexpected error: {error}"
+        );
+    }
+}
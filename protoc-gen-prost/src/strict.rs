@@ -0,0 +1,146 @@
+//! Extern paths for the types of files that were not requested
+//!
+//! A protobuf package may be split between several crates, each generated
+//! from only some of the files of the package. Rather than generating the
+//! types of every file of a package, only the files that were requested are
+//! generated, and the types of the other files are given extern paths.
+
+use crate::{Error, ModuleRequestSet, Resolver};
+
+/// The extern paths to use when only the requested files are generated
+///
+/// An extern path that covers the types of a requested file, such as one
+/// for the package of a crate that generates its other files, is replaced
+/// with an extern path for each type it covers in a file that was not
+/// requested. Every type in a file that was not requested, but whose package
+/// is generated, must be covered by an extern path.
+pub fn strict_extern_paths(
+    module_request_set: &ModuleRequestSet,
+    extern_path: &[(String, String)],
+) -> Result<Vec<(String, String)>, Error> {
+    let mut requested = Vec::new();
+    let mut unrequested = Vec::new();
+    for (_, request) in module_request_set.requests() {
+        let requested_files: Vec<&str> = request.requested_files().map(|f| f.name()).collect();
+        for file in request.files() {
+            let scope = match file.package() {
+                "" => String::new(),
+                package => format!(".{package}"),
+            };
+            let types = file
+                .message_type
+                .iter()
+                .map(|message| message.name())
+                .chain(file.enum_type.iter().map(|enumeration| enumeration.name()))
+                .map(|name| format!("{scope}.{name}"));
+            if requested_files.contains(&file.name()) {
+                requested.extend(types);
+            } else {
+                // Files that share a package with requested files would
+                // otherwise be generated along with them
+                let generated = !requested_files.is_empty();
+                unrequested.extend(types.map(|name| (name, file.name(), generated)));
+            }
+        }
+    }
+
+    let mut strict = Vec::new();
+    for (proto_path, rust_path) in extern_path {
+        if !requested.iter().any(|name| is_within(name, proto_path)) {
+            strict.push((proto_path.clone(), rust_path.clone()));
+            continue;
+        }
+
+        let resolver = Resolver::new(vec![(proto_path.clone(), rust_path.clone())], true);
+        let from = prost_build::Module::from_parts(Vec::<String>::new());
+        for (name, ..) in &unrequested {
+            if is_within(name, proto_path) {
+                strict.push((name.clone(), resolver.resolve_ident(&from, name)));
+            }
+        }
+    }
+
+    for (name, file, generated) in &unrequested {
+        let covered = strict
+            .iter()
+            .any(|(proto_path, _)| proto_path == name || is_within(name, proto_path));
+        if *generated && !covered {
+            return Err(format!(
+                "`{name}` is defined in `{file}`, which was not requested; add an `extern_path` \
+                 for it or for its package"
+            )
+            .into());
+        }
+    }
+
+    Ok(strict)
+}
+
+/// Whether the type `name` is within the proto path `proto_path`, but is not
+/// the type that it names
+fn is_within(name: &str, proto_path: &str) -> bool {
+    proto_path == "."
+        || name
+            .strip_prefix(proto_path)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file, request};
+
+    fn split_package() -> ModuleRequestSet {
+        request()
+            .file(
+                file("foo/v1/local.proto")
+                    .package("foo.v1")
+                    .import("foo/v1/remote.proto")
+                    .message("Local", |m| m),
+            )
+            .import(
+                file("foo/v1/remote.proto")
+                    .package("foo.v1")
+                    .message("Remote", |m| m)
+                    .enumeration("Kind", |e| e.value("KIND_UNSPECIFIED", 0)),
+            )
+            .import(
+                file("bar/v1/bar.proto")
+                    .package("bar.v1")
+                    .message("Bar", |m| m),
+            )
+            .module_request_set()
+    }
+
+    #[test]
+    fn package_extern_paths_only_cover_types_that_were_not_requested() {
+        let extern_path = [
+            (".foo.v1".to_owned(), "::remote::foo::v1".to_owned()),
+            (".bar".to_owned(), "::bar".to_owned()),
+        ];
+        assert_eq!(
+            strict_extern_paths(&split_package(), &extern_path).unwrap(),
+            [
+                (
+                    ".foo.v1.Remote".to_owned(),
+                    "::remote::foo::v1::Remote".to_owned()
+                ),
+                (
+                    ".foo.v1.Kind".to_owned(),
+                    "::remote::foo::v1::Kind".to_owned()
+                ),
+                (".bar".to_owned(), "::bar".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn types_that_were_not_requested_need_an_extern_path() {
+        let error = strict_extern_paths(&split_package(), &[]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`.foo.v1.Remote` is defined in `foo/v1/remote.proto`, which was not requested; add \
+             an `extern_path` for it or for its package"
+        );
+    }
+}
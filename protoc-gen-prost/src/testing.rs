@@ -0,0 +1,988 @@
+//! Helpers for testing plugins and [`Generator`][crate::Generator]s
+//!
+//! Requests can be built in code with [`request()`] and [`file()`], which
+//! fill in the source code info that _Prost!_ expects for every definition,
+//! or from encoded [`FileDescriptorSet`]s checked in as fixtures.
+//!
+//! Generated files can be compared with an expected snapshot. Set
+//! `PROTOC_GEN_PROST_BLESS=1` to write the generated files to the snapshots
+//! instead, and review the changes as part of the diff.
+//!
+//! [`FileDescriptorSet`]: prost_types::FileDescriptorSet
+
+use std::{env, fs, path::Path};
+
+use prost::{encoding, Message};
+use prost_types::{
+    compiler::{code_generator_response::File, CodeGeneratorRequest},
+    field_descriptor_proto::{Label, Type},
+    source_code_info::Location,
+    DescriptorProto, EnumDescriptorProto, EnumOptions, EnumValueDescriptorProto, EnumValueOptions,
+    FieldDescriptorProto, FieldOptions, FileDescriptorProto, FileOptions, MessageOptions,
+    MethodDescriptorProto, MethodOptions, OneofDescriptorProto, ServiceDescriptorProto,
+    ServiceOptions, SourceCodeInfo,
+};
+
+use crate::{cli::RawFileDescriptorSet, replay::describe_files, ModuleRequestSet, PluginRequest};
+
+/// The environment variable that causes snapshots to be updated rather
+/// than compared
+pub const BLESS_VAR: &str = "PROTOC_GEN_PROST_BLESS";
+
+/// The field number of `proto_file` in a `CodeGeneratorRequest`
+const REQUEST_PROTO_FILE_TAG: u32 = 15;
+
+/// The field number of `edition` in a `FileDescriptorProto`
+const FILE_EDITION_TAG: u32 = 14;
+
+/// Builds an encoded [`CodeGeneratorRequest`] to generate `files` from an
+/// encoded [`FileDescriptorSet`], as `protoc` would with `parameter`
+///
+/// The descriptor set must include the files imported by `files`, as
+/// written by `protoc --include_imports` or `buf build`.
+///
+/// # Panics
+///
+/// Panics if the descriptor set cannot be decoded or does not hold `files`.
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+/// [`FileDescriptorSet`]: prost_types::FileDescriptorSet
+pub fn request_from_descriptor_set(
+    descriptor_set: &[u8],
+    files: &[&str],
+    parameter: &str,
+) -> Vec<u8> {
+    let descriptor_set =
+        RawFileDescriptorSet::decode(descriptor_set).expect("invalid FileDescriptorSet");
+    let files: Vec<String> = files.iter().map(|&file| file.to_owned()).collect();
+    crate::cli::encode_request(vec![descriptor_set], &files, parameter)
+        .unwrap_or_else(|e| panic!("unable to build request: {e}"))
+}
+
+/// Asserts that `files` match the snapshot at `path`
+///
+/// Each file is recorded with its name and insertion point, followed by its
+/// content, in the order that the plugin generated them. When
+/// `PROTOC_GEN_PROST_BLESS` is set, the snapshot is written instead.
+///
+/// # Panics
+///
+/// Panics if the snapshot is missing or differs from `files`, naming the
+/// first line that differs.
+#[track_caller]
+pub fn assert_snapshot(path: impl AsRef<Path>, files: &[File]) {
+    let path = path.as_ref();
+    let actual = describe_files(files);
+
+    if env::var_os(BLESS_VAR).is_some_and(|bless| !bless.is_empty() && bless != "0") {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("unable to create snapshot directory");
+        }
+        fs::write(path, actual)
+            .unwrap_or_else(|e| panic!("unable to write `{}`: {e}", path.display()));
+        return;
+    }
+
+    let expected = match fs::read_to_string(path) {
+        Ok(expected) => expected,
+        Err(e) => panic!(
+            "unable to read snapshot `{}`: {e}\nrun with {BLESS_VAR}=1 to create it",
+            path.display()
+        ),
+    };
+    if expected == actual {
+        return;
+    }
+
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (e, a) => panic!(
+                "generated files differ from snapshot `{}` at line {line}\n  expected: {}\n    \
+                 actual: {}\nrun with {BLESS_VAR}=1 to update it",
+                path.display(),
+                e.unwrap_or("<end of snapshot>"),
+                a.unwrap_or("<end of output>"),
+            ),
+        }
+    }
+}
+
+/// Starts building a [`CodeGeneratorRequest`]
+///
+/// ```
+/// use protoc_gen_prost::testing::{file, request, FieldType};
+///
+/// let request = request()
+///     .parameter("file_descriptor_set")
+///     .file(file("a.proto").package("x").message("M", |m| {
+///         m.field("id", FieldType::Int64, 1)
+///     }))
+///     .encode();
+/// let files = protoc_gen_prost::execute(&request).unwrap();
+/// ```
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+pub fn request() -> RequestBuilder {
+    RequestBuilder::default()
+}
+
+/// Starts building a `proto3` file named `name`
+pub fn file(name: &str) -> FileBuilder {
+    FileBuilder {
+        file: FileDescriptorProto {
+            name: Some(name.to_owned()),
+            syntax: Some("proto3".to_owned()),
+            ..Default::default()
+        },
+        comments: Comments::default(),
+        edition: None,
+    }
+}
+
+/// A builder of [`CodeGeneratorRequest`]s
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
+#[derive(Clone, Debug, Default)]
+pub struct RequestBuilder {
+    request: CodeGeneratorRequest,
+    /// The edition declared by each file in `request`, which `prost-types`
+    /// cannot represent
+    editions: Vec<Option<i32>>,
+}
+
+impl RequestBuilder {
+    /// Adds a file for which code is generated
+    ///
+    /// Files must be added after the files that they import.
+    pub fn file(mut self, file: FileBuilder) -> Self {
+        self.editions.push(file.edition);
+        let file = file.build();
+        self.request.file_to_generate.push(file.name().to_owned());
+        self.request.proto_file.push(file);
+        self
+    }
+
+    /// Adds a file that is only imported by the files for which code is
+    /// generated
+    pub fn import(mut self, file: FileBuilder) -> Self {
+        self.editions.push(file.edition);
+        self.request.proto_file.push(file.build());
+        self
+    }
+
+    /// Sets the parameter passed to the plugin
+    pub fn parameter(mut self, parameter: &str) -> Self {
+        self.request.parameter = Some(parameter.to_owned());
+        self
+    }
+
+    /// The request that has been built, without the editions declared by
+    /// its files
+    pub fn build(self) -> CodeGeneratorRequest {
+        self.request
+    }
+
+    /// The encoded request, as passed to a plugin by `protoc`
+    pub fn encode(&self) -> Vec<u8> {
+        let request = CodeGeneratorRequest {
+            proto_file: Vec::new(),
+            ..self.request.clone()
+        };
+        let mut buf = request.encode_to_vec();
+        for (file, edition) in self.request.proto_file.iter().zip(&self.editions) {
+            let mut file = file.encode_to_vec();
+            if let Some(edition) = edition {
+                encoding::int32::encode(FILE_EDITION_TAG, edition, &mut file);
+            }
+            encoding::bytes::encode(REQUEST_PROTO_FILE_TAG, &file, &mut buf);
+        }
+        buf
+    }
+
+    /// The modules of the request, as laid out by `protoc-gen-prost` with its
+    /// default options, for testing a [`Generator`][crate::Generator]
+    pub fn module_request_set(&self) -> ModuleRequestSet {
+        let request = PluginRequest::decode(&self.encode()).expect("invalid request");
+        ModuleRequestSet::from_request(request, None, false).expect("invalid request")
+    }
+}
+
+/// The type of a field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType<'a> {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Bytes,
+    Uint32,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+    /// A message, named by its fully-qualified name, such as `.x.M`
+    Message(&'a str),
+    /// An enum, named by its fully-qualified name, such as `.x.E`
+    Enum(&'a str),
+}
+
+impl FieldType<'_> {
+    fn apply(self, field: &mut FieldDescriptorProto) {
+        let (r#type, type_name) = match self {
+            Self::Double => (Type::Double, None),
+            Self::Float => (Type::Float, None),
+            Self::Int64 => (Type::Int64, None),
+            Self::Uint64 => (Type::Uint64, None),
+            Self::Int32 => (Type::Int32, None),
+            Self::Fixed64 => (Type::Fixed64, None),
+            Self::Fixed32 => (Type::Fixed32, None),
+            Self::Bool => (Type::Bool, None),
+            Self::String => (Type::String, None),
+            Self::Bytes => (Type::Bytes, None),
+            Self::Uint32 => (Type::Uint32, None),
+            Self::Sfixed32 => (Type::Sfixed32, None),
+            Self::Sfixed64 => (Type::Sfixed64, None),
+            Self::Sint32 => (Type::Sint32, None),
+            Self::Sint64 => (Type::Sint64, None),
+            Self::Message(name) => (Type::Message, Some(name)),
+            Self::Enum(name) => (Type::Enum, Some(name)),
+        };
+        field.set_type(r#type);
+        field.type_name = type_name.map(str::to_owned);
+    }
+}
+
+/// Leading and trailing comments, by path relative to the element that
+/// holds them
+#[derive(Clone, Debug, Default)]
+struct Comments {
+    leading: Vec<(Vec<i32>, String)>,
+    trailing: Vec<(Vec<i32>, String)>,
+}
+
+impl Comments {
+    /// Takes the comments of a child element, found at `field` and `index`
+    fn nest(&mut self, field: i32, index: usize, child: Comments) {
+        for (comments, child) in [
+            (&mut self.leading, child.leading),
+            (&mut self.trailing, child.trailing),
+        ] {
+            for (mut path, comment) in child {
+                path.splice(0..0, [field, index as i32]);
+                comments.push((path, comment));
+            }
+        }
+    }
+
+    fn set(&mut self, comment: &str) {
+        self.leading.push((Vec::new(), comment.to_owned()));
+    }
+
+    fn set_trailing(&mut self, comment: &str) {
+        self.trailing.push((Vec::new(), comment.to_owned()));
+    }
+}
+
+fn find_comment(comments: &[(Vec<i32>, String)], path: &[i32]) -> Option<String> {
+    comments
+        .iter()
+        .find(|(p, _)| p == path)
+        .map(|(_, comment)| recorded_comment(comment))
+}
+
+/// A builder of [`FileDescriptorProto`]s
+#[derive(Clone, Debug)]
+pub struct FileBuilder {
+    file: FileDescriptorProto,
+    comments: Comments,
+    edition: Option<i32>,
+}
+
+impl FileBuilder {
+    /// Sets the package of the file
+    pub fn package(mut self, package: &str) -> Self {
+        self.file.package = Some(package.to_owned());
+        self
+    }
+
+    /// Sets the leading comment of the `package` statement
+    pub fn package_comment(mut self, comment: &str) -> Self {
+        self.comments.leading.push((vec![2], comment.to_owned()));
+        self
+    }
+
+    /// Sets the syntax of the file, such as `proto2`
+    pub fn syntax(mut self, syntax: &str) -> Self {
+        self.file.syntax = Some(syntax.to_owned());
+        self
+    }
+
+    /// Declares an edition, such as [`MAXIMUM_EDITION`], with its default
+    /// features
+    ///
+    /// The edition is only kept in the encoded request.
+    ///
+    /// [`MAXIMUM_EDITION`]: crate::MAXIMUM_EDITION
+    pub fn edition(mut self, edition: i32) -> Self {
+        self.file.syntax = Some("editions".to_owned());
+        self.edition = Some(edition);
+        self
+    }
+
+    /// Imports another file by name
+    pub fn import(mut self, name: &str) -> Self {
+        self.file.dependency.push(name.to_owned());
+        self
+    }
+
+    /// Sets the options of the file
+    pub fn options(mut self, options: FileOptions) -> Self {
+        self.file.options = Some(options);
+        self
+    }
+
+    /// Adds a message
+    pub fn message<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(MessageBuilder) -> MessageBuilder,
+    {
+        let mut message = f(MessageBuilder::new(name));
+        let index = self.file.message_type.len();
+        self.comments
+            .nest(4, index, std::mem::take(&mut message.comments));
+        self.file.message_type.push(message.build());
+        self
+    }
+
+    /// Adds an enum
+    pub fn enumeration<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(EnumBuilder) -> EnumBuilder,
+    {
+        let enumeration = f(EnumBuilder::new(name));
+        let index = self.file.enum_type.len();
+        self.comments.nest(5, index, enumeration.comments);
+        self.file.enum_type.push(enumeration.descriptor);
+        self
+    }
+
+    /// Adds a service
+    pub fn service<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(ServiceBuilder) -> ServiceBuilder,
+    {
+        let service = f(ServiceBuilder {
+            descriptor: ServiceDescriptorProto {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            comments: Comments::default(),
+        });
+        let index = self.file.service.len();
+        self.comments.nest(6, index, service.comments);
+        self.file.service.push(service.descriptor);
+        self
+    }
+
+    /// The file that has been built, with a location for every message,
+    /// field, oneof, enum, enum value, service, and method
+    pub fn build(mut self) -> FileDescriptorProto {
+        let scope = match self.file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+        for message in &mut self.file.message_type {
+            qualify_map_entries(&scope, message);
+        }
+
+        let mut paths = Vec::new();
+        if self.file.package.is_some() {
+            paths.push(vec![2]);
+        }
+        for (index, message) in self.file.message_type.iter().enumerate() {
+            message_paths(vec![4, index as i32], message, &mut paths);
+        }
+        for (index, enumeration) in self.file.enum_type.iter().enumerate() {
+            enum_paths(vec![5, index as i32], enumeration, &mut paths);
+        }
+        for (index, service) in self.file.service.iter().enumerate() {
+            let path = vec![6, index as i32];
+            paths.extend(children(&path, 2, service.method.len()));
+            paths.push(path);
+        }
+        paths.sort();
+
+        let location = paths
+            .into_iter()
+            .enumerate()
+            .map(|(line, path)| {
+                let leading_comments = find_comment(&self.comments.leading, &path);
+                let trailing_comments = find_comment(&self.comments.trailing, &path);
+                Location {
+                    path,
+                    span: vec![line as i32, 0, 0],
+                    leading_comments,
+                    trailing_comments,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        self.file.source_code_info = Some(SourceCodeInfo { location });
+        self.file
+    }
+}
+
+/// Qualifies the type names of map fields, which are relative to the
+/// message's scope until the package is known
+fn qualify_map_entries(scope: &str, message: &mut DescriptorProto) {
+    for field in &mut message.field {
+        if let Some(type_name) = &mut field.type_name {
+            if !type_name.starts_with('.') {
+                *type_name = format!("{scope}.{type_name}");
+            }
+        }
+    }
+    let scope = format!("{scope}.{}", message.name());
+    for nested in &mut message.nested_type {
+        qualify_map_entries(&scope, nested);
+    }
+}
+
+/// A comment as `protoc` records it, keeping the space after each `//`
+fn recorded_comment(comment: &str) -> String {
+    let mut recorded = String::with_capacity(comment.len() + 8);
+    for line in comment.lines() {
+        if !line.is_empty() {
+            recorded.push(' ');
+            recorded.push_str(line);
+        }
+        recorded.push('\n');
+    }
+    recorded
+}
+
+fn children(path: &[i32], field: i32, count: usize) -> impl Iterator<Item = Vec<i32>> + '_ {
+    (0..count as i32).map(move |index| {
+        let mut child = path.to_vec();
+        child.extend([field, index]);
+        child
+    })
+}
+
+fn message_paths(path: Vec<i32>, message: &DescriptorProto, paths: &mut Vec<Vec<i32>>) {
+    paths.extend(children(&path, 2, message.field.len()));
+    paths.extend(children(&path, 8, message.oneof_decl.len()));
+    for (index, nested) in message.nested_type.iter().enumerate() {
+        let mut nested_path = path.clone();
+        nested_path.extend([3, index as i32]);
+        message_paths(nested_path, nested, paths);
+    }
+    for (index, enumeration) in message.enum_type.iter().enumerate() {
+        let mut enum_path = path.clone();
+        enum_path.extend([4, index as i32]);
+        enum_paths(enum_path, enumeration, paths);
+    }
+    paths.push(path);
+}
+
+fn enum_paths(path: Vec<i32>, enumeration: &EnumDescriptorProto, paths: &mut Vec<Vec<i32>>) {
+    paths.extend(children(&path, 2, enumeration.value.len()));
+    paths.push(path);
+}
+
+/// A builder of [`DescriptorProto`]s
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    descriptor: DescriptorProto,
+    /// The indexes of `proto3` optional fields, whose synthetic oneofs are
+    /// added after every other oneof
+    optional: Vec<usize>,
+    comments: Comments,
+}
+
+impl MessageBuilder {
+    fn new(name: &str) -> Self {
+        Self {
+            descriptor: DescriptorProto {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            optional: Vec::new(),
+            comments: Comments::default(),
+        }
+    }
+
+    /// Adds a singular field
+    pub fn field(self, name: &str, r#type: FieldType<'_>, number: i32) -> Self {
+        self.field_with(name, r#type, number, |f| f)
+    }
+
+    /// Adds a repeated field
+    pub fn repeated(self, name: &str, r#type: FieldType<'_>, number: i32) -> Self {
+        self.field_with(name, r#type, number, |f| f.label(Label::Repeated))
+    }
+
+    /// Adds a `proto3` optional field
+    pub fn optional(mut self, name: &str, r#type: FieldType<'_>, number: i32) -> Self {
+        self.optional.push(self.descriptor.field.len());
+        self.field_with(name, r#type, number, |mut f| {
+            f.descriptor.proto3_optional = Some(true);
+            f
+        })
+    }
+
+    /// Adds a map field, along with its nested entry message
+    pub fn map(
+        mut self,
+        name: &str,
+        key: FieldType<'_>,
+        value: FieldType<'_>,
+        number: i32,
+    ) -> Self {
+        // Qualified with the package and any enclosing messages once the
+        // file is built
+        let entry_name = format!("{}Entry", json_name(name, true));
+        let type_name = format!("{}.{entry_name}", self.descriptor.name());
+        let entry = MessageBuilder::new(&entry_name)
+            .field("key", key, 1)
+            .field("value", value, 2)
+            .options(MessageOptions {
+                map_entry: Some(true),
+                ..Default::default()
+            });
+        self.descriptor.nested_type.push(entry.build());
+        self.repeated(name, FieldType::Message(&type_name), number)
+    }
+
+    /// Adds a field, customized by `f`
+    pub fn field_with<F>(mut self, name: &str, r#type: FieldType<'_>, number: i32, f: F) -> Self
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        let mut descriptor = FieldDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            json_name: Some(json_name(name, false)),
+            ..Default::default()
+        };
+        descriptor.set_label(Label::Optional);
+        r#type.apply(&mut descriptor);
+
+        let field = f(FieldBuilder {
+            descriptor,
+            comments: Comments::default(),
+        });
+        let index = self.descriptor.field.len();
+        self.comments.nest(2, index, field.comments);
+        self.descriptor.field.push(field.descriptor);
+        self
+    }
+
+    /// Adds a oneof, with the fields added by `f`
+    pub fn oneof<F>(self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(OneofBuilder) -> OneofBuilder,
+    {
+        let oneof_index = self.descriptor.oneof_decl.len() as i32;
+        let first_field = self.descriptor.field.len();
+        let oneof = f(OneofBuilder {
+            message: self,
+            comments: Comments::default(),
+        });
+
+        let mut message = oneof.message;
+        for field in &mut message.descriptor.field[first_field..] {
+            field.oneof_index = Some(oneof_index);
+        }
+        message
+            .comments
+            .nest(8, oneof_index as usize, oneof.comments);
+        message.descriptor.oneof_decl.push(OneofDescriptorProto {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        });
+        message
+    }
+
+    /// Adds a nested message
+    pub fn message<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(MessageBuilder) -> MessageBuilder,
+    {
+        let mut message = f(MessageBuilder::new(name));
+        let index = self.descriptor.nested_type.len();
+        self.comments
+            .nest(3, index, std::mem::take(&mut message.comments));
+        self.descriptor.nested_type.push(message.build());
+        self
+    }
+
+    /// Adds a nested enum
+    pub fn enumeration<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(EnumBuilder) -> EnumBuilder,
+    {
+        let enumeration = f(EnumBuilder::new(name));
+        let index = self.descriptor.enum_type.len();
+        self.comments.nest(4, index, enumeration.comments);
+        self.descriptor.enum_type.push(enumeration.descriptor);
+        self
+    }
+
+    /// Sets the leading comment of the message
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comments.set(comment);
+        self
+    }
+
+    /// Sets the trailing comment of the message
+    pub fn trailing_comment(mut self, comment: &str) -> Self {
+        self.comments.set_trailing(comment);
+        self
+    }
+
+    /// Sets the options of the message
+    pub fn options(mut self, options: MessageOptions) -> Self {
+        self.descriptor.options = Some(options);
+        self
+    }
+
+    fn build(mut self) -> DescriptorProto {
+        for index in self.optional {
+            let field = &mut self.descriptor.field[index];
+            field.oneof_index = Some(self.descriptor.oneof_decl.len() as i32);
+            self.descriptor.oneof_decl.push(OneofDescriptorProto {
+                name: Some(format!("_{}", field.name())),
+                ..Default::default()
+            });
+        }
+        self.descriptor
+    }
+}
+
+/// A builder of the fields of a oneof
+#[derive(Clone, Debug)]
+pub struct OneofBuilder {
+    message: MessageBuilder,
+    comments: Comments,
+}
+
+impl OneofBuilder {
+    /// Adds a field to the oneof
+    pub fn field(self, name: &str, r#type: FieldType<'_>, number: i32) -> Self {
+        self.field_with(name, r#type, number, |f| f)
+    }
+
+    /// Adds a field to the oneof, customized by `f`
+    pub fn field_with<F>(mut self, name: &str, r#type: FieldType<'_>, number: i32, f: F) -> Self
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        self.message = self.message.field_with(name, r#type, number, f);
+        self
+    }
+
+    /// Sets the leading comment of the oneof
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comments.set(comment);
+        self
+    }
+}
+
+/// A builder of [`FieldDescriptorProto`]s
+#[derive(Clone, Debug)]
+pub struct FieldBuilder {
+    descriptor: FieldDescriptorProto,
+    comments: Comments,
+}
+
+impl FieldBuilder {
+    /// Sets the label of the field
+    pub fn label(mut self, label: Label) -> Self {
+        self.descriptor.set_label(label);
+        self
+    }
+
+    /// Sets the leading comment of the field
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comments.set(comment);
+        self
+    }
+
+    /// Sets the trailing comment of the field
+    pub fn trailing_comment(mut self, comment: &str) -> Self {
+        self.comments.set_trailing(comment);
+        self
+    }
+
+    /// Sets the options of the field
+    pub fn options(mut self, options: FieldOptions) -> Self {
+        self.descriptor.options = Some(options);
+        self
+    }
+}
+
+/// A builder of [`EnumDescriptorProto`]s
+#[derive(Clone, Debug)]
+pub struct EnumBuilder {
+    descriptor: EnumDescriptorProto,
+    comments: Comments,
+}
+
+impl EnumBuilder {
+    fn new(name: &str) -> Self {
+        Self {
+            descriptor: EnumDescriptorProto {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            comments: Comments::default(),
+        }
+    }
+
+    /// Adds a value
+    pub fn value(self, name: &str, number: i32) -> Self {
+        self.value_with(name, number, None, None)
+    }
+
+    /// Adds a value with a leading comment and options
+    pub fn value_with(
+        mut self,
+        name: &str,
+        number: i32,
+        comment: Option<&str>,
+        options: Option<EnumValueOptions>,
+    ) -> Self {
+        if let Some(comment) = comment {
+            let mut comments = Comments::default();
+            comments.set(comment);
+            self.comments.nest(2, self.descriptor.value.len(), comments);
+        }
+        self.descriptor.value.push(EnumValueDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            options,
+        });
+        self
+    }
+
+    /// Sets the trailing comment of the value added last
+    pub fn value_trailing_comment(mut self, comment: &str) -> Self {
+        let mut comments = Comments::default();
+        comments.set_trailing(comment);
+        let index = self.descriptor.value.len().saturating_sub(1);
+        self.comments.nest(2, index, comments);
+        self
+    }
+
+    /// Sets the leading comment of the enum
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comments.set(comment);
+        self
+    }
+
+    /// Sets the trailing comment of the enum
+    pub fn trailing_comment(mut self, comment: &str) -> Self {
+        self.comments.set_trailing(comment);
+        self
+    }
+
+    /// Sets the options of the enum
+    pub fn options(mut self, options: EnumOptions) -> Self {
+        self.descriptor.options = Some(options);
+        self
+    }
+}
+
+/// A builder of [`ServiceDescriptorProto`]s
+#[derive(Clone, Debug)]
+pub struct ServiceBuilder {
+    descriptor: ServiceDescriptorProto,
+    comments: Comments,
+}
+
+impl ServiceBuilder {
+    /// Adds a method taking `input` and returning `output`, named by their
+    /// fully-qualified names, customized by `f`
+    pub fn method<F>(mut self, name: &str, input: &str, output: &str, f: F) -> Self
+    where
+        F: FnOnce(MethodBuilder) -> MethodBuilder,
+    {
+        let method = f(MethodBuilder {
+            descriptor: MethodDescriptorProto {
+                name: Some(name.to_owned()),
+                input_type: Some(input.to_owned()),
+                output_type: Some(output.to_owned()),
+                ..Default::default()
+            },
+            comments: Comments::default(),
+        });
+        let index = self.descriptor.method.len();
+        self.comments.nest(2, index, method.comments);
+        self.descriptor.method.push(method.descriptor);
+        self
+    }
+
+    /// Sets the leading comment of the service
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comments.set(comment);
+        self
+    }
+
+    /// Sets the trailing comment of the service
+    pub fn trailing_comment(mut self, comment: &str) -> Self {
+        self.comments.set_trailing(comment);
+        self
+    }
+
+    /// Sets the options of the service
+    pub fn options(mut self, options: ServiceOptions) -> Self {
+        self.descriptor.options = Some(options);
+        self
+    }
+}
+
+/// A builder of [`MethodDescriptorProto`]s
+#[derive(Clone, Debug)]
+pub struct MethodBuilder {
+    descriptor: MethodDescriptorProto,
+    comments: Comments,
+}
+
+impl MethodBuilder {
+    /// Makes the method take a stream of inputs
+    pub fn client_streaming(mut self) -> Self {
+        self.descriptor.client_streaming = Some(true);
+        self
+    }
+
+    /// Makes the method return a stream of outputs
+    pub fn server_streaming(mut self) -> Self {
+        self.descriptor.server_streaming = Some(true);
+        self
+    }
+
+    /// Sets the leading comment of the method
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comments.set(comment);
+        self
+    }
+
+    /// Sets the trailing comment of the method
+    pub fn trailing_comment(mut self, comment: &str) -> Self {
+        self.comments.set_trailing(comment);
+        self
+    }
+
+    /// Sets the options of the method
+    pub fn options(mut self, options: MethodOptions) -> Self {
+        self.descriptor.options = Some(options);
+        self
+    }
+}
+
+/// The JSON name that `protoc` gives a field, or the name of a map entry
+/// when `upper` is set
+fn json_name(name: &str, upper: bool) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut capitalize = upper;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            json_name.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_files_have_a_location_for_every_definition() {
+        let file = file("a.proto")
+            .package("x")
+            .message("M", |m| {
+                m.comment("A message.")
+                    .optional("id", FieldType::Int64, 1)
+                    .oneof("choice", |o| {
+                        o.field("name", FieldType::String, 2).field_with(
+                            "kind",
+                            FieldType::Enum(".x.M.Kind"),
+                            3,
+                            |f| f.comment("The kind."),
+                        )
+                    })
+                    .map("tags_by_name", FieldType::String, FieldType::String, 4)
+                    .enumeration("Kind", |e| e.value("KIND_UNSPECIFIED", 0))
+            })
+            .build();
+
+        let message = &file.message_type[0];
+        let oneofs: Vec<_> = message.oneof_decl.iter().map(|o| o.name()).collect();
+        assert_eq!(oneofs, ["choice", "_id"]);
+        assert_eq!(message.field[0].oneof_index, Some(1));
+        assert_eq!(message.field[2].oneof_index, Some(0));
+        assert_eq!(message.field[3].json_name(), "tagsByName");
+        assert_eq!(message.field[3].type_name(), ".x.M.TagsByNameEntry");
+
+        let locations = &file.source_code_info.as_ref().unwrap().location;
+        let paths: Vec<_> = locations.iter().map(|l| l.path.as_slice()).collect();
+        assert_eq!(
+            paths,
+            [
+                &[2][..],
+                &[4, 0],
+                &[4, 0, 2, 0],
+                &[4, 0, 2, 1],
+                &[4, 0, 2, 2],
+                &[4, 0, 2, 3],
+                &[4, 0, 3, 0],
+                &[4, 0, 3, 0, 2, 0],
+                &[4, 0, 3, 0, 2, 1],
+                &[4, 0, 4, 0],
+                &[4, 0, 4, 0, 2, 0],
+                &[4, 0, 8, 0],
+                &[4, 0, 8, 1],
+            ]
+        );
+        assert_eq!(locations[1].leading_comments(), " A message.\n");
+        assert_eq!(locations[4].leading_comments(), " The kind.\n");
+    }
+
+    #[test]
+    fn comments_are_generated_as_docs() {
+        let files = crate::execute(
+            &request()
+                .file(
+                    file("a.proto")
+                        .package("x")
+                        .message("M", |m| {
+                            m.comment("A message.")
+                                .field_with("id", FieldType::Int64, 1, |f| f.comment("An id."))
+                        })
+                        .service("S", |s| s.method("Get", ".x.M", ".x.M", |m| m)),
+                )
+                .encode(),
+        )
+        .unwrap();
+
+        let content = files[0].content();
+        assert!(content.contains("/// A message.\n"), "{content}");
+        assert!(content.contains("    /// An id.\n"), "{content}");
+    }
+}
@@ -0,0 +1,103 @@
+//! Snapshots of the code generated for the descriptor sets in `fixtures`
+//!
+//! Run with `PROTOC_GEN_PROST_BLESS=1` to update the snapshots.
+
+use std::{fs, path::Path};
+
+use protoc_gen_prost::testing::{assert_snapshot, request_from_descriptor_set};
+
+fn check(name: &str, fixture: &str, files: &[&str], parameter: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let descriptor_set = fs::read(root.join(format!("../fixtures/{fixture}.binpb"))).unwrap();
+    let request = request_from_descriptor_set(&descriptor_set, files, parameter);
+
+    let files = protoc_gen_prost::execute(&request).expect("code generation failed");
+    assert_snapshot(root.join(format!("tests/snapshots/{name}.txt")), &files);
+}
+
+#[test]
+fn helloworld() {
+    check(
+        "helloworld",
+        "helloworld",
+        &["helloworld/v1/helloworld.proto"],
+        "",
+    );
+}
+
+#[test]
+fn greeter() {
+    check(
+        "greeter",
+        "greeter",
+        &["greeter/v1/greeter.proto", "helloworld/v1/helloworld.proto"],
+        "",
+    );
+}
+
+#[test]
+fn greeter_formatted() {
+    check(
+        "greeter_format",
+        "greeter",
+        &["greeter/v1/greeter.proto"],
+        "format,output_layout=per_file",
+    );
+}
+
+#[test]
+fn greeter_with_separate_file_descriptor_set() {
+    check(
+        "greeter_separate_file_descriptor_set",
+        "greeter",
+        &["greeter/v1/greeter.proto"],
+        "file_descriptor_set=separate",
+    );
+}
+
+#[test]
+fn nested_packages() {
+    check(
+        "nested",
+        "nested",
+        &[
+            "nested/outer.proto",
+            "nested/inner/inner.proto",
+            "nested/inner/deep/deep.proto",
+        ],
+        "",
+    );
+}
+
+#[test]
+fn nested_packages_with_insertion_points() {
+    check(
+        "nested_insertion_points",
+        "nested",
+        &[
+            "nested/outer.proto",
+            "nested/inner/inner.proto",
+            "nested/inner/deep/deep.proto",
+        ],
+        "insertion_points,format,enable_type_names",
+    );
+}
+
+#[test]
+fn nested_packages_with_flat_output_dir() {
+    check(
+        "nested_flat_output_dir",
+        "nested",
+        &[
+            "nested/outer.proto",
+            "nested/inner/inner.proto",
+            "nested/inner/deep/deep.proto",
+        ],
+        "flat_output_dir,bytes=.nested",
+    );
+}
+
+#[test]
+fn well_known_types() {
+    check("wkt", "wkt", &["wkt/v1/types.proto"], "");
+}
@@ -1,17 +1,21 @@
 use proc_macro2::TokenStream;
-use prost_build::{Method, Module, Service};
+use prost_build::{Comments, Method, Module, Service};
 use prost_types::{
     compiler::code_generator_response::File, FileDescriptorProto, ServiceDescriptorProto,
 };
-use protoc_gen_prost::{Generator, ModuleRequest, ModuleRequestSet, Result};
+use protoc_gen_prost::{
+    deprecation_note, strict_extern_paths, DocLinks, Generator, ModuleRequest, ModuleRequestSet,
+    Resolver, Result,
+};
 use quote::ToTokens;
-use syn::Path;
+use syn::{parse_quote, Attribute, ImplItem, Item, Path, TraitItem};
 use tonic_build::Attributes;
 
-use crate::{resolver::Resolver, util};
+use crate::util;
 
 pub(crate) struct TonicGenerator {
-    pub(crate) resolver: Resolver,
+    pub(crate) extern_path: Vec<(String, String)>,
+    pub(crate) compile_well_known_types: bool,
     pub(crate) generate_server: bool,
     pub(crate) generate_client: bool,
     pub(crate) generate_transport: bool,
@@ -19,59 +23,89 @@ pub(crate) struct TonicGenerator {
     pub(crate) client_attributes: Attributes,
     pub(crate) emit_package: bool,
     pub(crate) insert_include: bool,
+    pub(crate) doc_links: bool,
+    pub(crate) strict: bool,
 }
 
 impl Generator for TonicGenerator {
     fn generate(&mut self, module_request_set: &ModuleRequestSet) -> Result {
-        module_request_set
-            .requests()
-            .filter_map(|(module, request)| self.handle_module_request(module, request))
-            .flatten()
-            .map(Ok)
-            .collect()
+        let extern_path = if self.strict {
+            strict_extern_paths(module_request_set, &self.extern_path)?
+        } else {
+            self.extern_path.clone()
+        };
+        let resolver = Resolver::new(extern_path, self.compile_well_known_types);
+        let doc_links = self
+            .doc_links
+            .then(|| DocLinks::new(module_request_set, &resolver));
+        let mut files = Vec::new();
+        for (module, request) in module_request_set.requests() {
+            files.extend(self.handle_module_request(
+                module,
+                request,
+                &resolver,
+                doc_links.as_ref(),
+            )?);
+        }
+        Ok(files)
+    }
+
+    /// Comments are linked to types anywhere in the request, not only to
+    /// those that the module imports
+    fn depends_on_whole_request(&self) -> bool {
+        self.doc_links
     }
 }
 
-/// A new type wrapper for a prost [`Service`] that implements [`tonic_build::Service`].
-struct ProstService(Service, Vec<ProstMethod>);
+/// A wrapper for a prost [`Service`] that implements [`tonic_build::Service`].
+struct ProstService {
+    service: Service,
+    methods: Vec<ProstMethod>,
+    /// The `#[deprecated]` attribute of a deprecated service
+    deprecated: Option<Attribute>,
+}
 
 impl tonic_build::Service for ProstService {
     type Comment = String;
     type Method = ProstMethod;
 
     fn name(&self) -> &str {
-        &self.0.name
+        &self.service.name
     }
 
     fn package(&self) -> &str {
-        &self.0.package
+        &self.service.package
     }
 
     fn identifier(&self) -> &str {
-        &self.0.proto_name
+        &self.service.proto_name
     }
 
     fn methods(&self) -> &[Self::Method] {
-        &self.1[..]
+        &self.methods[..]
     }
 
     fn comment(&self) -> &[Self::Comment] {
-        &self.0.comments.leading[..]
+        &self.service.comments.leading[..]
     }
 }
 
-/// A new type wrapper for a prost [`Method`] that implements [`tonic_build::Method`].
-struct ProstMethod(Method);
+/// A wrapper for a prost [`Method`] that implements [`tonic_build::Method`].
+struct ProstMethod {
+    method: Method,
+    /// The `#[deprecated]` attribute of a deprecated method
+    deprecated: Option<Attribute>,
+}
 
 impl tonic_build::Method for ProstMethod {
     type Comment = String;
 
     fn name(&self) -> &str {
-        &self.0.name
+        &self.method.name
     }
 
     fn identifier(&self) -> &str {
-        &self.0.proto_name
+        &self.method.proto_name
     }
 
     fn codec_path(&self) -> &str {
@@ -79,15 +113,19 @@ impl tonic_build::Method for ProstMethod {
     }
 
     fn client_streaming(&self) -> bool {
-        self.0.client_streaming
+        self.method.client_streaming
     }
 
     fn server_streaming(&self) -> bool {
-        self.0.server_streaming
+        self.method.server_streaming
     }
 
     fn comment(&self) -> &[Self::Comment] {
-        &self.0.comments.leading[..]
+        &self.method.comments.leading[..]
+    }
+
+    fn deprecated(&self) -> bool {
+        self.deprecated.is_some()
     }
 
     fn request_response_name(
@@ -120,83 +158,105 @@ impl tonic_build::Method for ProstMethod {
             }
         };
 
-        let request = convert_type(&self.0.input_proto_type, &self.0.input_type);
-        let response = convert_type(&self.0.output_proto_type, &self.0.output_type);
+        let request = convert_type(&self.method.input_proto_type, &self.method.input_type);
+        let response = convert_type(&self.method.output_proto_type, &self.method.output_type);
         (request, response)
     }
 }
 
 impl TonicGenerator {
-    fn handle_module_request(&self, module: &Module, request: &ModuleRequest) -> Option<Vec<File>> {
+    fn handle_module_request(
+        &self,
+        module: &Module,
+        request: &ModuleRequest,
+        resolver: &Resolver,
+        doc_links: Option<&DocLinks<'_>>,
+    ) -> Result {
         const PROTO_PATH: &str = "super";
 
         let output_filename = format!("{}.tonic.rs", request.proto_package_name());
 
-        let services = request
-            .files()
+        // In strict mode, the services of the other files are generated
+        // along with their types
+        let files: Vec<_> = if self.strict {
+            request.requested_files().collect()
+        } else {
+            request.files().collect()
+        };
+        let services = files
+            .into_iter()
             .flat_map(|file| {
                 file.service
                     .iter()
                     .enumerate()
                     .filter_map(|(service_index, descriptor)| {
-                        self.prepare_service(module, file, descriptor, service_index)
+                        self.prepare_service(
+                            module,
+                            file,
+                            descriptor,
+                            service_index,
+                            resolver,
+                            doc_links,
+                        )
                     })
-                    .flat_map(|mut service| {
-                        let methods = std::mem::take(&mut service.methods)
-                            .into_iter()
-                            .map(ProstMethod)
-                            .collect();
-                        let service = ProstService(service, methods);
+                    .flat_map(|service| {
                         let client = self.generate_client.then(|| {
-                            tonic_build::CodeGenBuilder::new()
+                            let client = tonic_build::CodeGenBuilder::new()
                                 .emit_package(self.emit_package)
                                 .build_transport(self.generate_transport)
-                                .compile_well_known_types(self.resolver.compile_well_known_types())
-                                .attributes(self.client_attributes.clone())
-                                .generate_client(&service, PROTO_PATH)
+                                .compile_well_known_types(self.compile_well_known_types)
+                                .attributes(deprecation_attributes(
+                                    &self.client_attributes,
+                                    &service,
+                                ))
+                                .generate_client(&service, PROTO_PATH);
+                            mark_deprecated_methods(client, &service)
                         });
                         let server = self.generate_server.then(|| {
-                            tonic_build::CodeGenBuilder::new()
+                            let server = tonic_build::CodeGenBuilder::new()
                                 .emit_package(self.emit_package)
                                 .build_transport(self.generate_transport)
-                                .compile_well_known_types(self.resolver.compile_well_known_types())
-                                .attributes(self.server_attributes.clone())
-                                .generate_server(&service, PROTO_PATH)
+                                .compile_well_known_types(self.compile_well_known_types)
+                                .attributes(deprecation_attributes(
+                                    &self.server_attributes,
+                                    &service,
+                                ))
+                                .generate_server(&service, PROTO_PATH);
+                            mark_deprecated_methods(server, &service)
                         });
 
                         client.into_iter().chain(server)
                     })
             })
-            .reduce(|mut l, r| {
-                l.extend(r);
-                l
-            })
-            .unwrap_or_default();
+            .collect::<syn::Result<TokenStream>>()?;
 
         if services.is_empty() {
-            None
-        } else {
-            let mut res = Vec::with_capacity(2);
+            return Ok(Vec::new());
+        }
 
-            let file = syn::parse2(services).expect("valid rust file");
+        let mut res = Vec::with_capacity(2);
 
-            if self.insert_include {
-                res.push(request.append_to_file(|buf| {
-                    buf.push_str("include!(\"");
-                    buf.push_str(&output_filename);
-                    buf.push_str("\");\n");
-                })?);
+        let file = syn::parse2(services)?;
+
+        if self.insert_include {
+            match request.append_to_file(|buf| {
+                buf.push_str("include!(\"");
+                buf.push_str(&output_filename);
+                buf.push_str("\");\n");
+            }) {
+                Some(include) => res.push(include),
+                None => return Ok(Vec::new()),
             }
+        }
 
-            let out_dir = request.output_dir();
-            res.push(File {
-                name: Some(out_dir + &output_filename),
-                content: Some(format!("// @generated\n{}", prettyplease::unparse(&file))),
-                ..File::default()
-            });
+        let out_dir = request.output_dir();
+        res.push(File {
+            name: Some(out_dir + &output_filename),
+            content: Some(format!("// @generated\n{}", prettyplease::unparse(&file))),
+            ..File::default()
+        });
 
-            Some(res)
-        }
+        Ok(res)
     }
 
     fn prepare_service(
@@ -205,38 +265,138 @@ impl TonicGenerator {
         file: &FileDescriptorProto,
         descriptor: &ServiceDescriptorProto,
         service_index: usize,
-    ) -> Option<Service> {
-        let comments = util::get_service_comments(file, service_index);
+        resolver: &Resolver,
+        doc_links: Option<&DocLinks<'_>>,
+    ) -> Option<ProstService> {
+        // Services are documented within the client and server modules
+        let scope = format!(".{}", file.package());
+        let link = |mut comments: Comments| {
+            if let Some(doc_links) = doc_links {
+                for comment in &mut comments.leading {
+                    *comment = doc_links.rewrite(comment, module, &scope, 1);
+                }
+            }
+            comments
+        };
+
+        let comments = link(util::get_service_comments(file, service_index));
 
         let methods = descriptor
             .method
             .iter()
             .enumerate()
             .map(|(method_index, m)| {
-                let comments = util::get_method_comments(file, service_index, method_index);
-                prost_build::Method {
+                let comments = link(util::get_method_comments(file, service_index, method_index));
+                let path = [6, service_index as i32, 2, method_index as i32];
+                let deprecated = m
+                    .options
+                    .as_ref()
+                    .is_some_and(|options| options.deprecated())
+                    .then(|| deprecated_attribute(deprecation_note(file, &path, [4, 33])));
+                let method = prost_build::Method {
                     name: util::to_snake(m.name()),
                     proto_name: m.name().to_owned(),
                     comments,
-                    input_type: self.resolver.resolve_ident(module, m.input_type()),
-                    output_type: self.resolver.resolve_ident(module, m.output_type()),
+                    input_type: resolver.resolve_ident(module, m.input_type()),
+                    output_type: resolver.resolve_ident(module, m.output_type()),
                     input_proto_type: m.input_type().to_string(),
                     output_proto_type: m.output_type().to_string(),
                     options: m.options.clone().unwrap_or_default(),
                     client_streaming: m.client_streaming(),
                     server_streaming: m.server_streaming(),
-                }
+                };
+                ProstMethod { method, deprecated }
             });
 
         let service = prost_build::Service {
             package: file.package().to_owned(),
             comments,
-            methods: methods.collect(),
+            methods: Vec::new(),
             name: util::to_upper_camel(descriptor.name()),
             proto_name: descriptor.name().to_owned(),
             options: descriptor.options.clone().unwrap_or_default(),
         };
+        let deprecated = service.options.deprecated().then(|| {
+            deprecated_attribute(deprecation_note(file, &[6, service_index as i32], [3, 33]))
+        });
+
+        Some(ProstService {
+            service,
+            methods: methods.collect(),
+            deprecated,
+        })
+    }
+}
+
+fn deprecated_attribute(note: Option<String>) -> Attribute {
+    match note {
+        Some(note) => parse_quote!(#[deprecated = #note]),
+        None => parse_quote!(#[deprecated]),
+    }
+}
 
-        Some(service)
+/// The attributes for the client or server of `service`
+///
+/// The client and server of a deprecated service are marked deprecated, and
+/// deprecated definitions are allowed within their modules.
+fn deprecation_attributes(attributes: &Attributes, service: &ProstService) -> Attributes {
+    let mut attributes = attributes.clone();
+    attributes.push_mod(".", "#[allow(deprecated)]");
+    if let Some(deprecated) = &service.deprecated {
+        let deprecated = deprecated.to_token_stream().to_string();
+        attributes.push_struct(".", deprecated.clone());
+        attributes.push_trait(".", deprecated);
     }
+    attributes
+}
+
+/// Adds the notes of deprecated methods to the client, which marks them
+/// without one, and marks them deprecated in the server trait
+fn mark_deprecated_methods(
+    tokens: TokenStream,
+    service: &ProstService,
+) -> syn::Result<TokenStream> {
+    let deprecated = |name: &syn::Ident| {
+        service
+            .methods
+            .iter()
+            .find(|method| name == &method.method.name)
+            .and_then(|method| method.deprecated.clone())
+    };
+    let is_deprecated = |attribute: &Attribute| attribute.path().is_ident("deprecated");
+
+    let mut file: syn::File = syn::parse2(tokens)?;
+    for item in &mut file.items {
+        let items = match item {
+            Item::Mod(module) => module.content.iter_mut().flat_map(|(_, items)| items),
+            _ => continue,
+        };
+        for item in items {
+            match item {
+                Item::Impl(implementation) => {
+                    for item in &mut implementation.items {
+                        if let ImplItem::Fn(function) = item {
+                            if !function.attrs.iter().any(is_deprecated) {
+                                continue;
+                            }
+                            if let Some(deprecated) = deprecated(&function.sig.ident) {
+                                function.attrs.retain(|attribute| !is_deprecated(attribute));
+                                function.attrs.push(deprecated);
+                            }
+                        }
+                    }
+                }
+                Item::Trait(definition) => {
+                    for item in &mut definition.items {
+                        if let TraitItem::Fn(function) = item {
+                            function.attrs.extend(deprecated(&function.sig.ident));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(file.into_token_stream())
 }
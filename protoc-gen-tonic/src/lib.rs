@@ -2,45 +2,44 @@
 
 use std::str;
 
-use prost::Message;
-use prost_types::compiler::CodeGeneratorRequest;
-use protoc_gen_prost::{Generator, InvalidParameter, ModuleRequestSet, Param, Params};
+use protoc_gen_prost::{
+    GenerationCache, Generator, InvalidParameter, ModuleRequestSet, Param, ParamSpec,
+    ParameterError, PluginOpts, PluginRequest, PluginSpec,
+};
 use tonic_build::Attributes;
 
-use self::{generator::TonicGenerator, resolver::Resolver};
+use self::generator::TonicGenerator;
 
 mod generator;
-mod resolver;
 mod util;
 
 /// Execute the core _Prost!_ generator from a raw [`CodeGeneratorRequest`]
+///
+/// [`CodeGeneratorRequest`]: prost_types::compiler::CodeGeneratorRequest
 pub fn execute(raw_request: &[u8]) -> protoc_gen_prost::Result {
-    let request = CodeGeneratorRequest::decode(raw_request)?;
-    let params = request.parameter().parse::<Parameters>()?;
+    let request = PluginRequest::decode(raw_request)?;
+    let parameter = request.parameter().to_owned();
+    let opts = PluginOpts::load(&parameter, &PLUGIN)?;
+    protoc_gen_prost::dump_request(raw_request, env!("CARGO_PKG_VERSION"), &[&opts])?;
+    let params = Parameters::from_opts(&opts)?;
 
-    let module_request_set = ModuleRequestSet::new(
-        request.file_to_generate,
-        request.proto_file,
-        raw_request,
+    let mut module_request_set = ModuleRequestSet::from_request(
+        request,
         params.default_package_filename.as_deref(),
         params.flat_output_dir,
     )?;
+    let mut generator = params.into_generator();
+
+    match GenerationCache::from_opts(&opts, env!("CARGO_PKG_VERSION")) {
+        Some(cache) => cache.generate(&mut module_request_set, &mut generator),
+        None => generator.generate(&module_request_set),
+    }
+}
 
-    let resolver = Resolver::new(params.extern_path, params.compile_well_known_types);
-    let mut generator = TonicGenerator {
-        resolver,
-        generate_server: !params.no_server,
-        generate_client: !params.no_client,
-        generate_transport: !params.no_transport,
-        server_attributes: params.server_attributes,
-        client_attributes: params.client_attributes,
-        emit_package: !params.disable_package_emission,
-        insert_include: !params.no_include,
-    };
-
-    let files = generator.generate(&module_request_set)?;
-
-    Ok(files)
+/// Creates the generator of `protoc-gen-tonic` as configured by `opts`, for
+/// running as part of a combined plugin
+pub fn generator(opts: &PluginOpts<'_>) -> Result<impl Generator, InvalidParameter> {
+    Ok(Parameters::from_opts(opts)?.into_generator())
 }
 
 /// Parameters use to configure [`Generator`]s built into `protoc-gen-prost-serde`
@@ -59,131 +58,388 @@ struct Parameters {
     no_transport: bool,
     no_include: bool,
     flat_output_dir: bool,
+    doc_links: bool,
+    strict: bool,
+}
+
+/// The parameters accepted by `protoc-gen-tonic`
+pub const PLUGIN: PluginSpec = PluginSpec {
+    name: "protoc-gen-tonic",
+    section: "tonic",
+    params: &[
+        ParamSpec::new(
+            "default_package_filename",
+            "default_package_filename=<value>",
+        ),
+        ParamSpec::new("extern_path", "extern_path=<proto_path>=<rust_path>"),
+        ParamSpec::new(
+            "compile_well_known_types",
+            "compile_well_known_types(=<boolean>)",
+        ),
+        ParamSpec::new(
+            "disable_package_emission",
+            "disable_package_emission(=<boolean>)",
+        ),
+        ParamSpec::new(
+            "server_attribute",
+            "server_attribute=<proto_path>=<attribute>",
+        ),
+        ParamSpec::new(
+            "server_mod_attribute",
+            "server_mod_attribute=<proto_path>=<attribute>",
+        ),
+        ParamSpec::new(
+            "client_attribute",
+            "client_attribute=<proto_path>=<attribute>",
+        ),
+        ParamSpec::new(
+            "client_mod_attribute",
+            "client_mod_attribute=<proto_path>=<attribute>",
+        ),
+        ParamSpec::new("no_server", "no_server(=<boolean>)"),
+        ParamSpec::new("no_client", "no_client(=<boolean>)"),
+        ParamSpec::new("no_transport", "no_transport(=<boolean>)"),
+        ParamSpec::new("no_include", "no_include(=<boolean>)"),
+        ParamSpec::new("flat_output_dir", "flat_output_dir(=<boolean>)"),
+        ParamSpec::new("doc_links", "doc_links(=<boolean>)"),
+        ParamSpec::new("strict", "strict(=<boolean>)"),
+    ],
+};
+
+impl Parameters {
+    fn handle_parameter(&mut self, param: Param<'_>) -> Result<(), ParameterError> {
+        match param {
+            Param::Parameter {
+                param: "default_package_filename",
+            }
+            | Param::Value {
+                param: "default_package_filename",
+                ..
+            } => self.default_package_filename = param.value().map(|s| s.to_string()),
+            Param::KeyValue {
+                param: "extern_path",
+                key: prefix,
+                value: module,
+            } => self.extern_path.push((prefix.to_string(), module)),
+            Param::Parameter {
+                param: "compile_well_known_types",
+            }
+            | Param::Value {
+                param: "compile_well_known_types",
+                value: "true",
+            } => self.compile_well_known_types = true,
+            Param::Value {
+                param: "compile_well_known_types",
+                value: "false",
+            } => (),
+            Param::Parameter {
+                param: "disable_package_emission",
+            }
+            | Param::Value {
+                param: "disable_package_emission",
+                value: "true",
+            } => self.disable_package_emission = true,
+            Param::Value {
+                param: "disable_package_emission",
+                value: "false",
+            } => (),
+            Param::Parameter { param: "no_server" }
+            | Param::Value {
+                param: "no_server",
+                value: "true",
+            } => self.no_server = true,
+            Param::Value {
+                param: "no_server",
+                value: "false",
+            } => (),
+            Param::Parameter { param: "no_client" }
+            | Param::Value {
+                param: "no_client",
+                value: "true",
+            } => self.no_client = true,
+            Param::Value {
+                param: "no_client",
+                value: "false",
+            } => (),
+            Param::Parameter {
+                param: "no_transport",
+            }
+            | Param::Value {
+                param: "no_transport",
+                value: "true",
+            } => self.no_transport = true,
+            Param::Value {
+                param: "no_transport",
+                value: "false",
+            } => (),
+            Param::Parameter {
+                param: "no_include",
+            }
+            | Param::Value {
+                param: "no_include",
+                value: "true",
+            } => self.no_include = true,
+            Param::Value {
+                param: "no_include",
+                value: "false",
+            } => (),
+            Param::KeyValue {
+                param: "client_mod_attribute",
+                key: prefix,
+                value: attribute,
+            } => self
+                .client_attributes
+                .push_mod(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::KeyValue {
+                param: "client_attribute",
+                key: prefix,
+                value: attribute,
+            } => self
+                .client_attributes
+                .push_struct(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::KeyValue {
+                param: "server_mod_attribute",
+                key: prefix,
+                value: attribute,
+            } => self
+                .server_attributes
+                .push_mod(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::KeyValue {
+                param: "server_attribute",
+                key: prefix,
+                value: attribute,
+            } => self
+                .server_attributes
+                .push_struct(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
+            Param::Parameter {
+                param: "flat_output_dir",
+            }
+            | Param::Value {
+                param: "flat_output_dir",
+                value: "true",
+            } => self.flat_output_dir = true,
+            Param::Value {
+                param: "flat_output_dir",
+                value: "false",
+            } => (),
+            Param::Parameter { param: "doc_links" }
+            | Param::Value {
+                param: "doc_links",
+                value: "true",
+            } => self.doc_links = true,
+            Param::Value {
+                param: "doc_links",
+                value: "false",
+            } => (),
+            Param::Parameter { param: "strict" }
+            | Param::Value {
+                param: "strict",
+                value: "true",
+            } => self.strict = true,
+            Param::Value {
+                param: "strict",
+                value: "false",
+            } => (),
+            _ => return Err(PLUGIN.reject(param)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Parameters {
+    fn from_opts(opts: &PluginOpts<'_>) -> Result<Self, InvalidParameter> {
+        let mut ret_val = Self::default();
+        opts.apply(&mut ret_val, Self::handle_parameter)?;
+        Ok(ret_val)
+    }
+
+    fn into_generator(self) -> TonicGenerator {
+        TonicGenerator {
+            extern_path: self.extern_path,
+            compile_well_known_types: self.compile_well_known_types,
+            generate_server: !self.no_server,
+            generate_client: !self.no_client,
+            generate_transport: !self.no_transport,
+            server_attributes: self.server_attributes,
+            client_attributes: self.client_attributes,
+            emit_package: !self.disable_package_emission,
+            insert_include: !self.no_include,
+            doc_links: self.doc_links,
+            strict: self.strict,
+        }
+    }
 }
 
 impl str::FromStr for Parameters {
     type Err = InvalidParameter;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut ret_val = Self::default();
-        for param in Params::from_protoc_plugin_opts(s)? {
-            match param {
-                Param::Parameter {
-                    param: "default_package_filename",
-                }
-                | Param::Value {
-                    param: "default_package_filename",
-                    ..
-                } => ret_val.default_package_filename = param.value().map(|s| s.to_string()),
-                Param::KeyValue {
-                    param: "extern_path",
-                    key: prefix,
-                    value: module,
-                } => ret_val.extern_path.push((prefix.to_string(), module)),
-                Param::Parameter {
-                    param: "compile_well_known_types",
-                }
-                | Param::Value {
-                    param: "compile_well_known_types",
-                    value: "true",
-                } => ret_val.compile_well_known_types = true,
-                Param::Value {
-                    param: "compile_well_known_types",
-                    value: "false",
-                } => (),
-                Param::Parameter {
-                    param: "disable_package_emission",
-                }
-                | Param::Value {
-                    param: "disable_package_emission",
-                    value: "true",
-                } => ret_val.disable_package_emission = true,
-                Param::Value {
-                    param: "disable_package_emission",
-                    value: "false",
-                } => (),
-                Param::Parameter { param: "no_server" }
-                | Param::Value {
-                    param: "no_server",
-                    value: "true",
-                } => ret_val.no_server = true,
-                Param::Value {
-                    param: "no_server",
-                    value: "false",
-                } => (),
-                Param::Parameter { param: "no_client" }
-                | Param::Value {
-                    param: "no_client",
-                    value: "true",
-                } => ret_val.no_client = true,
-                Param::Value {
-                    param: "no_client",
-                    value: "false",
-                } => (),
-                Param::Parameter {
-                    param: "no_transport",
-                }
-                | Param::Value {
-                    param: "no_transport",
-                    value: "true",
-                } => ret_val.no_transport = true,
-                Param::Value {
-                    param: "no_transport",
-                    value: "false",
-                } => (),
-                Param::Parameter {
-                    param: "no_include",
-                }
-                | Param::Value {
-                    param: "no_include",
-                    value: "true",
-                } => ret_val.no_include = true,
-                Param::Value {
-                    param: "no_include",
-                    value: "false",
-                } => (),
-                Param::KeyValue {
-                    param: "client_mod_attribute",
-                    key: prefix,
-                    value: attribute,
-                } => ret_val
-                    .client_attributes
-                    .push_mod(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
-                Param::KeyValue {
-                    param: "client_attribute",
-                    key: prefix,
-                    value: attribute,
-                } => ret_val
-                    .client_attributes
-                    .push_struct(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
-                Param::KeyValue {
-                    param: "server_mod_attribute",
-                    key: prefix,
-                    value: attribute,
-                } => ret_val
-                    .server_attributes
-                    .push_mod(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
-                Param::KeyValue {
-                    param: "server_attribute",
-                    key: prefix,
-                    value: attribute,
-                } => ret_val
-                    .server_attributes
-                    .push_struct(prefix, attribute.replace(r"\,", ",").replace(r"\\", r"\")),
-                Param::Parameter {
-                    param: "flat_output_dir",
-                }
-                | Param::Value {
-                    param: "flat_output_dir",
-                    value: "true",
-                } => ret_val.flat_output_dir = true,
-                Param::Value {
-                    param: "flat_output_dir",
-                    value: "false",
-                } => (),
-                _ => return Err(InvalidParameter::from(param)),
-            }
+        Self::from_opts(&PluginOpts::load(s, &PLUGIN)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protoc_gen_prost::testing::{file, request};
+
+    use super::*;
+
+    #[test]
+    fn doc_links_link_references_in_service_comments() {
+        let request = request()
+            .parameter("doc_links")
+            .file(
+                file("greeter.proto")
+                    .package("greeter")
+                    .message("Greeting", |m| m)
+                    .service("Greeter", |s| {
+                        s.comment("Sends a `Greeting`.").method(
+                            "Greet",
+                            ".greeter.Greeting",
+                            ".greeter.Greeting",
+                            |m| m.comment("Replies with a [greeter.Greeting]."),
+                        )
+                    }),
+            )
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let content = files
+            .iter()
+            .find(|f| f.name() == "greeter/greeter.tonic.rs")
+            .unwrap()
+            .content();
+        assert!(
+            content.contains("Sends a [`Greeting`](super::Greeting)."),
+            "{content}"
+        );
+        assert!(
+            content.contains("Replies with a [greeter.Greeting](super::Greeting)."),
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn deprecated_services_and_methods_are_marked_deprecated() {
+        use prost_types::{MethodOptions, ServiceOptions};
+
+        let request = request()
+            .file(
+                file("greeter.proto")
+                    .package("greeter")
+                    .message("Greeting", |m| m)
+                    .service("Greeter", |s| {
+                        s.options(ServiceOptions {
+                            deprecated: Some(true),
+                            ..Default::default()
+                        })
+                        .trailing_comment(" Use `Welcomer` instead.\n")
+                        .method("Greet", ".greeter.Greeting", ".greeter.Greeting", |m| {
+                            m.options(MethodOptions {
+                                deprecated: Some(true),
+                                ..Default::default()
+                            })
+                            .trailing_comment(" Use `Welcome` instead.\n")
+                        })
+                        .method(
+                            "Wave",
+                            ".greeter.Greeting",
+                            ".greeter.Greeting",
+                            |m| m,
+                        )
+                    }),
+            )
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let content = files
+            .iter()
+            .find(|f| f.name() == "greeter/greeter.tonic.rs")
+            .unwrap()
+            .content();
+
+        for module in ["greeter_client", "greeter_server"] {
+            assert!(
+                content.contains(&format!("#[allow(deprecated)]\npub mod {module} {{")),
+                "{content}"
+            );
         }
+        // The client struct, the server trait and the server struct
+        assert_eq!(
+            content
+                .matches("#[deprecated = \"Use `Welcomer` instead.\"]")
+                .count(),
+            3,
+            "{content}"
+        );
+        // The client method and the trait method, but not `Wave`
+        assert_eq!(
+            content
+                .matches("#[deprecated = \"Use `Welcome` instead.\"]")
+                .count(),
+            2,
+            "{content}"
+        );
+        assert!(!content.contains("#[deprecated]"), "{content}");
+    }
 
-        Ok(ret_val)
+    #[test]
+    fn strict_mode_generates_only_the_services_of_requested_files() {
+        let request = request()
+            .parameter("strict,extern_path=.greeter=::remote::greeter")
+            .file(
+                file("greeter/local.proto")
+                    .package("greeter")
+                    .import("greeter/remote.proto")
+                    .service("Local", |s| {
+                        s.method("Greet", ".greeter.Greeting", ".greeter.Greeting", |m| m)
+                    }),
+            )
+            .import(
+                file("greeter/remote.proto")
+                    .package("greeter")
+                    .message("Greeting", |m| m)
+                    .service("Remote", |s| {
+                        s.method("Greet", ".greeter.Greeting", ".greeter.Greeting", |m| m)
+                    }),
+            )
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let content = files
+            .iter()
+            .find(|f| f.name() == "greeter/greeter.tonic.rs")
+            .unwrap()
+            .content();
+        assert!(content.contains("pub mod local_client"), "{content}");
+        assert!(!content.contains("pub mod remote_client"), "{content}");
+        assert!(
+            content.contains("tonic::Request<::remote::greeter::Greeting>"),
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn services_of_editions_files_are_generated() {
+        let request = request()
+            .file(
+                file("greeter.proto")
+                    .edition(protoc_gen_prost::MAXIMUM_EDITION)
+                    .package("greeter")
+                    .message("Greeting", |m| m)
+                    .service("Greeter", |s| {
+                        s.method("Greet", ".greeter.Greeting", ".greeter.Greeting", |m| m)
+                    }),
+            )
+            .encode();
+
+        let files = execute(&request).unwrap();
+        let content = files
+            .iter()
+            .find(|f| f.name() == "greeter/greeter.tonic.rs")
+            .unwrap()
+            .content();
+        assert!(content.contains("pub mod greeter_client"), "{content}");
+        assert!(content.contains("pub mod greeter_server"), "{content}");
     }
 }